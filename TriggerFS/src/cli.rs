@@ -0,0 +1,86 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+/// TriggerFS слушает события файловой системы и выполняет действия по правилам
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Путь до конфига elfo, по умолчанию `$XDG_CONFIG_HOME/TriggerFS/config.toml`
+    #[arg(short, long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Путь до PID файла
+    #[arg(long, global = true, default_value = "/tmp/TriggerFS.pid")]
+    pub pid_file: PathBuf,
+
+    /// Файл для логов до запуска акторов, по умолчанию логи пишутся в stdout
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Запустить в текущем процессе без демонизации
+    Run,
+    /// Запустить как демона
+    Start(StartArgs),
+    /// Остановить запущенного демона
+    Stop,
+    /// Показать запущен ли демон
+    Status,
+    /// Перечитать конфиги у запущенного демона
+    Reload,
+    /// Проверить конфиг и файл с правилами
+    Validate,
+    /// Отменить операции из журнала (`executors.journal_path` в конфиге)
+    Undo(UndoArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct StartArgs {
+    /// Куда перенаправить stdout демона
+    #[arg(long, default_value = "/tmp/TriggerFS.out")]
+    pub stdout: PathBuf,
+
+    /// Куда перенаправить stderr демона
+    #[arg(long, default_value = "/tmp/TriggerFS.err")]
+    pub stderr: PathBuf,
+
+    /// Рабочая директория демона
+    #[arg(long, default_value = "/tmp")]
+    pub working_directory: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct UndoArgs {
+    /// Сколько последних операций отменить
+    #[arg(long, default_value_t = 1, conflicts_with = "rule")]
+    pub last: usize,
+
+    /// Отменить все операции правила с этим именем
+    #[arg(long)]
+    pub rule: Option<String>,
+
+    /// Только показать, что будет отменено
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+impl Cli {
+    /// Путь до конфига elfo, если он не передан явно, то берется из `$XDG_CONFIG_HOME`
+    pub fn config_path(&self) -> PathBuf {
+        if let Some(config) = &self.config {
+            return config.clone();
+        }
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .unwrap_or_else(|| PathBuf::from("."));
+        config_home.join("TriggerFS").join("config.toml")
+    }
+}
@@ -1,25 +1,36 @@
-use std::future::Future;
+use std::{future::Future, path::PathBuf};
 
 use crate::signal_handler::cleanup;
 use daemonize::Daemonize;
 use tokio::runtime::Runtime;
-use tracing::info;
+use tracing::{info, subscriber::DefaultGuard};
+
+pub struct DaemonOptions {
+    pub pid_file: PathBuf,
+    pub stdout: PathBuf,
+    pub stderr: PathBuf,
+    pub working_directory: PathBuf,
+}
 
 pub struct Daemon;
 
 impl Daemon {
-    pub fn start<F, Fut>(pid_file: &str, async_main: F) -> Result<(), Box<dyn std::error::Error>>
+    pub fn start<F, Fut>(
+        options: &DaemonOptions,
+        log_guard: DefaultGuard,
+        async_main: F,
+    ) -> Result<(), Box<dyn std::error::Error>>
     where
         F: FnOnce() -> Fut + Send + 'static,
         Fut: Future<Output = ()> + Send + 'static,
     {
-        let stdout = std::fs::File::create("/tmp/TriggerFS.out")?;
-        let stderr = std::fs::File::create("/tmp/TriggerFS.err")?;
+        let stdout = std::fs::File::create(&options.stdout)?;
+        let stderr = std::fs::File::create(&options.stderr)?;
 
         let daemonize = Daemonize::new()
-            .pid_file(pid_file)
+            .pid_file(&options.pid_file)
             .chown_pid_file(true)
-            .working_directory("/tmp")
+            .working_directory(&options.working_directory)
             .stdout(stdout)
             .stderr(stderr)
             .privileged_action(|| {
@@ -29,13 +40,15 @@ impl Daemon {
         match daemonize.start() {
             Ok(_) => {
                 info!("Daemon started successfully");
+                // дальше логированием занимается elfo
+                drop(log_guard);
                 let rt = Runtime::new().expect("Failed to create Tokio runtime");
                 info!("start tokio rt");
                 rt.block_on(async_main());
             }
             Err(e) => {
                 eprintln!("Error while daemonizing: {}", e);
-                cleanup(pid_file);
+                cleanup(&options.pid_file);
             }
         }
         Ok(())
@@ -1,12 +1,26 @@
-use tracing::Level;
+use std::{path::Path, sync::Mutex};
+
+use tracing::{subscriber::DefaultGuard, Level};
 use tracing_subscriber::FmtSubscriber;
 
-pub fn init_logger() {
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::TRACE) // Устанавливаем уровень логирования на TRACE
-        .finish();
+/// Логгер на время до запуска акторов, дальше логированием занимается elfo. Логгер живет пока
+/// жив возвращаемый guard.
+pub fn init_logger(log_file: Option<&Path>) -> std::io::Result<DefaultGuard> {
+    let builder = FmtSubscriber::builder().with_max_level(Level::TRACE); // Устанавливаем уровень логирования на TRACE
 
-    // tracing::subscriber::set_global_default(subscriber).expect("Failed to set logger");
-    // TODO: разобраться с тем как писать логи до демнизации и запуска асинтхронного рантайма
-    let _ = tracing::subscriber::set_default(subscriber);
+    let guard = match log_file {
+        Some(log_file) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)?;
+            let subscriber = builder
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .finish();
+            tracing::subscriber::set_default(subscriber)
+        }
+        None => tracing::subscriber::set_default(builder.finish()),
+    };
+    Ok(guard)
 }
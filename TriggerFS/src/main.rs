@@ -1,26 +1,222 @@
+mod cli;
 mod daemon;
 mod logger;
+mod pid;
 mod signal_handler;
 
-use daemon::Daemon;
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
 
-use tracing::{error, info};
+use clap::Parser;
+use cli::{Cli, Command, StartArgs, UndoArgs};
+use daemon::{Daemon, DaemonOptions};
+use fs::journal::Journal;
+use tokio::runtime::Runtime;
 
-const PID_FILE: &str = "/tmp/TriggerFS.pid";
+use tracing::{info, subscriber::DefaultGuard};
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-fn main() {
     // // Инициализация логгера
-    logger::init_logger();
+    let log_guard = match logger::init_logger(cli.log_file.as_deref()) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Failed to open log file: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // демон меняет рабочую директорию, поэтому все пути делаем абсолютными заранее
+    let config_path = absolute(&cli.config_path());
+    let pid_file = absolute(&cli.pid_file);
+
+    let res = match cli.command {
+        Command::Run => run(config_path, pid_file, log_guard),
+        Command::Start(args) => start(config_path, pid_file, args, log_guard),
+        Command::Stop => stop(&pid_file),
+        Command::Status => return status(&pid_file),
+        Command::Reload => reload(&pid_file),
+        Command::Validate => validate(&config_path),
+        Command::Undo(args) => undo(&config_path, args),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
 
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
+fn run(config_path: PathBuf, pid_file: PathBuf, log_guard: DefaultGuard) -> CliResult {
+    ensure_not_running(&pid_file)?;
+    pid::write_pid(&pid_file)?;
+    // дальше логированием занимается elfo
+    drop(log_guard);
+    let rt = Runtime::new()?;
+    rt.block_on(async_main(config_path, pid_file));
+    Ok(())
+}
+
+fn start(
+    config_path: PathBuf,
+    pid_file: PathBuf,
+    args: StartArgs,
+    log_guard: DefaultGuard,
+) -> CliResult {
+    ensure_not_running(&pid_file)?;
+    let options = DaemonOptions {
+        pid_file: pid_file.clone(),
+        stdout: absolute(&args.stdout),
+        stderr: absolute(&args.stderr),
+        working_directory: args.working_directory,
+    };
     // Инициализация и запуск демона
-    if let Err(e) = Daemon::start(PID_FILE, async_main) {
-        error!("Error while daemonizing: {}", e);
+    Daemon::start(&options, log_guard, move || {
+        async_main(config_path, pid_file)
+    })
+}
+
+fn stop(pid_file: &Path) -> CliResult {
+    let pid = pid::running_pid(pid_file)?.ok_or("TriggerFS is not running")?;
+    pid::send_signal(pid, libc::SIGTERM)?;
+
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while pid::is_running(pid) {
+        if Instant::now() > deadline {
+            return Err(format!("TriggerFS (pid {}) did not stop in time", pid).into());
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    println!("TriggerFS (pid {}) stopped", pid);
+    Ok(())
+}
+
+fn status(pid_file: &Path) -> ExitCode {
+    match pid::running_pid(pid_file) {
+        Ok(Some(pid)) => {
+            println!("TriggerFS is running (pid {})", pid);
+            ExitCode::SUCCESS
+        }
+        Ok(None) => {
+            println!("TriggerFS is not running");
+            // код 3 по соглашению LSB означает что сервис не запущен
+            ExitCode::from(3)
+        }
+        Err(e) => {
+            eprintln!("Failed to read PID file {:?}: {}", pid_file, e);
+            ExitCode::FAILURE
+        }
     }
 }
 
-async fn async_main() {
+fn reload(pid_file: &Path) -> CliResult {
+    let pid = pid::running_pid(pid_file)?.ok_or("TriggerFS is not running")?;
+    // конфигуратор elfo перечитывает конфиги по SIGHUP
+    pid::send_signal(pid, libc::SIGHUP)?;
+    println!("Reload signal sent to TriggerFS (pid {})", pid);
+    Ok(())
+}
+
+/// Читает строковый параметр группы акторов из конфига elfo
+fn config_value(
+    config_path: &Path,
+    group: &str,
+    key: &str,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(config_path)
+        .map_err(|e| format!("{}: {}", config_path.display(), e))?;
+    let config: toml::Table =
+        toml::from_str(&content).map_err(|e| format!("{}: {}", config_path.display(), e))?;
+    let value = config
+        .get(group)
+        .and_then(|group| group.get(key))
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| format!("{}: missing `{}.{}`", config_path.display(), group, key))?;
+    Ok(value.to_owned())
+}
+
+fn validate(config_path: &Path) -> CliResult {
+    let rules_path = config_value(
+        config_path,
+        main_topology::FS_WATCHER_GROUP,
+        "watchers_conf_path",
+    )?;
+
+    let rt = Runtime::new()?;
+    let rules = rt.block_on(watcher::load_watchers_conf(Path::new(&rules_path)))?;
+    for warning in rules.warnings.iter() {
+        println!("{}", warning);
+    }
+    println!(
+        "{}: ok, {} rules in {}",
+        config_path.display(),
+        rules.watchers_conf.len(),
+        rules_path
+    );
+    Ok(())
+}
+
+fn undo(config_path: &Path, args: UndoArgs) -> CliResult {
+    let journal_path = config_value(config_path, main_topology::EXECUTORS_GROUP, "journal_path")?;
+    let journal =
+        Journal::open(Path::new(&journal_path)).map_err(|e| format!("{}: {}", journal_path, e))?;
+    let mut entries = journal.entries()?;
+    match &args.rule {
+        Some(rule) => entries.retain(|entry| entry.rule.as_ref() == Some(rule)),
+        None => {
+            let skip = entries.len().saturating_sub(args.last);
+            entries.drain(..skip);
+        }
+    }
+    if entries.is_empty() {
+        println!("nothing to undo");
+        return Ok(());
+    }
+    if args.dry_run {
+        for entry in entries.iter().rev() {
+            println!("would undo: {}", entry);
+        }
+        return Ok(());
+    }
+
+    let rt = Runtime::new()?;
+    let mut failed = 0;
+    for (entry, res) in rt.block_on(journal.undo(entries)) {
+        match res {
+            Ok(()) => println!("undone: {}", entry),
+            Err(e) => {
+                failed += 1;
+                eprintln!("fail to undo: {}: {}", entry, e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} operations were not undone", failed).into());
+    }
+    Ok(())
+}
+
+fn ensure_not_running(pid_file: &Path) -> CliResult {
+    if let Some(pid) = pid::running_pid(pid_file)? {
+        return Err(format!("TriggerFS is already running (pid {})", pid).into());
+    }
+    Ok(())
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+async fn async_main(config_path: PathBuf, pid_file: PathBuf) {
     info!("async_main called");
-    tokio::spawn(signal_handler::handle_signals(PID_FILE));
+    tokio::spawn(signal_handler::handle_signals(pid_file));
     info!("start actors topology");
-    elfo::init::start(main_topology::topology()).await;
+    elfo::init::start(main_topology::topology(&config_path)).await;
 }
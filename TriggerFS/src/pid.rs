@@ -0,0 +1,40 @@
+use std::{io, path::Path};
+
+/// Читает PID из файла, если файла нет возвращает `None`
+pub fn read_pid(pid_file: &Path) -> io::Result<Option<libc::pid_t>> {
+    let content = match std::fs::read_to_string(pid_file) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    content
+        .trim()
+        .parse()
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn write_pid(pid_file: &Path) -> io::Result<()> {
+    std::fs::write(pid_file, format!("{}\n", std::process::id()))
+}
+
+/// Проверяет жив ли процесс, сигнал 0 ничего не отправляет, только проверяет права и наличие
+pub fn is_running(pid: libc::pid_t) -> bool {
+    // SAFETY: kill с сигналом 0 не имеет побочных эффектов
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+pub fn send_signal(pid: libc::pid_t, signal: libc::c_int) -> io::Result<()> {
+    // SAFETY: просто системный вызов, pid прочитан из PID файла
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Возвращает PID запущенного демона, если PID файл устарел то `None`
+pub fn running_pid(pid_file: &Path) -> io::Result<Option<libc::pid_t>> {
+    Ok(read_pid(pid_file)?.filter(|pid| is_running(*pid)))
+}
@@ -1,8 +1,11 @@
+use std::path::{Path, PathBuf};
+
+use fs::content_index::ContentIndex;
 use tokio::signal::unix::{signal, SignalKind};
 use tracing::{error, info};
 
 // handle_signals слушатель системынх перываний
-pub async fn handle_signals(pid_file: &str) {
+pub async fn handle_signals(pid_file: PathBuf) {
     let sigterm = async {
         let mut stream = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
         stream.recv().await;
@@ -16,17 +19,18 @@ pub async fn handle_signals(pid_file: &str) {
     };
 
     tokio::select! {
-        _ = sigterm => {
-            cleanup(pid_file);
-        },
-        _ = sigint => {
-            cleanup(pid_file);
-        },
+        _ = sigterm => {},
+        _ = sigint => {},
+    }
+    // exit в cleanup не дает исполнителям дописать индекс содержимого, пишем его здесь
+    if let Err(e) = tokio::task::spawn_blocking(ContentIndex::flush_shared).await {
+        error!("Failed to save content index: {}", e);
     }
+    cleanup(&pid_file);
 }
 
 // cleanup синхронный так как ошибка может возникнуть до запуска асинхронного рантайма
-pub fn cleanup(pid_file: &str) {
+pub fn cleanup(pid_file: &Path) {
     if let Err(e) = std::fs::remove_file(pid_file) {
         error!("Failed to remove PID file: {}", e);
     } else {
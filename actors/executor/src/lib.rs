@@ -1,4 +1,8 @@
-use fs::actions::Action;
+use std::{path::PathBuf, sync::Arc};
+
+use fs::actions::{Action, ExecOptions};
+use fs::content_index::ContentIndex;
+use fs::journal::Journal;
 use notify::Event;
 use serde::Deserialize;
 
@@ -8,6 +12,7 @@ use elfo::{
 };
 
 use protocol::*;
+use tracing::error;
 
 pub fn new() -> Blueprint {
     ActorGroup::new()
@@ -25,19 +30,46 @@ pub fn new() -> Blueprint {
 
 #[derive(Debug, Deserialize, Clone)]
 struct Config {
+    /// Только логировать что будет сделано, не трогая файловую систему
+    #[serde(default)]
+    dry_run: bool,
+    /// Журнал операций для `TriggerFS undo`, без него операции не записываются
     #[serde(default)]
-    todo: Option<String>,
+    journal_path: Option<PathBuf>,
+    /// Где хранить индекс содержимого для `is_duplicate_of` и `dedupe`, без него индекс
+    /// живет в памяти до перезапуска
+    #[serde(default)]
+    content_index_path: Option<PathBuf>,
 }
 
 struct ExecutorActor {
     ctx: Context<Config, KeyAction>,
     action: Action,
+    journal: Option<Arc<Journal>>,
+    content_index: Arc<ContentIndex>,
 }
 
 impl ExecutorActor {
     fn new(ctx: Context<Config, KeyAction>) -> Self {
+        let journal = ctx.config().journal_path.as_ref().and_then(|path| {
+            Journal::open(path)
+                .map_err(|err| error!("fail to open journal {:?}: {}", path, err))
+                .ok()
+        });
+        let content_index = ctx
+            .config()
+            .content_index_path
+            .as_ref()
+            .and_then(|path| {
+                ContentIndex::shared(path)
+                    .map_err(|err| error!("fail to open content index {:?}: {}", path, err))
+                    .ok()
+            })
+            .unwrap_or_else(ContentIndex::in_memory);
         Self {
             action: ctx.key().action.clone(),
+            journal: journal.map(Arc::new),
+            content_index,
             ctx,
         }
     }
@@ -48,8 +80,29 @@ impl ExecutorActor {
                 FsEvent { event, .. } => self.process_event(event).await,
             });
         }
+        let index = self.content_index.clone();
+        match tokio::task::spawn_blocking(move || index.flush()).await {
+            Ok(Err(err)) => error!("fail to save content index: {}", err),
+            Err(err) => error!("fail to save content index: {}", err),
+            Ok(Ok(())) => {}
+        }
     }
     async fn process_event(&self, event: Event) {
-        self.action.execute(&event).await;
+        // индекс обновляется при любом событии, даже если действие его не выполнит
+        let index = self.content_index.clone();
+        let update = event.clone();
+        if let Err(err) = tokio::task::spawn_blocking(move || index.update(&update)).await {
+            error!("fail to update content index: {}", err);
+        }
+        let options = ExecOptions {
+            dry_run: self.ctx.config().dry_run,
+            journal: self.journal.clone(),
+            watch_root: Some(self.ctx.key().root.clone()),
+            content_index: Some(self.content_index.clone()),
+            trash_dir: None,
+        };
+        if let Err(err) = self.action.execute(&event, &options).await {
+            error!("fail to execute action: {}", err);
+        }
     }
 }
@@ -0,0 +1,106 @@
+//! Файл `.triggerfsignore` в папке из правил: пути, события которых не обрабатываются, в
+//! синтаксисе `.gitignore`. Учитывается только файл в самой папке из правил, не во вложенных.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use tracing::{trace, warn};
+
+pub const IGNORE_FILE_NAME: &str = ".triggerfsignore";
+
+#[derive(Default)]
+pub struct IgnoreFiles {
+    matchers: HashMap<PathBuf, Gitignore>,
+}
+
+impl IgnoreFiles {
+    pub fn load<'a>(roots: impl IntoIterator<Item = &'a PathBuf>) -> Self {
+        let matchers = roots
+            .into_iter()
+            .map(|root| (root.clone(), read_ignore_file(root)))
+            .collect();
+        Self { matchers }
+    }
+
+    /// Если `path` это файл игнорирования одной из папок, перечитывает его и возвращает true
+    pub fn reload_if_ignore_file(&mut self, path: &Path) -> bool {
+        if path.file_name() != Some(IGNORE_FILE_NAME.as_ref()) {
+            return false;
+        }
+        let Some(root) = path.parent() else {
+            return false;
+        };
+        match self.matchers.get_mut(root) {
+            Some(matcher) => {
+                trace!("reload {:?}", path);
+                *matcher = read_ignore_file(root);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Путь из папки `root` исключен ее файлом игнорирования, сам файл тоже исключен
+    pub fn is_ignored(&self, root: &Path, path: &Path) -> bool {
+        let Some(matcher) = self.matchers.get(root) else {
+            return false;
+        };
+        if !path.starts_with(root) {
+            return false;
+        }
+        if path.parent() == Some(root) && path.file_name() == Some(IGNORE_FILE_NAME.as_ref()) {
+            return true;
+        }
+        // удаленный путь уже не проверить на папку, для него работают только шаблоны без `/` в конце
+        matcher
+            .matched_path_or_any_parents(path, path.is_dir())
+            .is_ignore()
+    }
+}
+
+fn read_ignore_file(root: &Path) -> Gitignore {
+    let path = root.join(IGNORE_FILE_NAME);
+    if !path.exists() {
+        return Gitignore::empty();
+    }
+    let mut builder = GitignoreBuilder::new(root);
+    if let Some(err) = builder.add(&path) {
+        warn!("fail to read {:?}: {}", path, err);
+    }
+    builder.build().unwrap_or_else(|err| {
+        warn!("fail to read {:?}: {}", path, err);
+        Gitignore::empty()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        std::fs::create_dir_all(root.join("build/out")).unwrap();
+        std::fs::write(root.join(IGNORE_FILE_NAME), "*.tmp\nbuild/\n!keep.tmp\n").unwrap();
+
+        let mut ignores = IgnoreFiles::load([&root]);
+        assert!(ignores.is_ignored(&root, &root.join("a.tmp")));
+        assert!(ignores.is_ignored(&root, &root.join("sub/b.tmp")));
+        assert!(!ignores.is_ignored(&root, &root.join("keep.tmp")));
+        assert!(ignores.is_ignored(&root, &root.join("build/out/photo.jpg")));
+        assert!(ignores.is_ignored(&root, &root.join(IGNORE_FILE_NAME)));
+        assert!(!ignores.is_ignored(&root, &root.join("photo.jpg")));
+        // путь из другой папки этот файл не касается
+        assert!(!ignores.is_ignored(&root, Path::new("/elsewhere/a.tmp")));
+
+        std::fs::write(root.join(IGNORE_FILE_NAME), "*.jpg\n").unwrap();
+        assert!(!ignores.reload_if_ignore_file(&root.join("sub").join(IGNORE_FILE_NAME)));
+        assert!(ignores.reload_if_ignore_file(&root.join(IGNORE_FILE_NAME)));
+        assert!(ignores.is_ignored(&root, &root.join("photo.jpg")));
+        assert!(!ignores.is_ignored(&root, &root.join("a.tmp")));
+    }
+}
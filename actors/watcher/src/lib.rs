@@ -1,11 +1,24 @@
-use std::path::PathBuf;
+mod ignore_file;
+mod rules;
 
-use elfo::prelude::*;
-use fs::{actions::Action, FsWatcher, RecursiveModeInernal};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use elfo::{messages::ConfigUpdated, prelude::*};
+use fs::{
+    actions::{is_tmp_path, Action},
+    FsWatcher, RecursiveModeInernal,
+};
+use ignore_file::IgnoreFiles;
+use notify::EventKind;
 use protocol::{FsEvent, KeyAction};
 use serde::Deserialize;
 
-use tracing::{error, trace, warn};
+use tracing::{error, info, trace, warn};
+
+pub use rules::{load_watchers_conf, parse_watchers_conf, LoadedRules, LocatedProblem, RulesError};
 
 pub fn new() -> Blueprint {
     ActorGroup::new()
@@ -14,7 +27,7 @@ pub fn new() -> Blueprint {
 }
 
 #[derive(Debug, Deserialize, Clone)]
-struct WatcherConf {
+pub struct WatcherConf {
     path: PathBuf,
     recursive_mode: RecursiveModeInernal,
     action: Action,
@@ -28,7 +41,13 @@ struct Config {
 struct FsWatcherActor {
     ctx: Context<Config>,
     watchers_conf: Vec<WatcherConf>,
+    /// `.triggerfsignore` каждой папки из правил
+    ignores: IgnoreFiles,
     watcher: FsWatcher,
+    // отдельный слушатель для файла с правилами, чтобы его события не смешивались с
+    // событиями отслеживаемых папок
+    conf_watcher: FsWatcher,
+    watchers_conf_path: PathBuf,
 }
 
 impl FsWatcherActor {
@@ -37,29 +56,35 @@ impl FsWatcherActor {
             error!("Encountered an error: {}", err); // Логирование ошибки
             panic!("Aborting due to a critical error: {}", err); // Паника с сообщением
         });
-        let config = ctx.config();
-        let content = match tokio::fs::read(&config.watchers_conf_path).await {
-            Ok(content) => content,
+        let mut conf_watcher = FsWatcher::new().unwrap_or_else(|err| {
+            error!("Encountered an error: {}", err);
+            panic!("Aborting due to a critical error: {}", err);
+        });
+        let watchers_conf_path = ctx.config().watchers_conf_path.clone();
+        // с невалидными правилами не падаем, а ждем пока файл исправят
+        let watchers_conf = match load_watchers_conf(&watchers_conf_path).await {
+            Ok(rules) => rules.log_warnings(),
             Err(err) => {
-                error!("Error read WatcherConf: {}", err); // Логирование ошибки
-                panic!("Aborting due to a critical error: {}", err); // Паника с сообщением
+                error!("Error read WatcherConf, no rules are active:\n{}", err);
+                vec![]
             }
         };
-        let watchers_conf: Vec<WatcherConf> =
-            serde_json::from_slice(&content).unwrap_or_else(|err| {
-                error!("Error read WatcherConf: {}", err); // Логирование ошибки
-                panic!("Aborting due to a critical error: {}", err); // Паника с сообщением
-            });
-
-        for path in watchers_conf.iter() {
-            if let Err(err) = watcher.async_watch(&path.path, &path.recursive_mode) {
+
+        for (path, mode) in watched_paths(&watchers_conf) {
+            if let Err(err) = watcher.async_watch(&path, &mode) {
                 warn!("for path {:?}: {}", path, err)
             }
         }
+        watch_conf_file(&mut conf_watcher, &watchers_conf_path);
+        let ignores = IgnoreFiles::load(watched_paths(&watchers_conf).keys());
+
         Self {
             ctx,
             watchers_conf,
+            ignores,
             watcher,
+            conf_watcher,
+            watchers_conf_path,
         }
     }
 
@@ -69,16 +94,8 @@ impl FsWatcherActor {
                 envelope = self.ctx.recv() => {
                     if let Some(envelope) = envelope {
                         msg!(match envelope {
-                                    // More elaborate handling code can be delegated to methods.
-                                    // // These methods can easily be async.
-                                    // msg @ Increment => self.on_increment(msg),
-                                    //
-                                    // // Simpler code, however, can still be processed directly here.
-                                    // (GetValue, token) => {
-                                    //     self.ctx.respond(token, self.value);
-                                    // }
+                            ConfigUpdated => self.on_config_updated().await,
                         });
-
                     }
                 }
                 event = self.watcher.reciver.recv() => {
@@ -93,22 +110,269 @@ impl FsWatcherActor {
                     }
 
                 }
+                event = self.conf_watcher.reciver.recv() => {
+                    if let Some(event) = event {
+                        match event {
+                            Ok(event) => self.on_conf_file_event(event).await,
+                            Err(e) => error!("watch error: {:?}", e),
+                        }
+                    }
+                }
             }
         }
     }
-    async fn process_event(&self, event: notify::Event) {
+
+    async fn on_config_updated(&mut self) {
+        let watchers_conf_path = self.ctx.config().watchers_conf_path.clone();
+        if watchers_conf_path != self.watchers_conf_path {
+            info!(
+                "watchers_conf_path changed from {:?} to {:?}",
+                self.watchers_conf_path, watchers_conf_path
+            );
+            unwatch_conf_file(&mut self.conf_watcher, &self.watchers_conf_path).await;
+            watch_conf_file(&mut self.conf_watcher, &watchers_conf_path);
+            self.watchers_conf_path = watchers_conf_path;
+        }
+        self.reload().await;
+    }
+
+    async fn on_conf_file_event(&mut self, event: notify::Event) {
+        // редакторы часто сохраняют файл через создание нового и переименование, поэтому
+        // слушаем папку целиком и фильтруем события по имени файла
+        if matches!(event.kind, EventKind::Access(_))
+            || !event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == self.watchers_conf_path.file_name())
+        {
+            return;
+        }
+        trace!("rules file changed: {:?}", event);
+        self.reload().await;
+    }
+
+    /// Перечитывает файл с правилами и применяет разницу со старыми правилами. При ошибке
+    /// чтения продолжаем работать по старым правилам.
+    async fn reload(&mut self) {
+        let diff = match reload_rules(&self.watchers_conf_path, &mut self.watchers_conf).await {
+            Ok(diff) => diff,
+            Err(err) => {
+                error!("Error reload WatcherConf, keep old rules:\n{}", err);
+                return;
+            }
+        };
+        for path in diff.unwatch.iter() {
+            trace!("unwatch {:?}", path);
+            if let Err(err) = self.watcher.unwach(path).await {
+                warn!("for path {:?}: {}", path, err)
+            }
+        }
+        for (path, mode) in diff.watch.iter() {
+            trace!("watch {:?}", path);
+            if let Err(err) = self.watcher.async_watch(path, mode) {
+                warn!("for path {:?}: {}", path, err)
+            }
+        }
+
+        // действия передаются исполнителям вместе с событием, поэтому уже отправленные
+        // события дорабатывают по старым правилам, а новые пойдут по новым
+        self.ignores = IgnoreFiles::load(watched_paths(&self.watchers_conf).keys());
+        info!("rules reloaded from {:?}", self.watchers_conf_path);
+    }
+
+    async fn process_event(&mut self, event: notify::Event) {
         trace!("start iteration watchers");
+        for path in event.paths.iter() {
+            self.ignores.reload_if_ignore_file(path);
+        }
         let mut key_actions = vec![];
         for path in event.paths.iter() {
+            if is_tmp_path(path) {
+                trace!("{:?} is a temporary file of an action", path);
+                continue;
+            }
             for watcher in self.watchers_conf.iter() {
                 if path.starts_with(&watcher.path) {
+                    if self.ignores.is_ignored(&watcher.path, path) {
+                        trace!("{:?} is ignored by {:?}", path, watcher.path);
+                        continue;
+                    }
                     key_actions.push(KeyAction {
                         path: path.clone(),
+                        root: watcher.path.clone(),
                         action: watcher.action.clone(),
                     });
                 }
             }
         }
-        self.ctx.send(FsEvent { key_actions, event }).await;
+        let _ = self.ctx.send(FsEvent { key_actions, event }).await;
+    }
+}
+
+impl LoadedRules {
+    fn log_warnings(self) -> Vec<WatcherConf> {
+        for warning in self.warnings.iter() {
+            warn!("{}", warning);
+        }
+        self.watchers_conf
+    }
+}
+
+/// Собирает уникальные пути для слушателя, если хотя бы одно правило требует рекурсивного
+/// режима, то путь слушается рекурсивно.
+fn watched_paths(watchers_conf: &[WatcherConf]) -> HashMap<PathBuf, RecursiveModeInernal> {
+    let mut paths = HashMap::new();
+    for conf in watchers_conf {
+        let mode = paths
+            .entry(conf.path.clone())
+            .or_insert_with(|| conf.recursive_mode.clone());
+        if conf.recursive_mode == RecursiveModeInernal::Recursive {
+            *mode = RecursiveModeInernal::Recursive;
+        }
+    }
+    paths
+}
+
+/// Что поменять в слушателе при смене правил
+#[derive(Debug, Default, PartialEq)]
+struct PathsDiff {
+    /// Пути, которые больше не нужны или слушаются в другом режиме
+    unwatch: Vec<PathBuf>,
+    watch: Vec<(PathBuf, RecursiveModeInernal)>,
+}
+
+/// Пути с изменившимся режимом сначала снимаются со слушателя, а потом ставятся заново
+fn paths_diff(old: &[WatcherConf], new: &[WatcherConf]) -> PathsDiff {
+    let old_paths = watched_paths(old);
+    let new_paths = watched_paths(new);
+    let mut diff = PathsDiff::default();
+    for (path, mode) in old_paths.iter() {
+        if new_paths.get(path) != Some(mode) {
+            diff.unwatch.push(path.clone());
+        }
+    }
+    for (path, mode) in new_paths.into_iter() {
+        if old_paths.get(&path) != Some(&mode) {
+            diff.watch.push((path, mode));
+        }
+    }
+    diff.unwatch.sort();
+    diff.watch.sort_by(|a, b| a.0.cmp(&b.0));
+    diff
+}
+
+/// Заменяет `watchers_conf` правилами из файла и возвращает разницу для слушателя. Если файл
+/// не читается или правила с ошибками, то `watchers_conf` не меняется.
+async fn reload_rules(
+    path: &Path,
+    watchers_conf: &mut Vec<WatcherConf>,
+) -> Result<PathsDiff, RulesError> {
+    let new = load_watchers_conf(path).await?.log_warnings();
+    let diff = paths_diff(watchers_conf, &new);
+    *watchers_conf = new;
+    Ok(diff)
+}
+
+fn watch_conf_file(conf_watcher: &mut FsWatcher, path: &Path) {
+    let dir = conf_dir(path);
+    if let Err(err) = conf_watcher.async_watch(&dir, &RecursiveModeInernal::NonRecursive) {
+        warn!("fail to watch rules file {:?}: {}", path, err)
+    }
+}
+
+async fn unwatch_conf_file(conf_watcher: &mut FsWatcher, path: &Path) {
+    if let Err(err) = conf_watcher.unwach(conf_dir(path)).await {
+        warn!("fail to unwatch rules file {:?}: {}", path, err)
+    }
+}
+
+fn conf_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use RecursiveModeInernal::{NonRecursive, Recursive};
+
+    fn rule(path: &Path, mode: &str) -> serde_json::Value {
+        json!({
+            "path": path,
+            "recursive_mode": mode,
+            "action": {
+                "triggers": ["any"],
+                "conditions": {"condition": {"file_name_pattern_condition": {"pattern": "\\.tmp$"}}},
+                "action_type": {"delete_file": {"force": false}},
+            },
+        })
+    }
+
+    fn rules(rules: serde_json::Value) -> Vec<WatcherConf> {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    #[test]
+    fn test_paths_diff() {
+        let (a, b, c) = (Path::new("/a"), Path::new("/b"), Path::new("/c"));
+        let old = rules(json!([
+            rule(a, "non_recursive"),
+            rule(b, "non_recursive"),
+            rule(c, "recursive"),
+        ]));
+        assert_eq!(paths_diff(&old, &old), PathsDiff::default());
+
+        // `/a` остается, `/b` становится рекурсивным из-за второго правила, `/c` уходит
+        let new = rules(json!([
+            rule(a, "non_recursive"),
+            rule(b, "non_recursive"),
+            rule(b, "recursive"),
+            rule(Path::new("/d"), "non_recursive"),
+        ]));
+        assert_eq!(
+            paths_diff(&old, &new),
+            PathsDiff {
+                unwatch: vec![b.to_path_buf(), c.to_path_buf()],
+                watch: vec![
+                    (b.to_path_buf(), Recursive),
+                    (PathBuf::from("/d"), NonRecursive),
+                ],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_reload_keeps_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let conf_path = dir.path().join("rules.json");
+        let write = |rules: &serde_json::Value| {
+            std::fs::write(&conf_path, serde_json::to_string(rules).unwrap()).unwrap()
+        };
+        let watched =
+            |conf: &[WatcherConf]| conf.iter().map(|c| c.path.clone()).collect::<Vec<_>>();
+        let mut watchers_conf = vec![];
+        write(&json!([rule(dir.path(), "recursive")]));
+        reload_rules(&conf_path, &mut watchers_conf).await.unwrap();
+        assert_eq!(watched(&watchers_conf), vec![dir.path().to_path_buf()]);
+
+        // недописанный файл и правило с ошибкой не трогают текущие правила
+        std::fs::write(&conf_path, "[{").unwrap();
+        assert!(reload_rules(&conf_path, &mut watchers_conf).await.is_err());
+        let mut broken = rule(dir.path(), "recursive");
+        broken["action"]["action_type"] = json!({"unknown": {}});
+        write(&json!([broken]));
+        assert!(reload_rules(&conf_path, &mut watchers_conf).await.is_err());
+        assert_eq!(watched(&watchers_conf), vec![dir.path().to_path_buf()]);
+
+        let sub = dir.path().join("sub");
+        std::fs::create_dir(&sub).unwrap();
+        write(&json!([rule(&sub, "non_recursive")]));
+        let diff = reload_rules(&conf_path, &mut watchers_conf).await.unwrap();
+        assert_eq!(diff.unwatch, vec![dir.path().to_path_buf()]);
+        assert_eq!(diff.watch, vec![(sub.clone(), NonRecursive)]);
+        assert_eq!(watched(&watchers_conf), vec![sub]);
     }
 }
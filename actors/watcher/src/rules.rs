@@ -0,0 +1,209 @@
+use std::{
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+};
+
+use fs::validation::{locate, pointer_child, Problem, Severity, Validate};
+use serde_json::Value;
+
+use crate::WatcherConf;
+
+impl Validate for WatcherConf {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        let path_pointer = pointer_child(pointer, "path");
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => problems.push(Problem::error(
+                path_pointer,
+                format!("{:?} is not a directory", self.path),
+            )),
+            Err(err) => problems.push(Problem::warning(
+                path_pointer,
+                format!("{:?} can not be watched: {}", self.path, err),
+            )),
+        }
+        self.action
+            .validate(&pointer_child(pointer, "action"), problems);
+    }
+}
+
+/// Проблема в файле с правилами вместе с местом, где она найдена
+#[derive(Debug, Clone)]
+pub struct LocatedProblem {
+    pub file: PathBuf,
+    pub line: Option<(usize, usize)>,
+    pub problem: Problem,
+}
+
+impl Display for LocatedProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some((line, col)) = self.line {
+            write!(f, ":{}:{}", line, col)?;
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+#[derive(Debug)]
+pub enum RulesError {
+    Io(PathBuf, std::io::Error),
+    Invalid(Vec<LocatedProblem>),
+}
+
+impl Display for RulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RulesError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            RulesError::Invalid(problems) => {
+                for (i, problem) in problems.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RulesError {}
+
+/// Результат разбора файла с правилами: правила и предупреждения, которые не мешают их
+/// применить
+#[derive(Debug)]
+pub struct LoadedRules {
+    pub watchers_conf: Vec<WatcherConf>,
+    pub warnings: Vec<LocatedProblem>,
+}
+
+/// Ищет позицию значения, если его нет (например не хватает обязательного поля), то позицию
+/// ближайшего родителя
+fn locate_nearest(content: &str, pointer: &str) -> Option<(usize, usize)> {
+    let mut pointer = pointer;
+    loop {
+        if let Some(line) = locate(content, pointer) {
+            return Some(line);
+        }
+        pointer = &pointer[..pointer.rfind('/')?];
+    }
+}
+
+/// Читает и проверяет файл с правилами, возвращает сразу все найденные проблемы
+pub async fn load_watchers_conf(path: &Path) -> Result<LoadedRules, RulesError> {
+    let content = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|err| RulesError::Io(path.to_path_buf(), err))?;
+    parse_watchers_conf(path, &content)
+}
+
+pub fn parse_watchers_conf(path: &Path, content: &str) -> Result<LoadedRules, RulesError> {
+    let located = |problem: Problem| LocatedProblem {
+        file: path.to_path_buf(),
+        line: locate_nearest(content, &problem.pointer),
+        problem,
+    };
+
+    let value: Value = serde_json::from_str(content).map_err(|err| {
+        RulesError::Invalid(vec![LocatedProblem {
+            file: path.to_path_buf(),
+            line: Some((err.line(), err.column())),
+            problem: Problem::error("", err.to_string()),
+        }])
+    })?;
+    let Value::Array(items) = value else {
+        return Err(RulesError::Invalid(vec![located(Problem::error(
+            "",
+            "expected a list of watchers",
+        ))]));
+    };
+
+    // каждое правило разбираем отдельно, чтобы ошибка в одном не прятала ошибки в других
+    let mut problems = vec![];
+    let mut watchers_conf = vec![];
+    for (i, item) in items.into_iter().enumerate() {
+        let pointer = pointer_child("", i);
+        match serde_path_to_error::deserialize::<_, WatcherConf>(item) {
+            Ok(conf) => {
+                conf.validate(&pointer, &mut problems);
+                watchers_conf.push(conf);
+            }
+            Err(err) => {
+                let pointer = err.path().iter().fold(pointer, |pointer, segment| {
+                    use serde_path_to_error::Segment;
+                    match segment {
+                        Segment::Seq { index } => pointer_child(&pointer, index),
+                        Segment::Map { key } => pointer_child(&pointer, key),
+                        Segment::Enum { variant } => pointer_child(&pointer, variant),
+                        Segment::Unknown => pointer,
+                    }
+                });
+                problems.push(Problem::error(pointer, err.into_inner().to_string()));
+            }
+        }
+    }
+
+    let (errors, warnings): (Vec<_>, Vec<_>) = problems
+        .into_iter()
+        .map(located)
+        .partition(|p| p.problem.severity == Severity::Error);
+    if !errors.is_empty() {
+        let mut problems = errors;
+        problems.extend(warnings);
+        problems.sort_by_key(|p| p.line);
+        return Err(RulesError::Invalid(problems));
+    }
+    Ok(LoadedRules {
+        watchers_conf,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_all_problems() {
+        let content = r#"[
+  {
+    "path": "/",
+    "recursive_mode": "non_recursive",
+    "action": {
+      "triggers": ["any"],
+      "conditions": {"condition_group": {"cond_type": "and", "conditions": [
+        {"file_name_pattern_condition": {"pattern": "(["}},
+        {"file_size": {"operator": "greater_than", "size": 2, "unit": "megabytes"}},
+        {"file_size": {"operator": "less_than_or_equal", "size": 1, "unit": "megabytes"}}
+      ]}},
+      "action_type": {"delete_file": {"force": false}}
+    }
+  },
+  {
+    "path": "/",
+    "recursive_mode": "recursive"
+  }
+]"#;
+        let Err(RulesError::Invalid(problems)) =
+            parse_watchers_conf(Path::new("rules.json"), content)
+        else {
+            panic!("rules must be invalid");
+        };
+        let found: Vec<_> = problems
+            .iter()
+            .map(|p| (p.line.unwrap().0, p.problem.pointer.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (7, "/0/action/conditions/condition_group"),
+                (
+                    8,
+                    "/0/action/conditions/condition_group/conditions/0/file_name_pattern_condition/pattern"
+                ),
+                (15, "/1"),
+            ]
+        );
+    }
+}
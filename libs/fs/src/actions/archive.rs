@@ -0,0 +1,481 @@
+//! Добавление файлов и папок в архивы tar, tar.gz, tar.xz, tar.zst и zip. Сжатые потоки нельзя
+//! дописывать на месте, поэтому архив каждый раз пересобирается во временный файл: старые
+//! записи копируются, новые добавляются, результат проверяется и только потом заменяет архив.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, trace, warn};
+use walkdir::WalkDir;
+
+use super::pipeline::Applied;
+use super::template::{PathTemplate, TemplateContext};
+use super::transfer;
+use crate::validation::{pointer_child, Problem, Validate};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ArchiveAction {
+    archive: PathTemplate, // Путь до архива, например `/backup/logs-{mtime:%Y-%m}.tar.zst`
+    // Формат архива, по умолчанию определяется по расширению
+    #[serde(default)]
+    format: Option<ArchiveFormat>,
+    // Удалить исходный файл после того, как архив записан и проверен
+    #[serde(default)]
+    delete_source: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarXz,
+    TarZst,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.to_lowercase();
+        [
+            (".tar", ArchiveFormat::Tar),
+            (".tar.gz", ArchiveFormat::TarGz),
+            (".tgz", ArchiveFormat::TarGz),
+            (".tar.xz", ArchiveFormat::TarXz),
+            (".txz", ArchiveFormat::TarXz),
+            (".tar.zst", ArchiveFormat::TarZst),
+            (".tzst", ArchiveFormat::TarZst),
+            (".zip", ArchiveFormat::Zip),
+        ]
+        .into_iter()
+        .find(|(ext, _)| path.ends_with(ext))
+        .map(|(_, format)| format)
+    }
+
+    /// Формат по содержимому файла. Сжатый поток без tar внутри тоже считается tar, ошибка
+    /// будет при чтении.
+    pub fn from_kind(kind: &infer::Type) -> Option<Self> {
+        match kind.mime_type() {
+            "application/x-tar" => Some(ArchiveFormat::Tar),
+            "application/gzip" => Some(ArchiveFormat::TarGz),
+            "application/x-xz" => Some(ArchiveFormat::TarXz),
+            "application/zstd" => Some(ArchiveFormat::TarZst),
+            "application/zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+}
+
+impl ArchiveAction {
+    pub fn deletes_source(&self) -> bool {
+        self.delete_source
+    }
+
+    pub fn templates(&self) -> Vec<&PathTemplate> {
+        vec![&self.archive]
+    }
+
+    pub async fn execute(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext<'_>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        let archive = self.archive.render(ctx)?;
+        let format = self
+            .format
+            .or_else(|| ArchiveFormat::from_path(&archive.to_string_lossy()))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("unknown archive format of {:?}", archive),
+                )
+            })?;
+        let applied = Applied::Archived {
+            from: src.to_path_buf(),
+            archive: archive.clone(),
+            source_deleted: self.delete_source,
+        };
+        if dry_run {
+            info!(
+                "[dry-run] add {:?} to {:?} archive {:?}",
+                src, format, archive
+            );
+            if self.delete_source {
+                info!("[dry-run] remove {:?}", src);
+            }
+            return Ok(Some(applied));
+        }
+
+        let src = src.to_path_buf();
+        let delete_source = self.delete_source;
+        tokio::task::spawn_blocking(move || {
+            if let Some(parent) = archive.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            add_to_archive(&src, &archive, format)?;
+            trace!("{:?} added to {:?}", src, archive);
+            if delete_source {
+                if fs::symlink_metadata(&src)?.is_dir() {
+                    fs::remove_dir_all(&src)?;
+                } else {
+                    fs::remove_file(&src)?;
+                }
+            }
+            Ok::<_, Error>(())
+        })
+        .await??;
+        Ok(Some(applied))
+    }
+}
+
+impl Validate for ArchiveAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        let pointer = pointer_child(pointer, "archive");
+        self.archive.validate(&pointer, problems);
+        if self.format.is_none() && ArchiveFormat::from_path(self.archive.as_str()).is_none() {
+            problems.push(Problem::error(
+                pointer,
+                "can not detect archive format by extension, set `format`",
+            ));
+        }
+    }
+}
+
+/// Архивы пересобираются целиком, поэтому два действия не должны писать одновременно, иначе
+/// одно из добавлений потеряется
+static ARCHIVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Добавляет файл или папку в архив под своим именем. Если в архиве уже есть запись с таким
+/// именем, она заменяется.
+pub fn add_to_archive(src: &Path, archive: &Path, format: ArchiveFormat) -> Result<(), Error> {
+    let _guard = ARCHIVE_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let name = src
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid path {:?}", src)))?
+        .to_string_lossy()
+        .into_owned();
+    let entries = collect_entries(src, &name)?;
+
+    let tmp = transfer::tmp_path(archive)?;
+    let res = write_archive(archive, &tmp, format, &name, &entries)
+        .and_then(|()| verify_archive(&tmp, format, &entries))
+        .and_then(|()| fs::rename(&tmp, archive));
+    if let Err(err) = res {
+        if let Err(cleanup_err) = fs::remove_file(&tmp) {
+            if cleanup_err.kind() != ErrorKind::NotFound {
+                warn!("fail to remove temporary {:?}: {}", tmp, cleanup_err);
+            }
+        }
+        return Err(err);
+    }
+    transfer::sync_parent(archive)
+}
+
+/// Новая запись архива: имя внутри архива и путь на диске
+struct NewEntry {
+    name: String,
+    path: PathBuf,
+    metadata: fs::Metadata,
+}
+
+fn collect_entries(src: &Path, name: &str) -> Result<Vec<NewEntry>, Error> {
+    let mut entries = vec![];
+    for entry in WalkDir::new(src).follow_links(false) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(src).map_err(Error::other)?;
+        let mut entry_name = name.to_owned();
+        for component in relative.components() {
+            entry_name.push('/');
+            entry_name.push_str(&component.as_os_str().to_string_lossy());
+        }
+        entries.push(NewEntry {
+            name: entry_name,
+            metadata: entry.metadata()?,
+            path: entry.into_path(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Запись заменяется новой, если совпадает имя или она лежит внутри заменяемой папки
+fn is_replaced(entry_name: &str, name: &str) -> bool {
+    let entry_name = entry_name.trim_end_matches('/');
+    entry_name == name
+        || entry_name
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+fn write_archive(
+    archive: &Path,
+    tmp: &Path,
+    format: ArchiveFormat,
+    name: &str,
+    entries: &[NewEntry],
+) -> Result<(), Error> {
+    let existing = match File::open(archive) {
+        Ok(file) => Some(file),
+        Err(err) if err.kind() == ErrorKind::NotFound => None,
+        Err(err) => return Err(err),
+    };
+    let out = File::create(tmp)?;
+    let out = if format == ArchiveFormat::Zip {
+        write_zip(existing, out, name, entries)?
+    } else {
+        write_tar(existing, out, format, name, entries)?
+    };
+    out.sync_all()
+}
+
+fn write_tar(
+    existing: Option<File>,
+    out: File,
+    format: ArchiveFormat,
+    name: &str,
+    entries: &[NewEntry],
+) -> Result<File, Error> {
+    let mut builder = tar::Builder::new(Encoder::new(out, format)?);
+    builder.follow_symlinks(false);
+    if let Some(existing) = existing {
+        let mut old = tar::Archive::new(decoder(existing, format)?);
+        for entry in old.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.into_owned();
+            if is_replaced(&path.to_string_lossy(), name) {
+                continue;
+            }
+            let mut header = entry.header().clone();
+            builder.append_data(&mut header, path, &mut entry)?;
+        }
+    }
+    for entry in entries {
+        builder.append_path_with_name(&entry.path, &entry.name)?;
+    }
+    builder.into_inner()?.finish()
+}
+
+fn write_zip(
+    existing: Option<File>,
+    out: File,
+    name: &str,
+    entries: &[NewEntry],
+) -> Result<File, Error> {
+    use std::os::unix::fs::PermissionsExt;
+    use zip::write::SimpleFileOptions;
+
+    let mut writer = zip::ZipWriter::new(out);
+    if let Some(existing) = existing {
+        let mut old = zip::ZipArchive::new(BufReader::new(existing))?;
+        for i in 0..old.len() {
+            let file = old.by_index_raw(i)?;
+            if is_replaced(file.name(), name) {
+                continue;
+            }
+            writer.raw_copy_file(file)?;
+        }
+    }
+    for entry in entries {
+        let options = SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(entry.metadata.permissions().mode())
+            .last_modified_time(zip_time(&entry.metadata));
+        if entry.metadata.is_dir() {
+            writer.add_directory(entry.name.as_str(), options)?;
+        } else if entry.metadata.is_symlink() {
+            let target = fs::read_link(&entry.path)?;
+            writer.add_symlink(entry.name.as_str(), target.to_string_lossy(), options)?;
+        } else {
+            writer.start_file(entry.name.as_str(), options)?;
+            io::copy(&mut File::open(&entry.path)?, &mut writer)?;
+        }
+    }
+    Ok(writer.finish()?)
+}
+
+fn zip_time(metadata: &fs::Metadata) -> zip::DateTime {
+    use chrono::{Datelike, Timelike};
+    let Ok(modified) = metadata.modified() else {
+        return zip::DateTime::default();
+    };
+    let time: chrono::DateTime<chrono::Local> = modified.into();
+    // zip хранит время только с 1980 года, более ранние даты заменяются на 1980-01-01
+    zip::DateTime::from_date_and_time(
+        u16::try_from(time.year()).unwrap_or_default(),
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+/// Перечитывает записанный архив и сверяет хеши добавленных файлов с исходниками
+fn verify_archive(tmp: &Path, format: ArchiveFormat, entries: &[NewEntry]) -> Result<(), Error> {
+    let mut expected: HashMap<&str, &NewEntry> = entries
+        .iter()
+        .filter(|entry| entry.metadata.is_file())
+        .map(|entry| (entry.name.as_str(), entry))
+        .collect();
+    let mut check = |name: &str, reader: &mut dyn Read| -> Result<(), Error> {
+        let Some(entry) = expected.remove(name) else {
+            return Ok(());
+        };
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_reader(reader)?;
+        if hasher.finalize() != transfer::checksum(&entry.path)? {
+            return Err(Error::other(format!(
+                "checksum mismatch of {:?} in archive",
+                entry.path
+            )));
+        }
+        Ok(())
+    };
+
+    let file = File::open(tmp)?;
+    if format == ArchiveFormat::Zip {
+        let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let name = file.name().to_owned();
+            check(&name, &mut file)?;
+        }
+    } else {
+        let mut archive = tar::Archive::new(decoder(file, format)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().into_owned();
+            check(&name, &mut entry)?;
+        }
+    }
+    if let Some(name) = expected.keys().next() {
+        return Err(Error::other(format!("{} is missing in archive", name)));
+    }
+    Ok(())
+}
+
+pub fn decoder(file: File, format: ArchiveFormat) -> Result<Box<dyn Read>, Error> {
+    let reader = BufReader::new(file);
+    Ok(match format {
+        ArchiveFormat::TarGz => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        ArchiveFormat::TarXz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        ArchiveFormat::TarZst => Box::new(zstd::Decoder::with_buffer(reader)?),
+        ArchiveFormat::Tar | ArchiveFormat::Zip => Box::new(reader),
+    })
+}
+
+/// Поток сжатия для tar, который нужно явно завершить, чтобы дописать конец потока
+enum Encoder {
+    Plain(File),
+    Gz(flate2::write::GzEncoder<File>),
+    Xz(xz2::write::XzEncoder<File>),
+    Zst(zstd::Encoder<'static, File>),
+}
+
+impl Encoder {
+    fn new(file: File, format: ArchiveFormat) -> Result<Self, Error> {
+        Ok(match format {
+            ArchiveFormat::TarGz => Encoder::Gz(flate2::write::GzEncoder::new(
+                file,
+                flate2::Compression::default(),
+            )),
+            ArchiveFormat::TarXz => Encoder::Xz(xz2::write::XzEncoder::new(file, 6)),
+            ArchiveFormat::TarZst => Encoder::Zst(zstd::Encoder::new(file, 0)?),
+            ArchiveFormat::Tar | ArchiveFormat::Zip => Encoder::Plain(file),
+        })
+    }
+
+    fn finish(self) -> Result<File, Error> {
+        match self {
+            Encoder::Plain(file) => Ok(file),
+            Encoder::Gz(encoder) => encoder.finish(),
+            Encoder::Xz(encoder) => encoder.finish(),
+            Encoder::Zst(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl Write for Encoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(file) => file.write(buf),
+            Encoder::Gz(encoder) => encoder.write(buf),
+            Encoder::Xz(encoder) => encoder.write(buf),
+            Encoder::Zst(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(file) => file.flush(),
+            Encoder::Gz(encoder) => encoder.flush(),
+            Encoder::Xz(encoder) => encoder.flush(),
+            Encoder::Zst(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tar_names(archive: &Path, format: ArchiveFormat) -> Vec<String> {
+        let mut archive = tar::Archive::new(decoder(File::open(archive).unwrap(), format).unwrap());
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_add_to_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let logs = dir.path().join("logs");
+        fs::create_dir_all(logs.join("app")).unwrap();
+        fs::write(logs.join("app/1.log"), "first").unwrap();
+        fs::write(logs.join("2.log"), "second").unwrap();
+
+        for (file_name, format) in [
+            ("logs.tar", ArchiveFormat::Tar),
+            ("logs.tar.gz", ArchiveFormat::TarGz),
+            ("logs.tar.xz", ArchiveFormat::TarXz),
+            ("logs.tar.zst", ArchiveFormat::TarZst),
+        ] {
+            let archive = dir.path().join(file_name);
+            add_to_archive(&logs.join("2.log"), &archive, format).unwrap();
+            add_to_archive(&logs.join("app"), &archive, format).unwrap();
+            // повторное добавление заменяет запись, а не дублирует ее
+            add_to_archive(&logs.join("2.log"), &archive, format).unwrap();
+            let mut names = tar_names(&archive, format);
+            names.sort();
+            assert_eq!(names, ["2.log", "app", "app/1.log"], "{}", file_name);
+        }
+
+        let archive = dir.path().join("logs.zip");
+        add_to_archive(&logs.join("2.log"), &archive, ArchiveFormat::Zip).unwrap();
+        add_to_archive(&logs.join("app"), &archive, ArchiveFormat::Zip).unwrap();
+        let mut zip = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        let mut names: Vec<_> = zip.file_names().map(str::to_owned).collect();
+        names.sort();
+        assert_eq!(names, ["2.log", "app/", "app/1.log"]);
+        let mut content = String::new();
+        zip.by_name("app/1.log")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "first");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 6);
+    }
+}
@@ -0,0 +1,581 @@
+//! Условия по содержимому файла: поиск текста, контрольная сумма, кодировка и число строк.
+//! Файл читается потоком блоками, целиком в память не загружается. Для папок и других не
+//! обычных файлов условия ложны.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Error, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tracing::trace;
+
+use super::{CheckArgs, Comparison, ConditionChecker, OneOrMany, Pattern};
+use crate::actions::transfer;
+use crate::validation::{pointer_child, Problem, Validate};
+
+const BUF_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+impl CheckArgs {
+    /// Кодировка и число строк, `None` если файл не удалось прочитать
+    pub fn text(&self) -> Option<&TextInfo> {
+        self.details
+            .text
+            .get_or_init(|| {
+                if !self.file_metadata.is_file() {
+                    return None;
+                }
+                read_text_info(&self.file_path)
+                    .map_err(|err| trace!("fail to read {:?}: {}", self.file_path, err))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// Контрольная сумма в hex, считается один раз на файл для каждого алгоритма
+    pub fn checksum(&self, algorithm: HashAlgorithm) -> Option<&str> {
+        let cell = match algorithm {
+            HashAlgorithm::Sha256 => &self.details.sha256,
+            HashAlgorithm::Blake3 => &self.details.blake3,
+        };
+        cell.get_or_init(|| {
+            if !self.file_metadata.is_file() {
+                return None;
+            }
+            let res = match algorithm {
+                HashAlgorithm::Sha256 => sha256(&self.file_path),
+                HashAlgorithm::Blake3 => {
+                    transfer::checksum(&self.file_path).map(|hash| hash.to_hex().to_string())
+                }
+            };
+            res.map_err(|err| trace!("fail to hash {:?}: {}", self.file_path, err))
+                .ok()
+        })
+        .as_deref()
+    }
+}
+
+fn sha256(path: &Path) -> Result<String, Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Поиск в текстовом файле регуляркой или подстрокой, построчно:
+/// ```json
+/// {"content": {"regex": "(?i)invoice\\s+#\\d+"}}
+/// {"content": {"contains": "CONFIDENTIAL", "max_bytes": 65536}}
+/// ```
+/// Просматриваются первые `max_bytes` байт, по умолчанию 10 МБ. Регулярка не находит совпадения
+/// через перевод строки. Файл с нулевым байтом считается двоичным, для него условие ложно.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentCondition {
+    regex: Option<Pattern>,
+    contains: Option<String>,
+    max_bytes: Option<u64>,
+}
+
+impl ConditionChecker for ContentCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        if !args.file_metadata.is_file() {
+            return false;
+        }
+        self.search(&args.file_path)
+            .map_err(|err| trace!("fail to read {:?}: {}", args.file_path, err))
+            .unwrap_or(false)
+    }
+}
+
+impl ContentCondition {
+    fn search(&self, path: &Path) -> Result<bool, Error> {
+        let limit = self.max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+        let mut reader = BufReader::with_capacity(BUF_SIZE, File::open(path)?.take(limit));
+        let mut line = vec![];
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(false);
+            }
+            if line.contains(&0) {
+                return Ok(false);
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\n', '\r']);
+            let found = match (&self.regex, &self.contains) {
+                (Some(regex), _) => regex.is_match(text),
+                (None, Some(contains)) => text.contains(contains.as_str()),
+                (None, None) => false,
+            };
+            if found {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl Validate for ContentCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        match (&self.regex, &self.contains) {
+            (Some(regex), None) => regex.validate(&pointer_child(pointer, "regex"), problems),
+            (None, Some(contains)) if contains.is_empty() => problems.push(Problem::error(
+                pointer_child(pointer, "contains"),
+                "contains can not be empty",
+            )),
+            (None, Some(_)) => {}
+            _ => problems.push(Problem::error(
+                pointer,
+                "content needs exactly one of regex or contains",
+            )),
+        }
+        if self.max_bytes == Some(0) {
+            problems.push(Problem::error(
+                pointer_child(pointer, "max_bytes"),
+                "max_bytes must be greater than 0",
+            ));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Sha256,
+    Blake3,
+}
+
+/// Контрольная сумма равна одному из значений или есть в файле со списком:
+/// ```json
+/// {"checksum": {"algorithm": "sha256", "value": "9f86d081..."}}
+/// {"checksum": {"algorithm": "blake3", "list": "/srv/known.txt"}}
+/// ```
+/// В списке одна сумма в строке, можно в формате `sha256sum`: после суммы идет имя файла.
+/// Пустые строки и строки с `#` пропускаются. Список читается при загрузке правил.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "ChecksumSource", into = "ChecksumSource")]
+pub struct ChecksumCondition {
+    source: ChecksumSource,
+    // общий для всех копий действия, список может быть большим
+    known: Arc<HashSet<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ChecksumSource {
+    algorithm: HashAlgorithm,
+    value: Option<OneOrMany<String>>,
+    list: Option<PathBuf>,
+}
+
+impl TryFrom<ChecksumSource> for ChecksumCondition {
+    type Error = String;
+
+    fn try_from(source: ChecksumSource) -> Result<Self, Self::Error> {
+        let mut known = HashSet::new();
+        if let Some(value) = &source.value {
+            known.extend(value.as_slice().iter().map(|hash| hash.to_lowercase()));
+        }
+        if let Some(list) = &source.list {
+            let text = std::fs::read_to_string(list)
+                .map_err(|err| format!("fail to read checksum list {:?}: {}", list, err))?;
+            for (i, line) in text.lines().enumerate() {
+                let Some(hash) = line.split_whitespace().next() else {
+                    continue;
+                };
+                if hash.starts_with('#') {
+                    continue;
+                }
+                if !is_hex_hash(hash) {
+                    return Err(format!(
+                        "{:?} line {}: expected a 64 character hex checksum",
+                        list,
+                        i + 1
+                    ));
+                }
+                known.insert(hash.to_lowercase());
+            }
+        }
+        Ok(ChecksumCondition {
+            source,
+            known: Arc::new(known),
+        })
+    }
+}
+
+impl From<ChecksumCondition> for ChecksumSource {
+    fn from(checksum: ChecksumCondition) -> Self {
+        checksum.source
+    }
+}
+
+// без содержимого списка: по Debug действия считается ключ исполнителя
+impl fmt::Debug for ChecksumCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.source, f)
+    }
+}
+
+/// SHA-256 и BLAKE3 дают по 32 байта
+fn is_hex_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl ConditionChecker for ChecksumCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        // без сумм не читаем файл зря
+        !self.known.is_empty()
+            && args
+                .checksum(self.source.algorithm)
+                .is_some_and(|hash| self.known.contains(hash))
+    }
+}
+
+impl Validate for ChecksumCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        let Some(value) = &self.source.value else {
+            if self.source.list.is_none() {
+                problems.push(Problem::error(pointer, "checksum needs value or list"));
+            }
+            return;
+        };
+        let value_pointer = pointer_child(pointer, "value");
+        for (i, hash) in value.as_slice().iter().enumerate() {
+            if !is_hex_hash(hash) {
+                let pointer = match value {
+                    OneOrMany::One(_) => value_pointer.clone(),
+                    OneOrMany::Many(_) => pointer_child(&value_pointer, i),
+                };
+                problems.push(Problem::error(
+                    pointer,
+                    format!("invalid checksum `{}`, expected 64 hex characters", hash),
+                ));
+            }
+        }
+        if self.known.is_empty() {
+            problems.push(Problem::warning(pointer, "no checksums to compare with"));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextEncoding {
+    Ascii,
+    /// Корректный UTF-8 с BOM или без, условие `utf8` выполняется и для ASCII
+    Utf8,
+    /// UTF-16 определяется только по BOM
+    #[serde(rename = "utf16le")]
+    Utf16Le,
+    #[serde(rename = "utf16be")]
+    Utf16Be,
+    /// Не UTF-8 и без нулевых байтов, например cp1251 или latin1
+    Other,
+    /// Есть нулевые байты
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextInfo {
+    pub encoding: TextEncoding,
+    /// Последняя строка без перевода строки тоже считается
+    pub lines: u64,
+}
+
+/// Кодировка текста: `{"encoding": "utf8"}` или `{"encoding": ["ascii", "utf16le"]}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct EncodingCondition(OneOrMany<TextEncoding>);
+
+impl ConditionChecker for EncodingCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let Some(text) = args.text() else {
+            return false;
+        };
+        self.0.as_slice().iter().any(|encoding| {
+            *encoding == text.encoding
+                || (*encoding == TextEncoding::Utf8 && text.encoding == TextEncoding::Ascii)
+        })
+    }
+}
+
+/// Число строк текстового файла, для двоичных файлов условие ложно
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LineCountCondition(Comparison<u64>);
+
+impl ConditionChecker for LineCountCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        args.text().is_some_and(|text| {
+            text.encoding != TextEncoding::Binary && self.0.matches(&text.lines)
+        })
+    }
+}
+
+fn read_text_info(path: &Path) -> Result<TextInfo, Error> {
+    let mut file = File::open(path)?;
+    let mut head = vec![];
+    (&mut file).take(3).read_to_end(&mut head)?;
+    let (mut scanner, rest) = match head.as_slice() {
+        [0xef, 0xbb, 0xbf, ..] => (TextScanner::new(None, true), &head[3..]),
+        [0xff, 0xfe, ..] => (TextScanner::new(Some(false), false), &head[2..]),
+        [0xfe, 0xff, ..] => (TextScanner::new(Some(true), false), &head[2..]),
+        _ => (TextScanner::new(None, false), &head[..]),
+    };
+    scanner.update(rest);
+    let mut buf = vec![0; BUF_SIZE];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        scanner.update(&buf[..n]);
+    }
+    Ok(scanner.finish())
+}
+
+/// Считает строки и проверяет кодировку по блокам, неполные символы на границе блока
+/// переносятся в следующий
+struct TextScanner {
+    /// Порядок байтов UTF-16, `None` для однобайтовых кодировок и UTF-8
+    utf16_be: Option<bool>,
+    utf8_bom: bool,
+    ascii: bool,
+    utf8: bool,
+    nul: bool,
+    lines: u64,
+    ends_with_newline: bool,
+    empty: bool,
+    tail: Vec<u8>,
+}
+
+impl TextScanner {
+    fn new(utf16_be: Option<bool>, utf8_bom: bool) -> Self {
+        TextScanner {
+            utf16_be,
+            utf8_bom,
+            ascii: true,
+            utf8: true,
+            nul: false,
+            lines: 0,
+            ends_with_newline: false,
+            empty: true,
+            tail: vec![],
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        if chunk.is_empty() {
+            return;
+        }
+        let mut data = std::mem::take(&mut self.tail);
+        data.extend_from_slice(chunk);
+        match self.utf16_be {
+            Some(big_endian) => {
+                let units = data.chunks_exact(2).map(|pair| {
+                    let pair = [pair[0], pair[1]];
+                    if big_endian {
+                        u16::from_be_bytes(pair)
+                    } else {
+                        u16::from_le_bytes(pair)
+                    }
+                });
+                for unit in units {
+                    self.empty = false;
+                    self.ends_with_newline = unit == u16::from(b'\n');
+                    self.lines += u64::from(self.ends_with_newline);
+                }
+                if data.len() % 2 == 1 {
+                    self.tail.push(data[data.len() - 1]);
+                }
+            }
+            None => {
+                self.empty = false;
+                self.nul |= data.contains(&0);
+                self.ascii &= data.is_ascii();
+                self.lines += data.iter().filter(|b| **b == b'\n').count() as u64;
+                self.ends_with_newline = data.last() == Some(&b'\n');
+                if self.utf8 {
+                    if let Err(err) = std::str::from_utf8(&data) {
+                        match err.error_len() {
+                            Some(_) => self.utf8 = false,
+                            // символ оборвался на границе блока, в его байтах нет `\n`
+                            None => self.tail.extend_from_slice(&data[err.valid_up_to()..]),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> TextInfo {
+        let encoding = match self.utf16_be {
+            Some(true) => TextEncoding::Utf16Be,
+            Some(false) => TextEncoding::Utf16Le,
+            None if self.nul => TextEncoding::Binary,
+            // оборванный символ в конце файла
+            None if !self.utf8 || !self.tail.is_empty() => TextEncoding::Other,
+            None if self.ascii && !self.utf8_bom => TextEncoding::Ascii,
+            None => TextEncoding::Utf8,
+        };
+        let lines = self.lines + u64::from(!self.empty && !self.ends_with_newline);
+        TextInfo { encoding, lines }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{check, problems};
+    use super::super::Condition;
+    use super::*;
+    use serde_json::json;
+
+    fn text_info(bytes: &[u8]) -> TextInfo {
+        let mut scanner = TextScanner::new(None, false);
+        // по одному байту, чтобы символы рвались на границе блоков
+        for byte in bytes {
+            scanner.update(std::slice::from_ref(byte));
+        }
+        scanner.finish()
+    }
+
+    fn invoice(dir: &Path) -> CheckArgs {
+        let path = dir.join("invoice.txt");
+        std::fs::write(&path, "Счет\r\nINVOICE #42\nитого: 100").unwrap();
+        CheckArgs::for_path(&path)
+    }
+
+    fn binary(dir: &Path) -> CheckArgs {
+        let path = dir.join("data.bin");
+        std::fs::write(&path, b"INVOICE\0\n").unwrap();
+        CheckArgs::for_path(&path)
+    }
+
+    #[test]
+    fn test_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = invoice(dir.path());
+        assert!(check(
+            json!({"content": {"regex": r"(?i)invoice\s+#\d+$"}}),
+            &args
+        ));
+        assert!(check(json!({"content": {"contains": "итого"}}), &args));
+        assert!(!check(
+            json!({"content": {"contains": "итого", "max_bytes": 16}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_encoding_and_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = invoice(dir.path());
+        assert!(check(json!({"encoding": "utf8"}), &args));
+        assert!(!check(json!({"encoding": ["ascii", "utf16le"]}), &args));
+        assert!(check(
+            json!({"line_count": {"operator": "equal", "value": 3}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_binary() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = binary(dir.path());
+        assert!(!check(json!({"content": {"contains": "INVOICE"}}), &args));
+        assert!(check(json!({"encoding": "binary"}), &args));
+        assert!(!check(
+            json!({"line_count": {"operator": "greater_than", "value": 0}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_checksum_value() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = invoice(dir.path());
+        let hash = sha256(&args.file_path).unwrap();
+        assert!(check(
+            json!({"checksum": {"algorithm": "sha256", "value": [hash.to_uppercase()]}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_checksum_list() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = invoice(dir.path());
+        let list = dir.path().join("known.txt");
+        let blake3 = transfer::checksum(&args.file_path).unwrap().to_hex();
+        std::fs::write(&list, format!("# известные\n\n{}  invoice.txt\n", blake3)).unwrap();
+        assert!(check(
+            json!({"checksum": {"algorithm": "blake3", "list": list}}),
+            &args
+        ));
+        assert!(!check(
+            json!({"checksum": {"algorithm": "sha256", "list": list}}),
+            &args
+        ));
+        std::fs::write(&list, "not a hash\n").unwrap();
+        assert!(serde_json::from_value::<Condition>(
+            json!({"checksum": {"algorithm": "blake3", "list": list}})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_text_scanner() {
+        assert_eq!(text_info(b"").lines, 0);
+        assert_eq!(text_info(b"a\nb\n").lines, 2);
+        assert_eq!(text_info("й\nё".as_bytes()).encoding, TextEncoding::Utf8);
+        assert_eq!(text_info(b"plain").encoding, TextEncoding::Ascii);
+        assert_eq!(
+            text_info(b"\xcf\xf0\xe8\xe2\xe5\xf2").encoding,
+            TextEncoding::Other
+        );
+        assert_eq!(
+            text_info("обрыв".as_bytes()[..3].as_ref()).encoding,
+            TextEncoding::Other
+        );
+    }
+
+    #[test]
+    fn test_utf16() {
+        let dir = tempfile::tempdir().unwrap();
+        let utf16 = dir.path().join("utf16.txt");
+        std::fs::write(&utf16, b"\xff\xfea\0\n\0b\0").unwrap();
+        assert_eq!(
+            read_text_info(&utf16).unwrap(),
+            TextInfo {
+                encoding: TextEncoding::Utf16Le,
+                lines: 2
+            }
+        );
+        assert!(check(
+            json!({"encoding": "utf16le"}),
+            &CheckArgs::for_path(&utf16)
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(
+            problems(json!({"content": {"regex": "a", "contains": "b"}})).len(),
+            1
+        );
+        assert_eq!(
+            problems(json!({"content": {"contains": "", "max_bytes": 0}})).len(),
+            2
+        );
+        assert_eq!(
+            problems(json!({"checksum": {"algorithm": "sha256", "value": ["abc"]}})).len(),
+            1
+        );
+        assert_eq!(
+            problems(json!({"checksum": {"algorithm": "sha256"}})).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Поиск копий файла по содержимому через `ContentIndex`
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::trace;
+
+use super::{CheckArgs, ConditionChecker};
+use crate::content_index::ContentIndex;
+use crate::validation::{pointer_child, Problem, Validate};
+
+impl CheckArgs {
+    /// Индекс из `content_index_path` или общий индекс в памяти, если он не задан
+    pub fn content_index(&self) -> Arc<ContentIndex> {
+        self.content_index
+            .clone()
+            .unwrap_or_else(ContentIndex::in_memory)
+    }
+
+    /// Более старая копия файла в `dirs`, без папок ищется в папке из правила
+    pub fn original(&self, dirs: &[PathBuf]) -> Option<PathBuf> {
+        let dirs = search_dirs(dirs, self);
+        self.content_index()
+            .find_original(&self.file_path, &dirs)
+            .map_err(|err| trace!("fail to find duplicates of {:?}: {}", self.file_path, err))
+            .ok()
+            .flatten()
+    }
+}
+
+fn search_dirs(dirs: &[PathBuf], args: &CheckArgs) -> Vec<PathBuf> {
+    if !dirs.is_empty() {
+        return dirs.to_vec();
+    }
+    args.watch_root
+        .clone()
+        .or_else(|| args.file_path.parent().map(|dir| dir.to_path_buf()))
+        .into_iter()
+        .collect()
+}
+
+/// В папках есть более старый файл с тем же содержимым:
+/// `{"is_duplicate_of": {"dirs": ["/home/user/Downloads", "/home/user/Documents"]}}`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IsDuplicateOfCondition {
+    #[serde(default)]
+    pub dirs: Vec<PathBuf>,
+}
+
+impl ConditionChecker for IsDuplicateOfCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        args.original(&self.dirs).is_some()
+    }
+}
+
+impl Validate for IsDuplicateOfCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        validate_dirs(&self.dirs, &pointer_child(pointer, "dirs"), problems);
+    }
+}
+
+pub fn validate_dirs(dirs: &[PathBuf], pointer: &str, problems: &mut Vec<Problem>) {
+    for (i, dir) in dirs.iter().enumerate() {
+        let pointer = pointer_child(pointer, i);
+        if !dir.is_absolute() {
+            problems.push(Problem::error(
+                pointer,
+                format!("{:?} is not absolute", dir),
+            ));
+        } else if !dir.is_dir() {
+            problems.push(Problem::warning(
+                pointer,
+                format!("{:?} is not a directory", dir),
+            ));
+        }
+    }
+}
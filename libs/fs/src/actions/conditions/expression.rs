@@ -0,0 +1,724 @@
+//! Компактная запись условий строкой, например
+//! `type == image && size > 2MB && name ~ "^IMG_" && !(ext in [gif, webp])`.
+//!
+//! Выражение разбирается в то же дерево `ConditionOrConditionsGroup`, что и JSON: `&&`, `||` и
+//! `!` превращаются в группы `and`, `or` и `not`, а каждое сравнение в обычное условие. Условие
+//! собирается как JSON и разбирается serde, поэтому значения проверяются так же, как в JSON
+//! записи, а ошибка указывает на колонку в выражении.
+
+use serde_json::{json, Value};
+use std::fmt::{self, Display};
+
+use super::{Condition, ConditionOrConditionsGroup, ConditionType, ConditionsGroup};
+use crate::validation::{Severity, Validate};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpressionError {
+    /// Колонка в выражении, начиная с 1
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid expression at column {}: {}",
+            self.column, self.message
+        )
+    }
+}
+
+fn error(column: usize, message: impl Into<String>) -> ExpressionError {
+    ExpressionError {
+        column,
+        message: message.into(),
+    }
+}
+
+pub fn parse(src: &str) -> Result<ConditionOrConditionsGroup, ExpressionError> {
+    let mut parser = Parser {
+        tokens: lex(src)?,
+        pos: 0,
+    };
+    let cond = parser.or()?;
+    let next = parser.next();
+    if next.token != Token::End {
+        return Err(error(
+            next.column,
+            format!("unexpected {}, expected `&&` or `||`", next.token),
+        ));
+    }
+    Ok(match cond {
+        Condition::ConditionGroup(group) => ConditionOrConditionsGroup::ConditionGroup(group),
+        cond => ConditionOrConditionsGroup::Condition(cond),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    /// Число с необязательным суффиксом: `2MB`, `30d`, `1.5`
+    Number(String),
+    Punct(&'static str),
+    End,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "`{}`", ident),
+            Token::Str(s) => write!(f, "\"{}\"", s),
+            Token::Number(n) => write!(f, "`{}`", n),
+            Token::Punct(p) => write!(f, "`{}`", p),
+            Token::End => write!(f, "end of expression"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Lexed {
+    token: Token,
+    column: usize,
+}
+
+/// Сначала длинные, чтобы `!=` не разобрался как `!` и `=`
+const PUNCTS: &[&str] = &[
+    "&&", "||", "==", "!=", "!~", ">=", "<=", ">", "<", "~", "!", "(", ")", "[", "]", ",",
+];
+
+const COMPARISONS: &[&str] = &["==", "!=", ">=", "<=", ">", "<", "~", "!~"];
+
+fn lex(src: &str) -> Result<Vec<Lexed>, ExpressionError> {
+    let chars = src.chars().collect::<Vec<_>>();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        let token = if c == '"' {
+            // экранируется только кавычка, остальные `\` остаются для регулярок
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(error(column, "unterminated string")),
+                    Some('"') => break,
+                    Some('\\') if chars.get(i + 1) == Some(&'"') => {
+                        s.push('"');
+                        i += 1;
+                    }
+                    Some(&c) => s.push(c),
+                }
+                i += 1;
+            }
+            i += 1;
+            Token::Str(s)
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while chars
+                .get(i)
+                .is_some_and(|c| c.is_ascii_alphanumeric() || *c == '.')
+            {
+                i += 1;
+            }
+            Token::Number(chars[start..i].iter().collect())
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while chars
+                .get(i)
+                .is_some_and(|c| c.is_alphanumeric() || *c == '_' || *c == '.')
+            {
+                i += 1;
+            }
+            Token::Ident(chars[start..i].iter().collect())
+        } else {
+            let rest = chars[i..].iter().take(2).collect::<String>();
+            let punct = PUNCTS
+                .iter()
+                .find(|p| rest.starts_with(**p))
+                .ok_or_else(|| error(column, format!("unexpected character `{}`", c)))?;
+            i += punct.len();
+            Token::Punct(punct)
+        };
+        tokens.push(Lexed { token, column });
+    }
+    tokens.push(Lexed {
+        token: Token::End,
+        column: chars.len() + 1,
+    });
+    Ok(tokens)
+}
+
+/// Значение справа от оператора: строка, слово, число или список `[a, b]`
+struct Operand {
+    items: Vec<String>,
+    list: bool,
+    column: usize,
+}
+
+impl Operand {
+    fn single(&self) -> Result<&str, ExpressionError> {
+        match self.items.as_slice() {
+            [item] if !self.list => Ok(item),
+            _ => Err(error(self.column, "expected a single value, not a list")),
+        }
+    }
+
+    /// Строка или список строк для условий, которые принимают и то и другое
+    fn one_or_many(&self) -> Value {
+        if self.list {
+            json!(self.items)
+        } else {
+            json!(self.items[0])
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Lexed>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Lexed {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Lexed {
+        let lexed = self.tokens[self.pos].clone();
+        if lexed.token != Token::End {
+            self.pos += 1;
+        }
+        lexed
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek().token, Token::Punct(p) if p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ExpressionError> {
+        if self.eat(punct) {
+            return Ok(());
+        }
+        let next = self.peek();
+        Err(error(
+            next.column,
+            format!("expected `{}`, found {}", punct, next.token),
+        ))
+    }
+
+    fn or(&mut self) -> Result<Condition, ExpressionError> {
+        let mut conditions = vec![self.and()?];
+        while self.eat("||") {
+            conditions.push(self.and()?);
+        }
+        Ok(group(ConditionType::Or, conditions))
+    }
+
+    fn and(&mut self) -> Result<Condition, ExpressionError> {
+        let mut conditions = vec![self.unary()?];
+        while self.eat("&&") {
+            conditions.push(self.unary()?);
+        }
+        Ok(group(ConditionType::And, conditions))
+    }
+
+    fn unary(&mut self) -> Result<Condition, ExpressionError> {
+        if self.eat("!") {
+            return Ok(not(self.unary()?));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Condition, ExpressionError> {
+        let next = self.next();
+        match next.token {
+            Token::Punct("(") => {
+                let cond = self.or()?;
+                self.expect(")")?;
+                Ok(cond)
+            }
+            Token::Ident(field) => {
+                if self.peek().token == Token::Punct("(") {
+                    return self.call(&field, next.column);
+                }
+                let op = match &self.peek().token {
+                    Token::Punct(p) if COMPARISONS.contains(p) => Some(*p),
+                    Token::Ident(word) if word == "in" => Some("in"),
+                    _ => None,
+                };
+                let Some(op) = op else {
+                    return leaf(&field, next.column, None);
+                };
+                let op_column = self.next().column;
+                let operand = self.operand()?;
+                leaf(&field, next.column, Some((op, op_column, operand)))
+            }
+            token => Err(error(
+                next.column,
+                format!("expected a condition, found {}", token),
+            )),
+        }
+    }
+
+    /// Вызов вроде `path.glob("**/*.jpg", "**/*.png")`
+    fn call(&mut self, function: &str, column: usize) -> Result<Condition, ExpressionError> {
+        self.expect("(")?;
+        let args_column = self.peek().column;
+        let mut args = vec![self.scalar()?];
+        while self.eat(",") {
+            args.push(self.scalar()?);
+        }
+        self.expect(")")?;
+        let json = match function {
+            "path.glob" => json!({ "glob": args }),
+            "content.contains" => match args.as_slice() {
+                [text] => json!({"content": {"contains": text}}),
+                _ => return Err(error(args_column, "expected one string")),
+            },
+            _ => return Err(error(column, format!("unknown function `{}`", function))),
+        };
+        build(json, args_column)
+    }
+
+    fn operand(&mut self) -> Result<Operand, ExpressionError> {
+        let column = self.peek().column;
+        if self.eat("[") {
+            let mut items = vec![];
+            loop {
+                items.push(self.scalar()?);
+                if !self.eat(",") {
+                    break;
+                }
+            }
+            self.expect("]")?;
+            return Ok(Operand {
+                items,
+                list: true,
+                column,
+            });
+        }
+        Ok(Operand {
+            items: vec![self.scalar()?],
+            list: false,
+            column,
+        })
+    }
+
+    fn scalar(&mut self) -> Result<String, ExpressionError> {
+        let next = self.next();
+        match next.token {
+            Token::Ident(s) | Token::Str(s) | Token::Number(s) => Ok(s),
+            token => Err(error(
+                next.column,
+                format!("expected a value, found {}", token),
+            )),
+        }
+    }
+}
+
+fn group(cond_type: ConditionType, mut conditions: Vec<Condition>) -> Condition {
+    if conditions.len() == 1 {
+        return conditions.remove(0);
+    }
+    Condition::ConditionGroup(ConditionsGroup {
+        cond_type,
+        conditions,
+    })
+}
+
+fn not(cond: Condition) -> Condition {
+    Condition::ConditionGroup(ConditionsGroup {
+        cond_type: ConditionType::Not,
+        conditions: vec![cond],
+    })
+}
+
+fn operator_name(op: &str) -> Option<&'static str> {
+    Some(match op {
+        "==" => "equal",
+        "!=" => "not_equal",
+        ">" => "greater_than",
+        ">=" => "greater_than_or_equal",
+        "<" => "less_than",
+        "<=" => "less_than_or_equal",
+        _ => return None,
+    })
+}
+
+/// Собирает условие для `field op operand`, без оператора это булево поле вроде `ext_mismatch`
+fn leaf(
+    field: &str,
+    field_column: usize,
+    comparison: Option<(&str, usize, Operand)>,
+) -> Result<Condition, ExpressionError> {
+    let Some((op, op_column, operand)) = comparison else {
+        let json = match field {
+            "ext_mismatch" => json!({"extension_mismatch": true}),
+            "exif.has_gps" => json!({"exif": {"has_gps": true}}),
+            // копии ищутся в папке из правила
+            "is_duplicate" => json!({"is_duplicate_of": {}}),
+            _ => {
+                return Err(error(
+                    field_column,
+                    format!(
+                        "`{}` needs a comparison, for example `{} == ...`",
+                        field, field
+                    ),
+                ))
+            }
+        };
+        return build(json, field_column);
+    };
+    let unsupported = || {
+        error(
+            op_column,
+            format!("operator `{}` is not supported for `{}`", op, field),
+        )
+    };
+    // `!=` и `!~` для условий без оператора это отрицание `==` и `~`
+    let (op, negate) = match (op, field) {
+        ("!~", _) => ("~", true),
+        ("!=", "type" | "size" | "lines") => ("!=", false),
+        ("!=", f) if !is_numeric(f) => ("==", true),
+        _ => (op, false),
+    };
+    let column = operand.column;
+    let comparison = |value: Value| -> Result<Value, ExpressionError> {
+        let operator = operator_name(op).ok_or_else(unsupported)?;
+        Ok(json!({"operator": operator, "value": value}))
+    };
+
+    let json = match field {
+        "type" => {
+            let operator = match op {
+                "==" | "in" => "equal",
+                "!=" => "not_equal",
+                _ => return Err(unsupported()),
+            };
+            match operand.items.as_slice() {
+                [kind] if kind == "directory" || kind == "symlink" => {
+                    let entity = json!({"file_system_entity": kind});
+                    let entity = build(entity, column)?;
+                    return Ok(if operator == "not_equal" {
+                        not(entity)
+                    } else {
+                        entity
+                    });
+                }
+                _ => json!({"file_system_entity": {"file": {
+                    "matcher_type": operand.one_or_many(),
+                    "operator": operator,
+                }}}),
+            }
+        }
+        "lines" => {
+            let operator = match op {
+                "!=" => "not_equal",
+                op => operator_name(op).ok_or_else(unsupported)?,
+            };
+            json!({"line_count": {"operator": operator, "value": number(operand.single()?)}})
+        }
+        "content" => {
+            if op != "~" {
+                return Err(unsupported());
+            }
+            json!({"content": {"regex": operand.single()?}})
+        }
+        "sha256" | "blake3" => {
+            if !matches!(op, "==" | "in") {
+                return Err(unsupported());
+            }
+            json!({"checksum": {"algorithm": field, "value": operand.one_or_many()}})
+        }
+        "encoding" => {
+            if !matches!(op, "==" | "in") {
+                return Err(unsupported());
+            }
+            json!({ "encoding": operand.one_or_many() })
+        }
+        "size" => {
+            let operator = match op {
+                "!=" => "not_equal",
+                op => operator_name(op).ok_or_else(unsupported)?,
+            };
+            let (size, unit) = parse_size(operand.single()?)
+                .ok_or_else(|| error(column, "expected a size like `100`, `2MB` or `1.5GB`"))?;
+            json!({"file_size": {"operator": operator, "size": size, "unit": unit}})
+        }
+        "name" => {
+            let pattern = match op {
+                "~" => operand.single()?.to_owned(),
+                "==" => format!("^{}$", regex::escape(operand.single()?)),
+                _ => return Err(unsupported()),
+            };
+            json!({"file_name_pattern_condition": {"pattern": pattern}})
+        }
+        "path" | "rel_path" => {
+            let pattern = match op {
+                "~" => operand.single()?.to_owned(),
+                "==" => format!("^{}$", regex::escape(operand.single()?)),
+                _ => return Err(unsupported()),
+            };
+            json!({"path_pattern": {"pattern": pattern, "relative": field == "rel_path"}})
+        }
+        "mime" | "ext" | "detected_ext" => {
+            if !matches!(op, "==" | "in") {
+                return Err(unsupported());
+            }
+            let name = match field {
+                "mime" => "mime",
+                "ext" => "extension",
+                _ => "detected_extension",
+            };
+            json!({ name: operand.one_or_many() })
+        }
+        "ext_mismatch" | "exif.has_gps" => {
+            if op != "==" {
+                return Err(unsupported());
+            }
+            let value = match operand.single()? {
+                "true" => true,
+                "false" => false,
+                _ => return Err(error(column, "expected `true` or `false`")),
+            };
+            match field {
+                "ext_mismatch" => json!({"extension_mismatch": value}),
+                _ => json!({"exif": {"has_gps": value}}),
+            }
+        }
+        "modified" | "accessed" | "created" => {
+            let bound = match op {
+                "<" => "before",
+                ">" => "after",
+                _ => return Err(unsupported()),
+            };
+            json!({"file_time": {"time": field, bound: operand.single()?}})
+        }
+        "modified.age" | "accessed.age" | "created.age" => {
+            let bound = match op {
+                ">" => "older_than",
+                "<" => "newer_than",
+                _ => return Err(unsupported()),
+            };
+            let time = field.trim_end_matches(".age");
+            json!({"file_time": {"time": time, bound: operand.single()?}})
+        }
+        _ => {
+            let Some((section, name)) = field.split_once('.') else {
+                return Err(error(field_column, format!("unknown field `{}`", field)));
+            };
+            let value = match (section, name) {
+                ("image", "orientation") if op == "==" => json!(operand.single()?),
+                ("image", "width" | "height" | "megapixels" | "aspect_ratio")
+                | ("media", "bitrate" | "sample_rate" | "width" | "height") => {
+                    comparison(number(operand.single()?))?
+                }
+                ("exif", "date_taken") | ("media", "duration") => {
+                    comparison(json!(operand.single()?))?
+                }
+                ("exif", "camera_model")
+                | ("media", "codec" | "container" | "artist" | "album" | "genre" | "title") => {
+                    match op {
+                        "~" => json!(operand.single()?),
+                        "==" => json!(format!("^{}$", regex::escape(operand.single()?))),
+                        _ => return Err(unsupported()),
+                    }
+                }
+                ("image" | "exif" | "media", _) if !is_known(field) => {
+                    return Err(error(field_column, format!("unknown field `{}`", field)))
+                }
+                _ => return Err(unsupported()),
+            };
+            json!({ section: { name: value } })
+        }
+    };
+    let cond = build(json, column)?;
+    Ok(if negate { not(cond) } else { cond })
+}
+
+/// Поля, у которых есть обычные операторы сравнения
+fn is_numeric(field: &str) -> bool {
+    matches!(
+        field,
+        "image.width"
+            | "image.height"
+            | "image.megapixels"
+            | "image.aspect_ratio"
+            | "exif.date_taken"
+            | "media.duration"
+            | "media.bitrate"
+            | "media.sample_rate"
+            | "media.width"
+            | "media.height"
+    )
+}
+
+fn is_known(field: &str) -> bool {
+    is_numeric(field)
+        || matches!(
+            field,
+            "image.orientation"
+                | "exif.camera_model"
+                | "exif.has_gps"
+                | "media.codec"
+                | "media.container"
+                | "media.artist"
+                | "media.album"
+                | "media.genre"
+                | "media.title"
+        )
+}
+
+fn number(text: &str) -> Value {
+    text.parse::<u64>()
+        .map(Value::from)
+        .or_else(|_| text.parse::<f64>().map(Value::from))
+        .unwrap_or_else(|_| json!(text))
+}
+
+/// `2MB` -> (2, megabytes), дробные размеры переводятся в байты: `1.5KB` -> (1536, bytes)
+fn parse_size(text: &str) -> Option<(u64, &'static str)> {
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let (unit, multiplier) = match unit.to_lowercase().as_str() {
+        "" | "b" => ("bytes", 1),
+        "k" | "kb" | "kib" => ("kilobytes", 1024),
+        "m" | "mb" | "mib" => ("megabytes", 1024 * 1024),
+        "g" | "gb" | "gib" => ("gigabytes", 1024 * 1024 * 1024),
+        _ => return None,
+    };
+    if let Ok(size) = number.parse::<u64>() {
+        return Some((size, unit));
+    }
+    let size = number.parse::<f64>().ok()? * multiplier as f64;
+    (size.is_finite() && size >= 0.0 && size <= u64::MAX as f64).then_some((size as u64, "bytes"))
+}
+
+/// Разбирает собранный JSON как обычное условие и сразу проверяет его, ошибки указывают на
+/// значение в выражении
+fn build(json: Value, column: usize) -> Result<Condition, ExpressionError> {
+    let cond: Condition =
+        serde_json::from_value(json).map_err(|err| error(column, err.to_string()))?;
+    let mut problems = vec![];
+    cond.validate("", &mut problems);
+    if let Some(problem) = problems.iter().find(|p| p.severity == Severity::Error) {
+        return Err(error(column, problem.message.clone()));
+    }
+    Ok(cond)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::conditions::{CheckArgs, ConditionChecker};
+    use std::path::Path;
+
+    fn column(src: &str) -> (usize, String) {
+        let err = parse(src).unwrap_err();
+        (err.column, err.message)
+    }
+
+    /// `IMG_0001.png` из 3MB нулей, который считается изображением
+    fn image(dir: &Path) -> CheckArgs {
+        let path = dir.join("IMG_0001.png");
+        std::fs::write(&path, vec![0u8; 3 * 1024 * 1024]).unwrap();
+        CheckArgs {
+            file_type: Some(infer::MatcherType::Image),
+            watch_root: Some(dir.to_path_buf()),
+            ..CheckArgs::for_path(&path)
+        }
+    }
+
+    #[test]
+    fn test_metadata_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = image(dir.path());
+        let check = |src: &str| parse(src).unwrap().check(&args);
+        assert!(check(r#"type == image && size > 2MB && name ~ "^IMG_""#));
+        assert!(check(r#"type in [video, image] && !(ext in [gif, webp])"#));
+        assert!(check(r#"size < 1MB || ext != jpg"#));
+        assert!(!check(r#"name !~ "\d{4}\.png$""#));
+        assert!(check(
+            r#"!(size <= 1.5KB) && modified.age < 1h && type != directory"#
+        ));
+        assert!(!check(r#"ext_mismatch || media.duration > 1m"#));
+    }
+
+    #[test]
+    fn test_path_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = image(dir.path());
+        let check = |src: &str| parse(src).unwrap().check(&args);
+        assert!(check(
+            r#"path.glob("**/*.{jpg,png}") && rel_path ~ "^IMG_""#
+        ));
+        assert!(!check(r#"path.glob("raw/**", "*.jpg") || path ~ "^IMG_""#));
+    }
+
+    #[test]
+    fn test_content_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = image(dir.path());
+        let check = |src: &str| parse(src).unwrap().check(&args);
+        // нулевые байты: двоичный файл без строк и текста
+        assert!(check(r#"encoding in [binary, utf8] && !content ~ "IMG""#));
+        assert!(!check(r#"lines > 0 || content.contains("IMG")"#));
+        assert!(!check("is_duplicate"));
+    }
+
+    #[test]
+    fn test_precedence() {
+        // && связывает сильнее ||
+        let ConditionOrConditionsGroup::ConditionGroup(group) =
+            parse("size > 1 || size < 2 && name == a").unwrap()
+        else {
+            panic!("expected a group");
+        };
+        assert_eq!(group.cond_type, ConditionType::Or);
+        assert!(matches!(
+            &group.conditions[1],
+            Condition::ConditionGroup(ConditionsGroup {
+                cond_type: ConditionType::And,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_error_column() {
+        assert_eq!(column("size > 2MB &&").0, 14);
+        assert_eq!(column(r#"name ~ "(""#).0, 8);
+        assert_eq!(column("size > 2XB").0, 8);
+        assert_eq!(column("size ~ 2").0, 6);
+        assert_eq!(column("colour == red").0, 1);
+        assert_eq!(column(r#"name ~ "a"#).0, 8);
+        assert_eq!(column("(size > 1").0, 10);
+        assert_eq!(column("type == image size > 1").0, 15);
+        assert_eq!(column(r#"!path.find("**/.cache/**")"#).0, 2);
+        assert_eq!(column(r#"path.glob("a", "{b")"#).0, 11);
+        assert_eq!(column("modified.age > 30 days").0, 16);
+        assert_eq!(column(r#"sha256 in ["00"]"#).0, 11);
+        assert_eq!(column("lines ~ 1").0, 7);
+    }
+
+    #[test]
+    fn test_error_message() {
+        let (col, message) = column("image.orientation == diagonal");
+        assert_eq!(col, 22);
+        assert!(message.contains("landscape"), "{}", message);
+    }
+}
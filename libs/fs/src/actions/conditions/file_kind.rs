@@ -0,0 +1,245 @@
+//! Точные условия на тип файла: MIME и расширение по содержимому, расширение в имени и их
+//! несовпадение. Тип по содержимому определяет `infer`, для файлов неизвестного типа условия
+//! на MIME, определенное расширение и несовпадение ложны.
+
+use serde::{Deserialize, Serialize};
+
+use super::{file_name, CheckArgs, ConditionChecker, OneOrMany};
+use crate::validation::{pointer_child, Problem, Validate};
+
+/// Расширения, которые `infer` называет иначе, но которые не нужно считать несовпадением
+const EXTENSION_ALIASES: &[(&str, &[&str])] = &[
+    ("jpg", &["jpeg", "jpe", "jfif"]),
+    ("tif", &["tiff"]),
+    ("gz", &["tgz"]),
+    ("xz", &["txz"]),
+    ("zst", &["tzst"]),
+    ("html", &["htm"]),
+    ("mp4", &["m4v"]),
+    ("zip", &["jar", "apk", "cbz", "whl", "xpi", "nupkg"]),
+];
+
+/// Подходит ли расширение из имени `declared` к расширению по содержимому `detected`
+pub fn extension_matches(declared: &str, detected: &str) -> bool {
+    let declared = declared.to_lowercase();
+    declared == detected
+        || EXTENSION_ALIASES.iter().any(|(canonical, aliases)| {
+            *canonical == detected && aliases.contains(&declared.as_str())
+        })
+}
+
+/// MIME по содержимому: `application/pdf` или все подтипы `image/*`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MimeCondition(OneOrMany<String>);
+
+impl ConditionChecker for MimeCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let Some(kind) = args.file_kind else {
+            return false;
+        };
+        let mime = kind.mime_type();
+        self.0
+            .as_slice()
+            .iter()
+            .any(|pattern| match pattern.strip_suffix("/*") {
+                Some(top) => mime.split('/').next() == Some(top) || top == "*",
+                None => pattern.eq_ignore_ascii_case(mime),
+            })
+    }
+}
+
+impl Validate for MimeCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        check_not_empty(&self.0, pointer, problems);
+        for (i, pattern) in self.0.as_slice().iter().enumerate() {
+            let valid = match pattern.split_once('/') {
+                Some(("*", "*")) => true,
+                Some((top, sub)) => {
+                    !top.is_empty()
+                        && !top.contains('*')
+                        && !sub.is_empty()
+                        && (sub == "*" || !sub.contains('*'))
+                }
+                None => false,
+            };
+            if !valid {
+                problems.push(Problem::error(
+                    item_pointer(&self.0, pointer, i),
+                    format!(
+                        "invalid MIME pattern `{}`, expected `type/subtype` or `type/*`",
+                        pattern
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Расширение в имени файла без учета регистра, можно составное: `tar.gz`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ExtensionCondition(OneOrMany<String>);
+
+impl ConditionChecker for ExtensionCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let name = file_name(&args.file_path).to_lowercase();
+        self.0.as_slice().iter().any(|ext| {
+            let ext = ext.trim_start_matches('.').to_lowercase();
+            name.strip_suffix(&ext)
+                .and_then(|stem| stem.strip_suffix('.'))
+                .is_some_and(|stem| !stem.is_empty())
+        })
+    }
+}
+
+impl Validate for ExtensionCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        check_extensions(&self.0, pointer, problems);
+    }
+}
+
+/// Расширение, которое соответствует содержимому, например `pdf` для PDF с любым именем
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DetectedExtensionCondition(OneOrMany<String>);
+
+impl ConditionChecker for DetectedExtensionCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let Some(kind) = args.file_kind else {
+            return false;
+        };
+        self.0
+            .as_slice()
+            .iter()
+            .any(|ext| extension_matches(ext.trim_start_matches('.'), kind.extension()))
+    }
+}
+
+impl Validate for DetectedExtensionCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        check_extensions(&self.0, pointer, problems);
+    }
+}
+
+/// Расширение в имени не соответствует содержимому, файл без расширения тоже считается
+/// несовпадением
+pub fn extension_mismatch(args: &CheckArgs) -> Option<bool> {
+    let detected = args.file_kind?.extension();
+    let declared = args
+        .file_path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    Some(!extension_matches(declared, detected))
+}
+
+fn check_not_empty(values: &OneOrMany<String>, pointer: &str, problems: &mut Vec<Problem>) {
+    if values.as_slice().is_empty() {
+        problems.push(Problem::error(pointer, "list can not be empty"));
+    }
+}
+
+fn check_extensions(values: &OneOrMany<String>, pointer: &str, problems: &mut Vec<Problem>) {
+    check_not_empty(values, pointer, problems);
+    for (i, ext) in values.as_slice().iter().enumerate() {
+        if ext.trim_start_matches('.').is_empty() || ext.contains('/') {
+            problems.push(Problem::error(
+                item_pointer(values, pointer, i),
+                format!("invalid extension `{}`", ext),
+            ));
+        }
+    }
+}
+
+/// Указатель на элемент, если в JSON был список, и на само значение, если строка
+fn item_pointer(values: &OneOrMany<String>, pointer: &str, i: usize) -> String {
+    match values {
+        OneOrMany::One(_) => pointer.to_owned(),
+        OneOrMany::Many(_) => pointer_child(pointer, i),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{check, problems};
+    use super::*;
+    use serde_json::json;
+    use std::path::{Path, PathBuf};
+
+    /// PDF, сохраненный с расширением epub
+    fn misnamed_pdf(dir: &Path) -> PathBuf {
+        let pdf = dir.join("book.EPUB");
+        std::fs::write(&pdf, b"%PDF-1.7\n").unwrap();
+        pdf
+    }
+
+    #[test]
+    fn test_mime() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = CheckArgs::for_path(&misnamed_pdf(dir.path()));
+        assert!(check(json!({"mime": "application/pdf"}), &args));
+        assert!(check(json!({"mime": ["image/*", "application/*"]}), &args));
+        assert!(!check(json!({"mime": "application/epub+zip"}), &args));
+        assert!(check(json!({"detected_extension": ".pdf"}), &args));
+    }
+
+    #[test]
+    fn test_matcher_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = CheckArgs::for_path(&misnamed_pdf(dir.path()));
+        // infer относит PDF к архивам
+        assert!(check(
+            json!({"file_system_entity": {"file": {"matcher_type": ["doc", "archive"], "operator": "equal"}}}),
+            &args
+        ));
+        assert!(check(
+            json!({"file_system_entity": {"file": {"matcher_type": ["doc", "book"], "operator": "not_equal"}}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let pdf = CheckArgs::for_path(&misnamed_pdf(dir.path()));
+        assert!(check(json!({"extension": ["pdf", "epub"]}), &pdf));
+        assert!(!check(json!({"extension": "tar.gz"}), &pdf));
+
+        let archive = dir.path().join("logs.tar.gz");
+        std::fs::write(&archive, [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(check(
+            json!({"extension": "tar.gz"}),
+            &CheckArgs::for_path(&archive)
+        ));
+    }
+
+    #[test]
+    fn test_extension_mismatch() {
+        let dir = tempfile::tempdir().unwrap();
+        let pdf = CheckArgs::for_path(&misnamed_pdf(dir.path()));
+        assert!(check(json!({"extension_mismatch": true}), &pdf));
+
+        let archive = dir.path().join("logs.tar.gz");
+        std::fs::write(&archive, [0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        assert!(check(
+            json!({"extension_mismatch": false}),
+            &CheckArgs::for_path(&archive)
+        ));
+
+        // тип неизвестен: несовпадение не определить
+        let text = dir.path().join("notes");
+        std::fs::write(&text, "text").unwrap();
+        let args = CheckArgs::for_path(&text);
+        assert!(!check(json!({"extension_mismatch": true}), &args));
+        assert!(!check(json!({"extension_mismatch": false}), &args));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(problems(json!({"mime": ["image/*", "*/*"]})).len(), 0);
+        assert_eq!(problems(json!({"mime": ["pdf", "image/png*"]})).len(), 2);
+        assert_eq!(problems(json!({"extension": []})).len(), 1);
+        assert_eq!(problems(json!({"detected_extension": "."})).len(), 1);
+    }
+}
@@ -0,0 +1,174 @@
+//! Условия по времени изменения, последнего доступа и создания файла
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+use tracing::trace;
+
+use super::{CheckArgs, ComparisonOperator, ConditionChecker, DateValue, DurationValue};
+use crate::validation::{pointer_child, Problem, Validate};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileTimeKind {
+    #[default]
+    Modified,
+    /// Время последнего доступа, при `relatime` обновляется не чаще раза в сутки
+    Accessed,
+    /// Время создания, есть не на всех файловых системах, без него условие ложно
+    Created,
+}
+
+/// Условие по возрасту файла, все заданные границы должны выполняться вместе:
+/// ```json
+/// {"file_time": {"time": "accessed", "older_than": "30d"}}
+/// {"file_time": {"after": "2024-01-01", "newer_than": "12h"}}
+/// ```
+/// Возраст считается от текущего момента, но условие проверяется только при событии файла, сам по
+/// себе файл не перепроверяется, когда становится старше.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileTimeCondition {
+    #[serde(default)]
+    time: FileTimeKind,
+    older_than: Option<DurationValue>,
+    newer_than: Option<DurationValue>,
+    /// Локальная дата, `2024-05-01` или `2024-05-01T12:00:00`
+    before: Option<DateValue>,
+    after: Option<DateValue>,
+}
+
+impl ConditionChecker for FileTimeCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let metadata = &args.file_metadata;
+        let time = match self.time {
+            FileTimeKind::Modified => metadata.modified(),
+            FileTimeKind::Accessed => metadata.accessed(),
+            FileTimeKind::Created => metadata.created(),
+        };
+        match time {
+            Ok(time) => self.is_satisfied(time, SystemTime::now()),
+            Err(err) => {
+                trace!(
+                    "{:?} time of {:?} is not available: {}",
+                    self.time,
+                    args.file_path,
+                    err
+                );
+                false
+            }
+        }
+    }
+}
+
+impl FileTimeCondition {
+    fn is_satisfied(&self, time: SystemTime, now: SystemTime) -> bool {
+        // время из будущего считается только что измененным
+        let age = now.duration_since(time).unwrap_or_default();
+        let local = DateTime::<Local>::from(time).naive_local();
+        self.older_than.is_none_or(|older_than| age > older_than.0)
+            && self.newer_than.is_none_or(|newer_than| age < newer_than.0)
+            && self
+                .before
+                .is_none_or(|before| before.compare(&ComparisonOperator::LessThan, local))
+            && self
+                .after
+                .is_none_or(|after| after.compare(&ComparisonOperator::GreaterThan, local))
+    }
+}
+
+impl Validate for FileTimeCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if self.older_than.is_none()
+            && self.newer_than.is_none()
+            && self.before.is_none()
+            && self.after.is_none()
+        {
+            problems.push(Problem::error(
+                pointer,
+                "file_time needs older_than, newer_than, before or after",
+            ));
+        }
+        if let (Some(older_than), Some(newer_than)) = (self.older_than, self.newer_than) {
+            if older_than >= newer_than {
+                problems.push(Problem::error(
+                    pointer_child(pointer, "newer_than"),
+                    "newer_than must be greater than older_than",
+                ));
+            }
+        }
+        if let (Some(before), Some(after)) = (self.before, self.after) {
+            if before.time <= after.time {
+                problems.push(Problem::error(
+                    pointer_child(pointer, "before"),
+                    "before must be later than after",
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{NaiveDate, TimeZone};
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn condition(json: serde_json::Value) -> FileTimeCondition {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn problems(json: serde_json::Value) -> usize {
+        let mut problems = vec![];
+        condition(json).validate("", &mut problems);
+        problems.len()
+    }
+
+    #[test]
+    fn test_file_time() {
+        let now = SystemTime::from(
+            Local
+                .from_local_datetime(
+                    &NaiveDate::from_ymd_opt(2024, 6, 15)
+                        .unwrap()
+                        .and_hms_opt(12, 0, 0)
+                        .unwrap(),
+                )
+                .unwrap(),
+        );
+        let days_ago = |days: u64| now - Duration::from_secs(days * 24 * 60 * 60);
+
+        let old = condition(json!({"time": "accessed", "older_than": "30d"}));
+        assert_eq!(old.time, FileTimeKind::Accessed);
+        assert!(old.is_satisfied(days_ago(31), now));
+        assert!(!old.is_satisfied(days_ago(29), now));
+        assert!(!old.is_satisfied(now + Duration::from_secs(60), now));
+
+        let recent = condition(json!({"newer_than": "12h"}));
+        assert!(recent.is_satisfied(now - Duration::from_secs(60 * 60), now));
+        assert!(!recent.is_satisfied(days_ago(1), now));
+
+        // 2024-06-14 12:00 входит в день `before`, поэтому не раньше него
+        let range = condition(json!({"after": "2024-06-01", "before": "2024-06-14"}));
+        assert!(range.is_satisfied(days_ago(5), now));
+        assert!(!range.is_satisfied(days_ago(1), now));
+        assert!(!range.is_satisfied(days_ago(14), now));
+
+        assert_eq!(
+            problems(json!({"older_than": "7d", "newer_than": "30d"})),
+            0
+        );
+        assert_eq!(
+            problems(json!({"older_than": "30d", "newer_than": "7d"})),
+            1
+        );
+        assert_eq!(
+            problems(json!({"before": "2024-01-01", "after": "2024-02-01"})),
+            1
+        );
+        assert_eq!(problems(json!({"time": "created"})), 1);
+        assert!(
+            serde_json::from_value::<FileTimeCondition>(json!({"older_than": "30 дней"})).is_err()
+        );
+    }
+}
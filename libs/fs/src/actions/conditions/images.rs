@@ -0,0 +1,322 @@
+//! Условия по размерам изображения и EXIF. Заголовок изображения и EXIF читаются с диска только
+//! при первой проверке такого условия и кешируются в `CheckArgs` для остальных условий правила.
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use tracing::trace;
+
+use super::{CheckArgs, Comparison, ConditionChecker, DateValue, Pattern};
+use crate::validation::{pointer_child, Problem, Validate};
+
+/// Размеры изображения, как оно отображается: с учетом поворота из EXIF Orientation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ExifInfo {
+    pub camera_model: Option<String>,
+    /// DateTimeOriginal, время съемки по часам камеры
+    pub date_taken: Option<NaiveDateTime>,
+    pub has_gps: bool,
+    /// Значение тега Orientation от 1 до 8
+    pub orientation: Option<u32>,
+}
+
+impl CheckArgs {
+    /// Размеры изображения или `None`, если файл не удалось прочитать как изображение
+    pub fn image(&self) -> Option<&ImageInfo> {
+        self.details
+            .image
+            .get_or_init(|| {
+                let (width, height) = image::ImageReader::open(&self.file_path)
+                    .ok()?
+                    .with_guessed_format()
+                    .ok()?
+                    .into_dimensions()
+                    .map_err(|err| trace!("{:?} is not an image: {}", self.file_path, err))
+                    .ok()?;
+                // 5-8 означают поворот на 90 градусов, ширина и высота меняются местами
+                let rotated = self
+                    .exif()
+                    .and_then(|exif| exif.orientation)
+                    .is_some_and(|orientation| (5..=8).contains(&orientation));
+                Some(if rotated {
+                    ImageInfo {
+                        width: height,
+                        height: width,
+                    }
+                } else {
+                    ImageInfo { width, height }
+                })
+            })
+            .as_ref()
+    }
+
+    /// EXIF из JPEG, TIFF, HEIF, PNG или WebP, `None` если его нет
+    pub fn exif(&self) -> Option<&ExifInfo> {
+        self.details
+            .exif
+            .get_or_init(|| read_exif(&self.file_path))
+            .as_ref()
+    }
+}
+
+fn read_exif(path: &Path) -> Option<ExifInfo> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .map_err(|err| trace!("no exif in {:?}: {}", path, err))
+        .ok()?;
+    let field = |tag| exif.get_field(tag, exif::In::PRIMARY);
+    let text = |tag| match &field(tag)?.value {
+        exif::Value::Ascii(values) => {
+            let value = String::from_utf8_lossy(values.first()?);
+            let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+            (!value.is_empty()).then(|| value.to_owned())
+        }
+        _ => None,
+    };
+    Some(ExifInfo {
+        camera_model: text(exif::Tag::Model),
+        date_taken: text(exif::Tag::DateTimeOriginal)
+            .or_else(|| text(exif::Tag::DateTime))
+            .and_then(|date| NaiveDateTime::parse_from_str(&date, "%Y:%m:%d %H:%M:%S").ok()),
+        has_gps: field(exif::Tag::GPSLatitude).is_some(),
+        orientation: field(exif::Tag::Orientation).and_then(|field| field.value.get_uint(0)),
+    })
+}
+
+/// Условие по размерам изображения, для файлов, которые не удалось прочитать как изображение,
+/// всегда ложно
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageCondition {
+    Width(Comparison<u32>),
+    Height(Comparison<u32>),
+    Megapixels(Comparison<f64>),
+    /// Отношение ширины к высоте, 16:9 это 1.78
+    AspectRatio(Comparison<f64>),
+    Orientation(Orientation),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+impl ConditionChecker for ImageCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let Some(image) = args.image() else {
+            return false;
+        };
+        let (width, height) = (image.width, image.height);
+        match self {
+            ImageCondition::Width(cmp) => cmp.matches(&width),
+            ImageCondition::Height(cmp) => cmp.matches(&height),
+            ImageCondition::Megapixels(cmp) => {
+                cmp.matches(&(f64::from(width) * f64::from(height) / 1_000_000.0))
+            }
+            ImageCondition::AspectRatio(cmp) => {
+                height > 0 && cmp.matches(&(f64::from(width) / f64::from(height)))
+            }
+            ImageCondition::Orientation(orientation) => {
+                let actual = match width.cmp(&height) {
+                    std::cmp::Ordering::Greater => Orientation::Landscape,
+                    std::cmp::Ordering::Less => Orientation::Portrait,
+                    std::cmp::Ordering::Equal => Orientation::Square,
+                };
+                actual == *orientation
+            }
+        }
+    }
+}
+
+impl Validate for ImageCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        let (field, value) = match self {
+            ImageCondition::Megapixels(cmp) => ("megapixels", cmp.value),
+            ImageCondition::AspectRatio(cmp) => ("aspect_ratio", cmp.value),
+            _ => return,
+        };
+        if !value.is_finite() || value < 0.0 {
+            problems.push(Problem::error(
+                pointer_child(&pointer_child(pointer, field), "value"),
+                "value must be a non-negative number",
+            ));
+        }
+    }
+}
+
+/// Условие по EXIF, для файлов без EXIF или без нужного тега всегда ложно
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExifCondition {
+    /// Регулярка по тегу Model, например `(?i)iphone`
+    CameraModel(Pattern),
+    DateTaken(Comparison<DateValue>),
+    HasGps(bool),
+}
+
+impl ConditionChecker for ExifCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let Some(exif) = args.exif() else {
+            return false;
+        };
+        match self {
+            ExifCondition::CameraModel(pattern) => {
+                let Some(model) = &exif.camera_model else {
+                    return false;
+                };
+                pattern.is_match(model)
+            }
+            ExifCondition::DateTaken(cmp) => exif
+                .date_taken
+                .is_some_and(|date_taken| cmp.matches_time(date_taken)),
+            ExifCondition::HasGps(has_gps) => exif.has_gps == *has_gps,
+        }
+    }
+}
+
+impl Validate for ExifCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if let ExifCondition::CameraModel(pattern) = self {
+            pattern.validate(&pointer_child(pointer, "camera_model"), problems);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::check;
+    use super::*;
+    use img_parts::{Bytes, DynImage, ImageEXIF};
+    use serde_json::json;
+
+    /// EXIF в big-endian TIFF без префикса `Exif`, его добавляет img-parts: Model, Orientation = 6 и DateTimeOriginal в под-IFD
+    fn exif_block() -> Vec<u8> {
+        let model = b"Pixel 8\0";
+        let date = b"2023:07:14 18:30:00\0";
+        let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+        // IFD0: 3 записи, смещения данных после IFD (8 + 2 + 3 * 12 + 4 = 50)
+        tiff.extend([0, 3]);
+        tiff.extend([0x01, 0x10, 0, 2, 0, 0, 0, model.len() as u8, 0, 0, 0, 50]);
+        tiff.extend([0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0]);
+        let exif_ifd = 50 + model.len() as u8;
+        tiff.extend([0x87, 0x69, 0, 4, 0, 0, 0, 1, 0, 0, 0, exif_ifd]);
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(model);
+        // Exif IFD: DateTimeOriginal
+        let date_offset = exif_ifd + 2 + 12 + 4;
+        tiff.extend([0, 1]);
+        tiff.extend([
+            0x90,
+            0x03,
+            0,
+            2,
+            0,
+            0,
+            0,
+            date.len() as u8,
+            0,
+            0,
+            0,
+            date_offset,
+        ]);
+        tiff.extend([0, 0, 0, 0]);
+        tiff.extend(date);
+        tiff
+    }
+
+    /// JPEG 400x300 с EXIF из `exif_block`
+    fn photo(dir: &Path) -> CheckArgs {
+        let path = dir.join("photo.jpg");
+        image::RgbImage::new(400, 300).save(&path).unwrap();
+        let mut jpeg = DynImage::from_bytes(std::fs::read(&path).unwrap().into())
+            .unwrap()
+            .unwrap();
+        jpeg.set_exif(Some(Bytes::from(exif_block())));
+        jpeg.encoder()
+            .write_to(File::create(&path).unwrap())
+            .unwrap();
+        CheckArgs::for_path(&path)
+    }
+
+    fn text(dir: &Path) -> CheckArgs {
+        let path = dir.join("notes.txt");
+        std::fs::write(&path, "text").unwrap();
+        CheckArgs::for_path(&path)
+    }
+
+    #[test]
+    fn test_rotated_dimensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = photo(dir.path());
+        // снимок повернут, поэтому отображается 300x400
+        assert_eq!(
+            args.image(),
+            Some(&ImageInfo {
+                width: 300,
+                height: 400
+            })
+        );
+        assert!(check(
+            json!({"image": {"width": {"operator": "equal", "value": 300}}}),
+            &args
+        ));
+        assert!(check(json!({"image": {"orientation": "portrait"}}), &args));
+    }
+
+    #[test]
+    fn test_image_ratios() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = photo(dir.path());
+        assert!(check(
+            json!({"image": {"megapixels": {"operator": "greater_than", "value": 0.1}}}),
+            &args
+        ));
+        assert!(!check(
+            json!({"image": {"aspect_ratio": {"operator": "greater_than", "value": 1.0}}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = photo(dir.path());
+        assert!(check(
+            json!({"exif": {"camera_model": "(?i)^pixel"}}),
+            &args
+        ));
+        assert!(check(json!({"exif": {"has_gps": false}}), &args));
+        assert!(check(
+            json!({"exif": {"date_taken": {"operator": "equal", "value": "2023-07-14"}}}),
+            &args
+        ));
+        assert!(!check(
+            json!({"exif": {"date_taken": {"operator": "less_than", "value": "2023-07-14T18:00:00"}}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_not_an_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = text(dir.path());
+        // условия ложны, даже not_equal
+        assert!(!check(
+            json!({"image": {"width": {"operator": "not_equal", "value": 1}}}),
+            &args
+        ));
+        assert!(!check(json!({"exif": {"has_gps": false}}), &args));
+    }
+}
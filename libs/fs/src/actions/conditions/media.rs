@@ -0,0 +1,480 @@
+//! Условия по аудио и видео: длительность, битрейт, кодеки, разрешение и теги. Как и для
+//! изображений, файл читается только при первой проверке такого условия. Аудио и теги ID3,
+//! Vorbis, RIFF INFO и MP4 читает symphonia, разрешение видео берется из контейнера mp4 или mkv.
+
+use infer::MatcherType;
+use matroska::{Settings, TagValue, TargetTypeValue};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::Duration;
+use symphonia::core::codecs::CODEC_TYPE_NULL;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+use tracing::trace;
+
+use super::{CheckArgs, Comparison, ConditionChecker, DurationValue, Pattern};
+use crate::validation::{pointer_child, Problem, Validate};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MediaInfo {
+    pub duration: Option<Duration>,
+    /// Средний битрейт в бит/с: размер файла, деленный на длительность
+    pub bitrate: Option<u64>,
+    pub sample_rate: Option<u32>,
+    /// Кодеки всех дорожек, например `h264`, `aac`, `flac`
+    pub codecs: Vec<String>,
+    /// Разрешение первой видеодорожки
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub tags: MediaTags,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MediaTags {
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub title: Option<String>,
+    /// Год из даты выпуска или записи
+    pub year: Option<String>,
+    /// Номер трека без общего числа треков: `3` из `3/12`
+    pub track: Option<String>,
+}
+
+impl MediaTags {
+    /// Тег по имени подстановки в шаблоне, `album_artist` без тега берется из `artist`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        match name {
+            "artist" => self.artist.as_deref(),
+            "album_artist" => self.album_artist.as_deref().or(self.artist.as_deref()),
+            "album" => self.album.as_deref(),
+            "genre" => self.genre.as_deref(),
+            "title" => self.title.as_deref(),
+            "year" => self.year.as_deref(),
+            "track" => self.track.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+impl CheckArgs {
+    /// Сведения об аудио или видео, `None` для файлов, которые по содержимому не аудио и не
+    /// видео или которые не удалось разобрать
+    pub fn media(&self) -> Option<&MediaInfo> {
+        self.details
+            .media
+            .get_or_init(|| {
+                if !matches!(
+                    self.file_type,
+                    Some(MatcherType::Audio | MatcherType::Video)
+                ) {
+                    return None;
+                }
+                let mime = self.file_kind.map_or("", |kind| kind.mime_type());
+                read_media(&self.file_path, mime, self.file_metadata.len())
+            })
+            .as_ref()
+    }
+}
+
+fn read_media(path: &Path, mime: &str, size: u64) -> Option<MediaInfo> {
+    let mut info = MediaInfo::default();
+    match mime {
+        "video/x-matroska" | "video/webm" => read_matroska(path, &mut info),
+        "video/mp4" | "video/x-m4v" | "video/quicktime" | "audio/m4a" => read_mp4(path, &mut info),
+        _ => None,
+    };
+    // symphonia дополняет параметры аудио и теги, в том числе для mp4 и mkv
+    read_audio(path, &mut info);
+    if info.duration.is_none() && info.codecs.is_empty() {
+        trace!("{:?} is not a media file", path);
+        return None;
+    }
+    info.bitrate = info
+        .duration
+        .filter(|duration| !duration.is_zero())
+        .map(|duration| (size as f64 * 8.0 / duration.as_secs_f64()) as u64);
+    Some(info)
+}
+
+fn read_audio(path: &Path, info: &mut MediaInfo) -> Option<()> {
+    let file = File::open(path).ok()?;
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|ext| ext.to_str()) {
+        hint.with_extension(ext);
+    }
+    let source = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|err| trace!("symphonia can not read {:?}: {}", path, err))
+        .ok()?;
+
+    for track in probed.format.tracks() {
+        let params = &track.codec_params;
+        if params.codec == CODEC_TYPE_NULL {
+            continue;
+        }
+        if let Some(codec) = symphonia::default::get_codecs().get_codec(params.codec) {
+            push_codec(info, codec.short_name);
+        }
+        info.sample_rate = info.sample_rate.or(params.sample_rate);
+        if let (None, Some(time_base), Some(frames)) =
+            (info.duration, params.time_base, params.n_frames)
+        {
+            let time = time_base.calc_time(frames);
+            info.duration =
+                Some(Duration::from_secs(time.seconds) + Duration::from_secs_f64(time.frac));
+        }
+    }
+    // теги бывают и перед потоком (ID3v2), и внутри контейнера (Vorbis comment, MP4)
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            read_tags(revision, &mut info.tags);
+        }
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        read_tags(revision, &mut info.tags);
+    }
+    Some(())
+}
+
+fn read_tags(revision: &MetadataRevision, tags: &mut MediaTags) {
+    for tag in revision.tags() {
+        let value = tag.value.to_string();
+        match tag.std_key {
+            Some(StandardTagKey::Artist) => set_tag(&mut tags.artist, &value),
+            Some(StandardTagKey::AlbumArtist) => set_tag(&mut tags.album_artist, &value),
+            Some(StandardTagKey::Album) => set_tag(&mut tags.album, &value),
+            Some(StandardTagKey::Genre) => set_tag(&mut tags.genre, &value),
+            Some(StandardTagKey::TrackTitle) => set_tag(&mut tags.title, &value),
+            Some(
+                StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate,
+            ) => set_year(&mut tags.year, &value),
+            Some(StandardTagKey::TrackNumber) => set_track(&mut tags.track, &value),
+            _ => {}
+        }
+    }
+}
+
+fn read_mp4(path: &Path, info: &mut MediaInfo) -> Option<()> {
+    let file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mp4 = mp4::Mp4Reader::read_header(BufReader::new(file), size)
+        .map_err(|err| trace!("mp4 can not read {:?}: {}", path, err))
+        .ok()?;
+    if mp4.timescale() > 0 {
+        info.duration = Some(mp4.duration());
+    }
+    let mut tracks = mp4.tracks().values().collect::<Vec<_>>();
+    tracks.sort_by_key(|track| track.track_id());
+    for track in tracks {
+        if let Ok(media_type) = track.media_type() {
+            push_codec(
+                info,
+                match media_type {
+                    mp4::MediaType::H264 => "h264",
+                    mp4::MediaType::H265 => "hevc",
+                    mp4::MediaType::VP9 => "vp9",
+                    mp4::MediaType::AAC => "aac",
+                    mp4::MediaType::TTXT => "tx3g",
+                },
+            );
+        }
+        match track.track_type() {
+            Ok(mp4::TrackType::Video) if info.width.is_none() && track.width() > 0 => {
+                info.width = Some(u32::from(track.width()));
+                info.height = Some(u32::from(track.height()));
+            }
+            Ok(mp4::TrackType::Audio) if info.sample_rate.is_none() => {
+                info.sample_rate = track.sample_freq_index().ok().map(|index| index.freq());
+            }
+            _ => {}
+        }
+    }
+    Some(())
+}
+
+fn read_matroska(path: &Path, info: &mut MediaInfo) -> Option<()> {
+    let mkv = matroska::open(path)
+        .map_err(|err| trace!("matroska can not read {:?}: {}", path, err))
+        .ok()?;
+    info.duration = mkv.info.duration;
+    for track in &mkv.tracks {
+        push_codec(info, &matroska_codec(&track.codec_id));
+        match &track.settings {
+            Settings::Video(video) if info.width.is_none() => {
+                info.width = u32::try_from(video.pixel_width).ok();
+                info.height = u32::try_from(video.pixel_height).ok();
+            }
+            Settings::Audio(audio) if info.sample_rate.is_none() && audio.sample_rate > 0.0 => {
+                info.sample_rate = Some(audio.sample_rate as u32);
+            }
+            _ => {}
+        }
+    }
+    for tag in &mkv.tags {
+        // уровень 50 это альбом или фильм целиком, ниже отдельный трек
+        let album_level = tag.targets.as_ref().is_some_and(|target| {
+            matches!(target.target_type_value, Some(TargetTypeValue::Episode))
+        });
+        for simple in &tag.simple {
+            let Some(TagValue::String(value)) = &simple.value else {
+                continue;
+            };
+            let tags = &mut info.tags;
+            match (simple.name.to_uppercase().as_str(), album_level) {
+                ("ARTIST", true) => set_tag(&mut tags.album_artist, value),
+                ("ARTIST", false) => set_tag(&mut tags.artist, value),
+                ("TITLE", true) => set_tag(&mut tags.album, value),
+                ("TITLE", false) => set_tag(&mut tags.title, value),
+                ("GENRE", _) => set_tag(&mut tags.genre, value),
+                ("DATE_RELEASED" | "DATE_RECORDED", _) => set_year(&mut tags.year, value),
+                ("PART_NUMBER", false) => set_track(&mut tags.track, value),
+                _ => {}
+            }
+        }
+    }
+    if let Some(title) = &mkv.info.title {
+        set_tag(&mut info.tags.title, title);
+    }
+    Some(())
+}
+
+/// `V_MPEG4/ISO/AVC` -> `h264`, `A_OPUS` -> `opus`, `V_VP9` -> `vp9`
+fn matroska_codec(codec_id: &str) -> String {
+    match codec_id {
+        "V_MPEG4/ISO/AVC" => "h264".to_owned(),
+        "V_MPEGH/ISO/HEVC" => "hevc".to_owned(),
+        "A_MPEG/L3" => "mp3".to_owned(),
+        _ => {
+            let name = codec_id.split_once('_').map_or(codec_id, |(_, name)| name);
+            let name = name.split('/').next().unwrap_or(name);
+            name.to_lowercase()
+        }
+    }
+}
+
+fn push_codec(info: &mut MediaInfo, codec: &str) {
+    if !codec.is_empty() && !info.codecs.iter().any(|known| known == codec) {
+        info.codecs.push(codec.to_owned());
+    }
+}
+
+/// Первое непустое значение выигрывает: теги из заголовка файла важнее тегов контейнера
+fn set_tag(field: &mut Option<String>, value: &str) {
+    let value = value.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if field.is_none() && !value.is_empty() {
+        *field = Some(value.to_owned());
+    }
+}
+
+fn set_year(field: &mut Option<String>, date: &str) {
+    let year = date
+        .split(|c: char| !c.is_ascii_digit())
+        .find(|part| part.len() == 4);
+    if let Some(year) = year {
+        set_tag(field, year);
+    }
+}
+
+fn set_track(field: &mut Option<String>, track: &str) {
+    let number = track.split('/').next().unwrap_or_default().trim();
+    if let Ok(number) = number.parse::<u32>() {
+        set_tag(field, &number.to_string());
+    }
+}
+
+/// Условие по аудио или видео, для остальных файлов всегда ложно
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaCondition {
+    Duration(Comparison<DurationValue>),
+    /// Средний битрейт в кбит/с
+    Bitrate(Comparison<u64>),
+    /// Частота дискретизации в Гц
+    SampleRate(Comparison<u32>),
+    /// Регулярка по кодекам дорожек, выполняется, если подошел любой
+    Codec(Pattern),
+    /// Регулярка по контейнеру, определенному по содержимому: `mp4`, `mkv`, `mp3`, `flac`
+    Container(Pattern),
+    Width(Comparison<u32>),
+    Height(Comparison<u32>),
+    Artist(Pattern),
+    Album(Pattern),
+    Genre(Pattern),
+    Title(Pattern),
+}
+
+impl MediaCondition {
+    fn pattern(&self) -> Option<(&'static str, &Pattern)> {
+        match self {
+            MediaCondition::Codec(pattern) => Some(("codec", pattern)),
+            MediaCondition::Container(pattern) => Some(("container", pattern)),
+            MediaCondition::Artist(pattern) => Some(("artist", pattern)),
+            MediaCondition::Album(pattern) => Some(("album", pattern)),
+            MediaCondition::Genre(pattern) => Some(("genre", pattern)),
+            MediaCondition::Title(pattern) => Some(("title", pattern)),
+            _ => None,
+        }
+    }
+}
+
+impl ConditionChecker for MediaCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let Some(media) = args.media() else {
+            return false;
+        };
+        let is_match = |value: Option<&str>| {
+            let (Some((_, pattern)), Some(value)) = (self.pattern(), value) else {
+                return false;
+            };
+            pattern.is_match(value)
+        };
+        match self {
+            MediaCondition::Duration(cmp) => media
+                .duration
+                .is_some_and(|duration| cmp.matches(&DurationValue(duration))),
+            MediaCondition::Bitrate(cmp) => media
+                .bitrate
+                .is_some_and(|bitrate| cmp.matches(&(bitrate / 1000))),
+            MediaCondition::SampleRate(cmp) => {
+                media.sample_rate.is_some_and(|rate| cmp.matches(&rate))
+            }
+            MediaCondition::Codec(_) => media.codecs.iter().any(|codec| is_match(Some(codec))),
+            MediaCondition::Container(_) => is_match(args.file_kind.map(|kind| kind.extension())),
+            MediaCondition::Width(cmp) => media.width.is_some_and(|width| cmp.matches(&width)),
+            MediaCondition::Height(cmp) => media.height.is_some_and(|height| cmp.matches(&height)),
+            MediaCondition::Artist(_) => is_match(media.tags.artist.as_deref()),
+            MediaCondition::Album(_) => is_match(media.tags.album.as_deref()),
+            MediaCondition::Genre(_) => is_match(media.tags.genre.as_deref()),
+            MediaCondition::Title(_) => is_match(media.tags.title.as_deref()),
+        }
+    }
+}
+
+impl Validate for MediaCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if let Some((field, pattern)) = self.pattern() {
+            pattern.validate(&pointer_child(pointer, field), problems);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::check;
+    use super::*;
+    use serde_json::json;
+
+    /// WAV 8 кГц, моно, 16 бит, две секунды тишины и теги в LIST INFO
+    fn wav() -> Vec<u8> {
+        let data = vec![0u8; 8000 * 2 * 2];
+        let mut info = b"INFO".to_vec();
+        for (id, value) in [
+            (b"IART", &b"Boards of Canada\0"[..]),
+            (b"IPRD", b"Music Has the Right to Children\0"),
+            (b"IGNR", b"Electronic\0"),
+            (b"INAM", b"Roygbiv\0"),
+            (b"ICRD", b"1998-04-20\0"),
+        ] {
+            info.extend(id);
+            info.extend((value.len() as u32).to_le_bytes());
+            info.extend(value);
+            if value.len() % 2 == 1 {
+                info.push(0);
+            }
+        }
+        let mut fmt = vec![];
+        fmt.extend(1u16.to_le_bytes()); // PCM
+        fmt.extend(1u16.to_le_bytes()); // каналы
+        fmt.extend(8000u32.to_le_bytes());
+        fmt.extend((8000u32 * 2).to_le_bytes());
+        fmt.extend(2u16.to_le_bytes());
+        fmt.extend(16u16.to_le_bytes());
+
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", &fmt), (b"LIST", &info), (b"data", &data)] {
+            body.extend(id);
+            body.extend((chunk.len() as u32).to_le_bytes());
+            body.extend(chunk);
+        }
+        let mut wav = b"RIFF".to_vec();
+        wav.extend((body.len() as u32).to_le_bytes());
+        wav.extend(body);
+        wav
+    }
+
+    fn track(dir: &Path) -> CheckArgs {
+        let path = dir.join("track.wav");
+        std::fs::write(&path, wav()).unwrap();
+        CheckArgs::for_path(&path)
+    }
+
+    #[test]
+    fn test_media_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = track(dir.path());
+        let media = args.media().unwrap();
+        assert_eq!(media.duration, Some(Duration::from_secs(2)));
+        assert_eq!(media.sample_rate, Some(8000));
+        assert_eq!(media.tags.album_artist, None);
+        assert_eq!(media.tags.get("album_artist"), Some("Boards of Canada"));
+        assert_eq!(media.tags.year.as_deref(), Some("1998"));
+    }
+
+    #[test]
+    fn test_media_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = track(dir.path());
+        assert!(check(
+            json!({"media": {"duration": {"operator": "greater_than_or_equal", "value": "2s"}}}),
+            &args
+        ));
+        assert!(!check(
+            json!({"media": {"duration": {"operator": "greater_than", "value": "1m 30s"}}}),
+            &args
+        ));
+        // 8000 Гц * 16 бит = 128 кбит/с плюс заголовок
+        assert!(check(
+            json!({"media": {"bitrate": {"operator": "greater_than_or_equal", "value": 128}}}),
+            &args
+        ));
+        assert!(check(
+            json!({"media": {"sample_rate": {"operator": "equal", "value": 8000}}}),
+            &args
+        ));
+        // у аудио нет разрешения
+        assert!(!check(
+            json!({"media": {"width": {"operator": "greater_than", "value": 0}}}),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_media_patterns() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = track(dir.path());
+        assert!(check(json!({"media": {"codec": "^pcm_s16le$"}}), &args));
+        assert!(check(json!({"media": {"container": "wav"}}), &args));
+        assert!(check(json!({"media": {"genre": "(?i)electronic"}}), &args));
+        assert!(!check(json!({"media": {"artist": "Aphex"}}), &args));
+    }
+
+    #[test]
+    fn test_not_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let text = dir.path().join("notes.txt");
+        std::fs::write(&text, "text").unwrap();
+        assert!(CheckArgs::for_path(&text).media().is_none());
+    }
+}
@@ -0,0 +1,984 @@
+use chrono::{NaiveDate, NaiveDateTime};
+use infer::MatcherType;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+use tracing::{trace, warn};
+
+use crate::content_index::ContentIndex;
+use crate::validation::{pointer_child, Problem, Validate};
+
+mod content;
+mod duplicates;
+mod expression;
+mod file_kind;
+mod file_time;
+mod images;
+mod media;
+mod patterns;
+
+pub use content::{
+    ChecksumCondition, ContentCondition, EncodingCondition, LineCountCondition, TextInfo,
+};
+pub use duplicates::{validate_dirs, IsDuplicateOfCondition};
+pub use file_kind::{
+    extension_matches, DetectedExtensionCondition, ExtensionCondition, MimeCondition,
+};
+pub use file_time::FileTimeCondition;
+pub use images::{ExifCondition, ExifInfo, ImageCondition, ImageInfo};
+pub use media::{MediaCondition, MediaInfo};
+pub use patterns::{GlobCondition, PathPatternCondition, Pattern};
+
+pub trait ConditionChecker {
+    fn check(&self, args: &CheckArgs) -> bool;
+}
+
+#[derive(Debug)]
+pub struct CheckArgs {
+    pub file_metadata: std::fs::Metadata,
+    pub file_type: Option<infer::MatcherType>,
+    pub file_kind: Option<infer::Type>, // Точный тип по содержимому, из него берется MIME
+    pub file_path: PathBuf,
+    /// Папка из правила, от нее считаются относительные пути в `glob` и `path_pattern`
+    pub watch_root: Option<PathBuf>,
+    pub content_index: Option<Arc<ContentIndex>>,
+    pub details: FileDetails,
+}
+
+/// Сведения о содержимом файла, которые дорого получать. Заполняются при первом обращении из
+/// условия, поэтому правила без таких условий файл не читают.
+#[derive(Debug, Default)]
+pub struct FileDetails {
+    image: OnceLock<Option<ImageInfo>>,
+    exif: OnceLock<Option<ExifInfo>>,
+    media: OnceLock<Option<MediaInfo>>,
+    text: OnceLock<Option<TextInfo>>,
+    sha256: OnceLock<Option<String>>,
+    blake3: OnceLock<Option<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConditionType {
+    Or,
+    And,
+    /// Ни одно из условий не выполнилось, для одного условия это его отрицание
+    Not,
+    /// Выполнилось ровно одно условие
+    Xor,
+    /// Выполнилось хотя бы n условий: `{"at_least": 2}`
+    AtLeast(usize),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case", try_from = "ConditionsSource")]
+pub enum ConditionOrConditionsGroup {
+    Condition(Condition),
+    ConditionGroup(ConditionsGroup),
+}
+
+/// Условия в файле с правилами: дерево из JSON или строка-выражение
+/// `{"expression": "type == image && size > 2MB"}`, которая разбирается в такое же дерево
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ConditionsSource {
+    Condition(Condition),
+    ConditionGroup(ConditionsGroup),
+    Expression(String),
+}
+
+impl TryFrom<ConditionsSource> for ConditionOrConditionsGroup {
+    type Error = expression::ExpressionError;
+
+    fn try_from(source: ConditionsSource) -> Result<Self, Self::Error> {
+        match source {
+            ConditionsSource::Condition(cond) => Ok(ConditionOrConditionsGroup::Condition(cond)),
+            ConditionsSource::ConditionGroup(group) => {
+                Ok(ConditionOrConditionsGroup::ConditionGroup(group))
+            }
+            ConditionsSource::Expression(src) => expression::parse(&src),
+        }
+    }
+}
+
+impl ConditionChecker for ConditionOrConditionsGroup {
+    fn check(&self, args: &CheckArgs) -> bool {
+        match self {
+            ConditionOrConditionsGroup::Condition(cond) => cond.check(args),
+            ConditionOrConditionsGroup::ConditionGroup(conditions) => conditions.check(args),
+        }
+    }
+}
+
+impl ConditionOrConditionsGroup {
+    /// Условия, которые выполнились для файла, нужны для отчета в режиме dry-run
+    pub fn matched(&self, args: &CheckArgs) -> Vec<&Condition> {
+        let mut matched = vec![];
+        match self {
+            ConditionOrConditionsGroup::Condition(cond) => cond.collect_matched(args, &mut matched),
+            ConditionOrConditionsGroup::ConditionGroup(conditions) => {
+                conditions.collect_matched(args, &mut matched)
+            }
+        }
+        matched
+    }
+}
+
+impl ConditionOrConditionsGroup {
+    /// Группы из регулярок `file_name_pattern_condition` вне `not`, для подстановки в шаблоны
+    /// путей. Вызывается после `check`, поэтому остальные условия повторно не проверяются:
+    /// группы дает только совпавшая регулярка.
+    pub fn captures(&self, args: &CheckArgs) -> HashMap<String, String> {
+        let mut captures = HashMap::new();
+        match self {
+            ConditionOrConditionsGroup::Condition(cond) => {
+                cond.collect_captures(&args.file_path, &mut captures)
+            }
+            ConditionOrConditionsGroup::ConditionGroup(conditions) => {
+                conditions.collect_captures(&args.file_path, &mut captures)
+            }
+        }
+        captures
+    }
+}
+
+impl Validate for ConditionOrConditionsGroup {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        match self {
+            ConditionOrConditionsGroup::Condition(cond) => {
+                cond.validate(&pointer_child(pointer, "condition"), problems)
+            }
+            ConditionOrConditionsGroup::ConditionGroup(conditions) => {
+                conditions.validate(&pointer_child(pointer, "condition_group"), problems)
+            }
+        }
+    }
+}
+
+/// Группа условий, которые могут содержать вложенные группы `{"condition_group": {...}}`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConditionsGroup {
+    pub cond_type: ConditionType,
+    pub conditions: Vec<Condition>,
+}
+
+impl ConditionChecker for ConditionsGroup {
+    /// Условия проверяются от дешевых к дорогим и только пока результат группы не известен,
+    /// поэтому файл не читается, если группу уже решили метаданные
+    fn check(&self, args: &CheckArgs) -> bool {
+        let mut conditions = self.conditions.iter().collect::<Vec<_>>();
+        conditions.sort_by_key(|cond| cond.cost());
+        let mut results = conditions.into_iter().map(|cond| cond.check(args));
+        match self.cond_type {
+            ConditionType::Or => results.any(|res| res),
+            ConditionType::And => results.all(|res| res),
+            ConditionType::Not => !results.any(|res| res),
+            ConditionType::Xor => results.filter(|res| *res).take(2).count() == 1,
+            ConditionType::AtLeast(n) => results.filter(|res| *res).take(n).count() == n,
+        }
+    }
+}
+
+impl ConditionsGroup {
+    /// Условия внутри `not` не выполнились, поэтому в отчет не попадают
+    fn collect_matched<'a>(&'a self, args: &CheckArgs, out: &mut Vec<&'a Condition>) {
+        if self.cond_type == ConditionType::Not || !self.check(args) {
+            return;
+        }
+        for cond in &self.conditions {
+            cond.collect_matched(args, out);
+        }
+    }
+
+    fn collect_captures(&self, file_path: &Path, out: &mut HashMap<String, String>) {
+        if self.cond_type == ConditionType::Not {
+            return;
+        }
+        for cond in &self.conditions {
+            cond.collect_captures(file_path, out);
+        }
+    }
+}
+
+impl Validate for ConditionsGroup {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        let conditions_pointer = pointer_child(pointer, "conditions");
+        for (i, cond) in self.conditions.iter().enumerate() {
+            cond.validate(&pointer_child(&conditions_pointer, i), problems);
+        }
+        if let ConditionType::AtLeast(n) = self.cond_type {
+            if n == 0 {
+                problems.push(Problem::warning(
+                    pointer_child(pointer, "cond_type"),
+                    "at_least 0 is always satisfied",
+                ));
+            } else if n > self.conditions.len() {
+                problems.push(Problem::error(
+                    pointer_child(pointer, "cond_type"),
+                    format!(
+                        "at_least {} can never be satisfied by {} conditions",
+                        n,
+                        self.conditions.len()
+                    ),
+                ));
+            }
+        }
+        if let ConditionType::And = self.cond_type {
+            let sizes = self.conditions.iter().filter_map(|cond| match cond {
+                Condition::FileSize(size) => Some(size),
+                _ => None,
+            });
+            if !SizeRange::from_conditions(sizes).is_satisfiable() {
+                problems.push(Problem::error(
+                    pointer,
+                    "file_size conditions can never be satisfied together",
+                ));
+            }
+        }
+    }
+}
+
+// Condition представляет все возможные условия
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    FileSystemEntity(FileSystemEntity),
+    FileSize(FileSizeCondition),
+    FileTime(FileTimeCondition),
+    FileNamePatternCondition(FileNamePatternCondition),
+    Glob(GlobCondition),
+    PathPattern(PathPatternCondition),
+    Mime(MimeCondition),
+    Extension(ExtensionCondition),
+    DetectedExtension(DetectedExtensionCondition),
+    /// Расширение в имени не соответствует содержимому, для файлов неизвестного типа ложно
+    ExtensionMismatch(bool),
+    Image(ImageCondition),
+    Exif(ExifCondition),
+    Media(MediaCondition),
+    Content(ContentCondition),
+    Checksum(ChecksumCondition),
+    Encoding(EncodingCondition),
+    LineCount(LineCountCondition),
+    /// Есть более старая копия файла с тем же содержимым
+    IsDuplicateOf(IsDuplicateOfCondition),
+    /// Вложенная группа
+    ConditionGroup(ConditionsGroup),
+}
+
+impl Condition {
+    pub fn check(&self, args: &CheckArgs) -> bool {
+        match self {
+            Condition::ConditionGroup(group) => group.check(args),
+            Condition::FileSystemEntity(file_system_entity) => file_system_entity.check(args),
+            Condition::FileSize(file_size) => file_size.check(args),
+            Condition::FileTime(file_time) => file_time.check(args),
+            Condition::FileNamePatternCondition(pattern) => pattern.check(&args.file_path),
+            Condition::Glob(glob) => glob.check(args),
+            Condition::PathPattern(pattern) => pattern.check(args),
+            Condition::Mime(mime) => mime.check(args),
+            Condition::Extension(ext) => ext.check(args),
+            Condition::DetectedExtension(ext) => ext.check(args),
+            Condition::ExtensionMismatch(expected) => {
+                file_kind::extension_mismatch(args) == Some(*expected)
+            }
+            Condition::Image(image) => image.check(args),
+            Condition::Exif(exif) => exif.check(args),
+            Condition::Media(media) => media.check(args),
+            Condition::Content(content) => content.check(args),
+            Condition::Checksum(checksum) => checksum.check(args),
+            Condition::Encoding(encoding) => encoding.check(args),
+            Condition::LineCount(lines) => lines.check(args),
+            Condition::IsDuplicateOf(duplicate) => duplicate.check(args),
+        }
+    }
+}
+impl Condition {
+    /// Относительная стоимость проверки: 0 имя и метаданные, которые уже есть, 1 чтение
+    /// заголовка изображения, 2 разбор аудио или видео, 3 чтение всего файла. Группа стоит как
+    /// самое дорогое условие.
+    pub fn cost(&self) -> u8 {
+        match self {
+            Condition::FileSystemEntity(_)
+            | Condition::FileSize(_)
+            | Condition::FileTime(_)
+            | Condition::FileNamePatternCondition(_)
+            | Condition::Glob(_)
+            | Condition::PathPattern(_)
+            | Condition::Mime(_)
+            | Condition::Extension(_)
+            | Condition::DetectedExtension(_)
+            | Condition::ExtensionMismatch(_) => 0,
+            Condition::Image(_) | Condition::Exif(_) => 1,
+            Condition::Media(_) => 2,
+            Condition::Content(_)
+            | Condition::Checksum(_)
+            | Condition::Encoding(_)
+            | Condition::LineCount(_)
+            | Condition::IsDuplicateOf(_) => 3,
+            Condition::ConditionGroup(group) => group
+                .conditions
+                .iter()
+                .map(Condition::cost)
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+
+    fn collect_captures(&self, file_path: &Path, out: &mut HashMap<String, String>) {
+        match self {
+            Condition::ConditionGroup(group) => group.collect_captures(file_path, out),
+            Condition::FileNamePatternCondition(pattern) => pattern.captures(file_path, out),
+            _ => {}
+        }
+    }
+
+    fn collect_matched<'a>(&'a self, args: &CheckArgs, out: &mut Vec<&'a Condition>) {
+        match self {
+            Condition::ConditionGroup(group) => group.collect_matched(args, out),
+            cond => {
+                if cond.check(args) {
+                    out.push(cond)
+                }
+            }
+        }
+    }
+}
+
+impl Validate for Condition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        match self {
+            Condition::FileSystemEntity(file_system_entity) => {
+                file_system_entity.validate(&pointer_child(pointer, "file_system_entity"), problems)
+            }
+            Condition::FileSize(file_size) => {
+                file_size.validate(&pointer_child(pointer, "file_size"), problems)
+            }
+            Condition::FileTime(file_time) => {
+                file_time.validate(&pointer_child(pointer, "file_time"), problems)
+            }
+            Condition::FileNamePatternCondition(pattern) => pattern.validate(
+                &pointer_child(pointer, "file_name_pattern_condition"),
+                problems,
+            ),
+            Condition::Glob(glob) => glob.validate(&pointer_child(pointer, "glob"), problems),
+            Condition::PathPattern(pattern) => {
+                pattern.validate(&pointer_child(pointer, "path_pattern"), problems)
+            }
+            Condition::Mime(mime) => mime.validate(&pointer_child(pointer, "mime"), problems),
+            Condition::Extension(ext) => {
+                ext.validate(&pointer_child(pointer, "extension"), problems)
+            }
+            Condition::DetectedExtension(ext) => {
+                ext.validate(&pointer_child(pointer, "detected_extension"), problems)
+            }
+            Condition::ExtensionMismatch(_) => {}
+            Condition::Image(image) => image.validate(&pointer_child(pointer, "image"), problems),
+            Condition::Exif(exif) => exif.validate(&pointer_child(pointer, "exif"), problems),
+            Condition::Media(media) => media.validate(&pointer_child(pointer, "media"), problems),
+            Condition::Content(content) => {
+                content.validate(&pointer_child(pointer, "content"), problems)
+            }
+            Condition::Checksum(checksum) => {
+                checksum.validate(&pointer_child(pointer, "checksum"), problems)
+            }
+            Condition::Encoding(_) | Condition::LineCount(_) => {}
+            Condition::IsDuplicateOf(duplicate) => {
+                duplicate.validate(&pointer_child(pointer, "is_duplicate_of"), problems)
+            }
+            Condition::ConditionGroup(group) => {
+                group.validate(&pointer_child(pointer, "condition_group"), problems)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileNamePatternCondition {
+    pub pattern: Pattern, // Регулярное выражение для проверки имени файла и расширения
+}
+
+impl FileNamePatternCondition {
+    pub fn check(&self, file_path: &Path) -> bool {
+        self.pattern.is_match(file_name(file_path))
+    }
+
+    fn captures(&self, file_path: &Path, out: &mut HashMap<String, String>) {
+        let Some(re) = self.pattern.regex() else {
+            return;
+        };
+        let Some(caps) = re.captures(file_name(file_path)) else {
+            return;
+        };
+        for (i, name) in re.capture_names().enumerate() {
+            if let Some(m) = caps.get(i) {
+                out.insert(i.to_string(), m.as_str().to_owned());
+                if let Some(name) = name {
+                    out.insert(name.to_owned(), m.as_str().to_owned());
+                }
+            }
+        }
+    }
+}
+
+fn file_name(file_path: &Path) -> &str {
+    file_path
+        .file_name()
+        .unwrap_or_default()
+        .to_str()
+        .unwrap_or_default()
+}
+
+impl Validate for FileNamePatternCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        self.pattern
+            .validate(&pointer_child(pointer, "pattern"), problems);
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSystemEntity {
+    File(FileType), // Обычный файл с типом
+    Directory,      // Папка
+    Symlink,        // Символическая ссылка
+}
+
+impl ConditionChecker for FileSystemEntity {
+    fn check(&self, args: &CheckArgs) -> bool {
+        match &self {
+            FileSystemEntity::File(file) => file.check(args),
+            FileSystemEntity::Directory => args.file_metadata.is_dir(),
+            FileSystemEntity::Symlink => args.file_metadata.is_symlink(),
+        }
+    }
+}
+impl Validate for FileSystemEntity {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if let FileSystemEntity::File(file) = self {
+            file.validate(&pointer_child(pointer, "file"), problems)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileType {
+    // Один тип или список, equal выполняется для любого из них, not_equal ни для одного
+    matcher_type: OneOrMany<MatcherTypeInernal>,
+    operator: ComparisonOperator,
+}
+impl ConditionChecker for FileType {
+    fn check(&self, args: &CheckArgs) -> bool {
+        args.file_type.is_some_and(|file_type| {
+            let is_any = self
+                .matcher_type
+                .as_slice()
+                .iter()
+                .any(|matcher_type| MatcherType::from(matcher_type) == file_type);
+            match &self.operator {
+                ComparisonOperator::Equal => is_any,
+                ComparisonOperator::NotEqual => !is_any,
+                _ => {
+                    warn!("invalid ComparisonOperator for filetype");
+                    false
+                }
+            }
+        })
+    }
+}
+impl Validate for FileType {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if !matches!(
+            self.operator,
+            ComparisonOperator::Equal | ComparisonOperator::NotEqual
+        ) {
+            problems.push(Problem::error(
+                pointer_child(pointer, "operator"),
+                "only equal and not_equal operators are supported for file type",
+            ));
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum MatcherTypeInernal {
+    App,
+    Archive,
+    Audio,
+    Book,
+    Doc,
+    Font,
+    Image,
+    Text,
+    Video,
+    Custom,
+    //TODO: мб нужно добавить тип на случай ошибки потому что такое может быть
+}
+
+impl From<&MatcherTypeInernal> for MatcherType {
+    fn from(item: &MatcherTypeInernal) -> MatcherType {
+        match item {
+            MatcherTypeInernal::App => MatcherType::App,
+            MatcherTypeInernal::Archive => MatcherType::Archive,
+            MatcherTypeInernal::Audio => MatcherType::Audio,
+            MatcherTypeInernal::Book => MatcherType::Book,
+            MatcherTypeInernal::Doc => MatcherType::Doc,
+            MatcherTypeInernal::Font => MatcherType::Font,
+            MatcherTypeInernal::Image => MatcherType::Image,
+            MatcherTypeInernal::Text => MatcherType::Text,
+            MatcherTypeInernal::Video => MatcherType::Video,
+            MatcherTypeInernal::Custom => MatcherType::Custom,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SizeUnit {
+    // TODO: возможно стоит использовать newType(https://lagleki.github.io/patterns/patterns/behavioural/newtype.html)
+    // или вообще найти готовый крейт думаю такой сто проц есть
+    Bytes,
+    Kilobytes,
+    Megabytes,
+    Gigabytes,
+}
+
+impl SizeUnit {
+    fn to_bytes(self, size: u64) -> u64 {
+        self.checked_to_bytes(size).unwrap_or(u64::MAX)
+    }
+
+    fn checked_to_bytes(self, size: u64) -> Option<u64> {
+        match self {
+            SizeUnit::Bytes => Some(size),
+            SizeUnit::Kilobytes => size.checked_mul(1024),
+            SizeUnit::Megabytes => size.checked_mul(1024 * 1024),
+            SizeUnit::Gigabytes => size.checked_mul(1024 * 1024 * 1024),
+        }
+    }
+}
+/// FileSizeCondition условия по размеру файла, нужно быть аккуратными с тригерами перед этим
+/// условием, так как на момент создания файл может быть не до конца записан, и сравнение будет не
+/// корректным, лучше всего использовать условие
+/// ```json
+/// {
+///  "access": {
+///   "kind": {
+///     "close": "any"
+///   }
+///  }
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSizeCondition {
+    operator: ComparisonOperator,
+    size: u64,
+    unit: SizeUnit,
+}
+impl ConditionChecker for FileSizeCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let res = self.is_satisfied(&args.file_metadata);
+        trace!("res: {}", res);
+        res
+    }
+}
+impl FileSizeCondition {
+    fn is_satisfied(&self, metadata: &Metadata) -> bool {
+        let file_size = metadata.len();
+        let size_in_bytes = self.unit.to_bytes(self.size);
+        trace!("file_size: {}, size_in_bytes: {}", file_size, size_in_bytes);
+        self.operator.compare(&file_size, &size_in_bytes)
+    }
+}
+
+impl Validate for FileSizeCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if self.unit.checked_to_bytes(self.size).is_none() {
+            problems.push(Problem::error(
+                pointer_child(pointer, "size"),
+                "size is too large",
+            ));
+        } else if !SizeRange::from_conditions([self]).is_satisfiable() {
+            problems.push(Problem::error(
+                pointer,
+                "file_size condition can never be satisfied",
+            ));
+        }
+    }
+}
+
+/// Диапазон размеров файла, который удовлетворяет набору условий из `and` группы, нужен
+/// только для валидации
+struct SizeRange {
+    empty: bool,
+    min: u64,
+    max: u64,
+    excluded: Vec<u64>,
+}
+
+impl SizeRange {
+    fn from_conditions<'a>(conditions: impl IntoIterator<Item = &'a FileSizeCondition>) -> Self {
+        let mut range = SizeRange {
+            empty: false,
+            min: 0,
+            max: u64::MAX,
+            excluded: vec![],
+        };
+        for cond in conditions {
+            let size = cond.unit.to_bytes(cond.size);
+            match cond.operator {
+                ComparisonOperator::GreaterThan => match size.checked_add(1) {
+                    Some(min) => range.min = range.min.max(min),
+                    None => range.empty = true,
+                },
+                ComparisonOperator::GreaterThanOrEqual => range.min = range.min.max(size),
+                ComparisonOperator::LessThan => match size.checked_sub(1) {
+                    Some(max) => range.max = range.max.min(max),
+                    None => range.empty = true,
+                },
+                ComparisonOperator::LessThanOrEqual => range.max = range.max.min(size),
+                ComparisonOperator::Equal => {
+                    range.min = range.min.max(size);
+                    range.max = range.max.min(size);
+                }
+                ComparisonOperator::NotEqual => range.excluded.push(size),
+            }
+        }
+        range
+    }
+
+    fn is_satisfiable(&self) -> bool {
+        if self.empty || self.min > self.max {
+            return false;
+        }
+        // диапазон пуст только если все его значения исключены через not_equal
+        let span = self.max - self.min;
+        let excluded = self
+            .excluded
+            .iter()
+            .filter(|size| (self.min..=self.max).contains(*size))
+            .collect::<std::collections::HashSet<_>>()
+            .len() as u64;
+        excluded <= span
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ComparisonOperator {
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+    Equal,
+    NotEqual,
+    // TODO:
+    // In(Vec<T>),
+    // NotIn(Vec<T>),
+}
+
+impl ComparisonOperator {
+    /// Сравнивает значение файла `actual` с `expected` из условия
+    fn compare<T: PartialOrd + ?Sized>(&self, actual: &T, expected: &T) -> bool {
+        match self {
+            ComparisonOperator::GreaterThan => actual > expected,
+            ComparisonOperator::GreaterThanOrEqual => actual >= expected,
+            ComparisonOperator::LessThan => actual < expected,
+            ComparisonOperator::LessThanOrEqual => actual <= expected,
+            ComparisonOperator::Equal => actual == expected,
+            ComparisonOperator::NotEqual => actual != expected,
+        }
+    }
+}
+
+/// Одно значение или список: в JSON можно писать `"pdf"` или `["pdf", "epub"]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> OneOrMany<T> {
+    pub fn as_slice(&self) -> &[T] {
+        match self {
+            OneOrMany::One(value) => std::slice::from_ref(value),
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+/// Сравнение значения файла с числом или датой из условия, например
+/// `{"operator": "greater_than", "value": 2560}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Comparison<T> {
+    operator: ComparisonOperator,
+    value: T,
+}
+
+impl<T: PartialOrd> Comparison<T> {
+    fn matches(&self, actual: &T) -> bool {
+        self.operator.compare(actual, &self.value)
+    }
+}
+
+/// Локальные дата `2024-05-01` или дата со временем `2024-05-01T12:00:00`. Если время не
+/// указано, сравниваются только даты: `equal` выполнится для любого времени этого дня.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DateValue {
+    time: NaiveDateTime,
+    date_only: bool,
+}
+
+impl TryFrom<String> for DateValue {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Ok(date) = NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+            return Ok(DateValue {
+                time: date.and_time(chrono::NaiveTime::MIN),
+                date_only: true,
+            });
+        }
+        [
+            "%Y-%m-%dT%H:%M:%S",
+            "%Y-%m-%d %H:%M:%S",
+            "%Y-%m-%dT%H:%M",
+            "%Y-%m-%d %H:%M",
+        ]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&value, format).ok())
+        .map(|time| DateValue {
+            time,
+            date_only: false,
+        })
+        .ok_or_else(|| {
+            format!(
+                "invalid date `{}`, expected `2024-05-01` or `2024-05-01T12:00:00`",
+                value
+            )
+        })
+    }
+}
+
+impl From<DateValue> for String {
+    fn from(value: DateValue) -> Self {
+        if value.date_only {
+            value.time.format("%Y-%m-%d").to_string()
+        } else {
+            value.time.format("%Y-%m-%dT%H:%M:%S").to_string()
+        }
+    }
+}
+
+impl DateValue {
+    /// Сравнивает `actual` с этой датой, если время не указано, то только по дням
+    fn compare(&self, operator: &ComparisonOperator, actual: NaiveDateTime) -> bool {
+        if self.date_only {
+            operator.compare(&actual.date(), &self.time.date())
+        } else {
+            operator.compare(&actual, &self.time)
+        }
+    }
+}
+
+impl Comparison<DateValue> {
+    fn matches_time(&self, actual: NaiveDateTime) -> bool {
+        self.value.compare(&self.operator, actual)
+    }
+}
+
+/// Длительность в формате humantime: `90s`, `3m 30s`, `1h`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DurationValue(Duration);
+
+impl TryFrom<String> for DurationValue {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        humantime::parse_duration(&value)
+            .map(DurationValue)
+            .map_err(|err| format!("invalid duration `{}`: {}", value, err))
+    }
+}
+
+impl From<DurationValue> for String {
+    fn from(value: DurationValue) -> Self {
+        humantime::format_duration(value.0).to_string()
+    }
+}
+
+/// Заготовки для тестов условий и действий
+#[cfg(test)]
+pub mod testing {
+    use super::{CheckArgs, Condition, FileDetails};
+    use crate::validation::{Problem, Validate};
+    use std::path::Path;
+
+    impl CheckArgs {
+        /// Аргументы для файла на диске, как их собирает `Action::execute`: тип по содержимому
+        /// определяется только для файлов, папки из правила и индекса нет
+        pub fn for_path(path: &Path) -> CheckArgs {
+            let file_metadata = std::fs::metadata(path).unwrap();
+            let file_kind = if file_metadata.is_file() {
+                infer::get_from_path(path).unwrap()
+            } else {
+                None
+            };
+            CheckArgs {
+                file_metadata,
+                file_type: file_kind.map(|kind| kind.matcher_type()),
+                file_kind,
+                file_path: path.to_path_buf(),
+                watch_root: None,
+                content_index: None,
+                details: FileDetails::default(),
+            }
+        }
+    }
+
+    pub fn condition(json: serde_json::Value) -> Condition {
+        serde_json::from_value(json).unwrap()
+    }
+
+    pub fn check(json: serde_json::Value, args: &CheckArgs) -> bool {
+        condition(json).check(args)
+    }
+
+    pub fn problems(json: serde_json::Value) -> Vec<Problem> {
+        let mut problems = vec![];
+        condition(json).validate("", &mut problems);
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn group(json: serde_json::Value) -> ConditionOrConditionsGroup {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn check(
+        cond_type: serde_json::Value,
+        conditions: serde_json::Value,
+        args: &CheckArgs,
+    ) -> bool {
+        group(json!({"condition_group": {"cond_type": cond_type, "conditions": conditions}}))
+            .check(args)
+    }
+
+    /// Файл из 10 байт `report.txt`, который считается аудио
+    fn report(dir: &Path) -> CheckArgs {
+        let path = dir.join("report.txt");
+        std::fs::write(&path, "0123456789").unwrap();
+        CheckArgs {
+            file_type: Some(MatcherType::Audio),
+            ..CheckArgs::for_path(&path)
+        }
+    }
+
+    fn small() -> serde_json::Value {
+        json!({"file_size": {"operator": "less_than", "size": 1, "unit": "kilobytes"}})
+    }
+
+    fn big() -> serde_json::Value {
+        json!({"file_size": {"operator": "greater_than", "size": 1, "unit": "kilobytes"}})
+    }
+
+    fn txt() -> serde_json::Value {
+        json!({"file_name_pattern_condition": {"pattern": "^(?P<stem>.+)\\.txt$"}})
+    }
+
+    fn long() -> serde_json::Value {
+        json!({"media": {"duration": {"operator": "greater_than", "value": "1s"}}})
+    }
+
+    #[test]
+    fn test_cheap_conditions_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = report(dir.path());
+        // дорогое условие стоит первым, но проверяется последним и не нужно
+        assert!(!check(json!("and"), json!([long(), big()]), &args));
+        assert!(args.details.media.get().is_none());
+    }
+
+    #[test]
+    fn test_cond_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = report(dir.path());
+        assert!(check(json!("not"), json!([big()]), &args));
+        assert!(!check(json!("not"), json!([big(), small()]), &args));
+        assert!(check(json!("xor"), json!([small(), big()]), &args));
+        assert!(!check(json!("xor"), json!([small(), txt()]), &args));
+        assert!(check(
+            json!({"at_least": 2}),
+            json!([small(), big(), txt()]),
+            &args
+        ));
+        assert!(!check(
+            json!({"at_least": 2}),
+            json!([big(), long()]),
+            &args
+        ));
+    }
+
+    #[test]
+    fn test_nested_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = report(dir.path());
+        let nested = group(
+            json!({"condition_group": {"cond_type": "and", "conditions": [
+                txt(),
+                {"condition_group": {"cond_type": "not", "conditions": [big()]}},
+                {"condition_group": {"cond_type": "or", "conditions": [big(), small()]}},
+            ]}}),
+        );
+        assert!(nested.check(&args));
+        assert_eq!(nested.matched(&args).len(), 2);
+        assert_eq!(nested.captures(&args)["stem"], "report");
+
+        // регулярка внутри `not` групп не дает, хотя и совпала
+        let negated = group(
+            json!({"condition_group": {"cond_type": "not", "conditions": [
+                {"condition_group": {"cond_type": "and", "conditions": [
+                    {"file_name_pattern_condition": {"pattern": "^(?P<negated>report)"}},
+                    big(),
+                ]}},
+            ]}}),
+        );
+        assert!(negated.check(&args));
+        assert!(negated.captures(&args).is_empty());
+    }
+
+    #[test]
+    fn test_validate_nested_groups() {
+        let mut problems = vec![];
+        group(json!({"condition_group": {"cond_type": {"at_least": 3}, "conditions": [
+            {"condition_group": {"cond_type": "and", "conditions": [{"file_name_pattern_condition": {"pattern": "("}}]}},
+        ]}}))
+        .validate("", &mut problems);
+        let pointers = problems
+            .iter()
+            .map(|p| p.pointer.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pointers,
+            [
+                "/condition_group/conditions/0/condition_group/conditions/0/file_name_pattern_condition/pattern",
+                "/condition_group/cond_type",
+            ]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! Регулярки и glob по имени и пути файла. Шаблоны компилируются один раз при разборе правил,
+//! ошибки компиляции сообщает проверка правил, а при проверке файла такой шаблон не совпадает.
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{fmt, path::Path};
+
+use super::{CheckArgs, ConditionChecker, OneOrMany};
+use crate::validation::{pointer_child, Problem, Validate};
+
+/// Регулярное выражение из правил, скомпилированное при загрузке
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct Pattern {
+    source: String,
+    compiled: Result<Regex, regex::Error>,
+}
+
+impl Pattern {
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn regex(&self) -> Option<&Regex> {
+        self.compiled.as_ref().ok()
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex().is_some_and(|re| re.is_match(text))
+    }
+
+    pub fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if let Err(err) = &self.compiled {
+            problems.push(Problem::error(pointer, format!("invalid regex: {}", err)));
+        }
+    }
+}
+
+impl From<String> for Pattern {
+    fn from(source: String) -> Self {
+        let compiled = Regex::new(&source);
+        Pattern { source, compiled }
+    }
+}
+
+impl From<Pattern> for String {
+    fn from(pattern: Pattern) -> Self {
+        pattern.source
+    }
+}
+
+// только исходный текст: по Debug действия считается ключ исполнителя
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.source, f)
+    }
+}
+
+/// Путь, с которым сравниваются шаблоны: относительно папки из правила, а без нее полный
+fn relative_path(args: &CheckArgs) -> &Path {
+    args.watch_root
+        .as_deref()
+        .and_then(|root| args.file_path.strip_prefix(root).ok())
+        .unwrap_or(&args.file_path)
+}
+
+/// Glob по пути относительно папки из правила: `**/*.{jpg,png}`, `photos/*/raw/**`.
+/// `*` не переходит через `/`, шаблон с `/` в начале сравнивается с полным путем.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(from = "OneOrMany<String>", into = "OneOrMany<String>")]
+pub struct GlobCondition {
+    globs: OneOrMany<String>,
+    compiled: Result<CompiledGlobs, globset::Error>,
+}
+
+#[derive(Clone)]
+struct CompiledGlobs {
+    relative: GlobSet,
+    absolute: GlobSet,
+}
+
+impl CompiledGlobs {
+    fn new(globs: &[String]) -> Result<Self, globset::Error> {
+        let mut relative = GlobSetBuilder::new();
+        let mut absolute = GlobSetBuilder::new();
+        for glob in globs {
+            let compiled = GlobBuilder::new(glob).literal_separator(true).build()?;
+            if glob.starts_with('/') {
+                absolute.add(compiled);
+            } else {
+                relative.add(compiled);
+            }
+        }
+        Ok(CompiledGlobs {
+            relative: relative.build()?,
+            absolute: absolute.build()?,
+        })
+    }
+}
+
+impl ConditionChecker for GlobCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        let Ok(compiled) = &self.compiled else {
+            return false;
+        };
+        compiled.absolute.is_match(&args.file_path)
+            || compiled.relative.is_match(relative_path(args))
+    }
+}
+
+impl Validate for GlobCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if self.globs.as_slice().is_empty() {
+            problems.push(Problem::error(pointer, "list can not be empty"));
+        }
+        // в тексте ошибки globset есть сам шаблон
+        if let Err(err) = &self.compiled {
+            problems.push(Problem::error(pointer, format!("invalid glob: {}", err)));
+        }
+    }
+}
+
+impl From<OneOrMany<String>> for GlobCondition {
+    fn from(globs: OneOrMany<String>) -> Self {
+        let compiled = CompiledGlobs::new(globs.as_slice());
+        GlobCondition { globs, compiled }
+    }
+}
+
+impl From<GlobCondition> for OneOrMany<String> {
+    fn from(glob: GlobCondition) -> Self {
+        glob.globs
+    }
+}
+
+impl fmt::Debug for GlobCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("GlobCondition").field(&self.globs).finish()
+    }
+}
+
+/// Регулярка по полному пути или по пути относительно папки из правила:
+/// `{"path_pattern": {"pattern": "^inbox/\\d{4}/", "relative": true}}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathPatternCondition {
+    pub pattern: Pattern,
+    #[serde(default)]
+    pub relative: bool,
+}
+
+impl PathPatternCondition {
+    fn path<'a>(&self, args: &'a CheckArgs) -> &'a Path {
+        if self.relative {
+            relative_path(args)
+        } else {
+            &args.file_path
+        }
+    }
+}
+
+impl ConditionChecker for PathPatternCondition {
+    fn check(&self, args: &CheckArgs) -> bool {
+        self.path(args)
+            .to_str()
+            .is_some_and(|path| self.pattern.is_match(path))
+    }
+}
+
+impl Validate for PathPatternCondition {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        self.pattern
+            .validate(&pointer_child(pointer, "pattern"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::{condition, problems};
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    /// Путь в папке из правила, сам файл не нужен, метаданные берутся от папки
+    fn args(root: &Path, path: &str) -> CheckArgs {
+        CheckArgs {
+            file_path: root.join(path),
+            watch_root: Some(root.to_path_buf()),
+            ..CheckArgs::for_path(root)
+        }
+    }
+
+    #[test]
+    fn test_glob() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let photo = args(root, "2024/trip/IMG_1.JPG");
+        let images = condition(json!({"glob": "**/*.{jpg,JPG,png}"}));
+        assert!(images.check(&photo));
+        assert!(images.check(&args(root, "cover.png")));
+        // `*` не переходит через `/`
+        let shallow = condition(json!({"glob": ["*.JPG", "2024/*.JPG"]}));
+        assert!(!shallow.check(&photo));
+        let absolute = format!("{}/2024/**", root.display());
+        assert!(condition(json!({"glob": absolute})).check(&photo));
+    }
+
+    #[test]
+    fn test_path_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let photo = args(root, "2024/trip/IMG_1.JPG");
+        let relative =
+            condition(json!({"path_pattern": {"pattern": r"^\d{4}/", "relative": true}}));
+        assert!(relative.check(&photo));
+        assert!(!condition(json!({"path_pattern": {"pattern": r"^\d{4}/"}})).check(&photo));
+        assert!(condition(json!({"path_pattern": {"pattern": "/trip/"}})).check(&photo));
+        // без папки из правила относительный путь совпадает с полным
+        let no_root = CheckArgs {
+            file_path: PathBuf::from("2024/a.JPG"),
+            ..CheckArgs::for_path(root)
+        };
+        assert!(relative.check(&no_root));
+    }
+
+    #[test]
+    fn test_source_is_kept() {
+        // регулярка уже скомпилирована, но в Debug и JSON остается исходный текст
+        let pattern: Pattern = serde_json::from_value(json!("^IMG_")).unwrap();
+        assert_eq!(format!("{:?}", pattern), r#""^IMG_""#);
+        assert_eq!(serde_json::to_value(&pattern).unwrap(), json!("^IMG_"));
+        assert_eq!(
+            format!("{:?}", condition(json!({"glob": "**/*.{jpg,JPG,png}"}))),
+            r#"Glob(GlobCondition(One("**/*.{jpg,JPG,png}")))"#
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(problems(json!({"glob": ["**/*.jpg", "{a"]})).len(), 1);
+        assert_eq!(problems(json!({"glob": []})).len(), 1);
+        assert_eq!(
+            problems(json!({"path_pattern": {"pattern": "(["}})).len(),
+            1
+        );
+        assert_eq!(
+            problems(json!({"file_name_pattern_condition": {"pattern": "(["}})).len(),
+            1
+        );
+    }
+}
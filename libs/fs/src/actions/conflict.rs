@@ -0,0 +1,421 @@
+use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tokio::fs;
+use tracing::trace;
+
+use super::pipeline::Applied;
+use super::TrashAction;
+
+/// Что делать, если в месте назначения уже есть файл с таким именем
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Ничего не делать, исходный файл остается на месте
+    Skip,
+    /// Заменить существующий файл
+    #[default]
+    Overwrite,
+    /// Подобрать свободное имя вида `name (1).ext`
+    Rename,
+    /// Добавить к имени время, `name_20240501-120000.ext`
+    RenameWithTimestamp,
+    /// Заменить, только если исходный файл изменялся позже существующего
+    KeepNewer,
+    /// Заменить, только если исходный файл больше существующего
+    KeepLarger,
+    /// Завершить действие с ошибкой
+    Fail,
+}
+
+/// Итог разрешения конфликта имен
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Можно выполнять действие по пути `dest`, если `replace` то там уже что-то лежит и это
+    /// нужно заменить
+    Proceed {
+        dest: PathBuf,
+        replace: bool,
+    },
+    Skip,
+}
+
+impl ConflictPolicy {
+    pub async fn resolve(&self, src: &Path, dest: &Path) -> Result<Resolution, Error> {
+        let existing = match fs::symlink_metadata(dest).await {
+            Ok(existing) => existing,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(Resolution::Proceed {
+                    dest: dest.to_path_buf(),
+                    replace: false,
+                })
+            }
+            Err(err) => return Err(err),
+        };
+        // файл уже на месте: замена удалила бы сам исходник
+        if is_same_file(src, &existing).await? {
+            trace!("{:?} is already at {:?}, nothing to do", src, dest);
+            return Ok(Resolution::Skip);
+        }
+        trace!("{:?} already exists, resolve with {:?}", dest, self);
+
+        let replace = Resolution::Proceed {
+            dest: dest.to_path_buf(),
+            replace: true,
+        };
+        match self {
+            ConflictPolicy::Skip => Ok(Resolution::Skip),
+            ConflictPolicy::Overwrite => Ok(replace),
+            ConflictPolicy::Rename => Ok(Resolution::Proceed {
+                dest: free_name(dest, "").await?,
+                replace: false,
+            }),
+            ConflictPolicy::RenameWithTimestamp => {
+                let suffix = chrono::Local::now().format("_%Y%m%d-%H%M%S").to_string();
+                Ok(Resolution::Proceed {
+                    dest: free_name(dest, &suffix).await?,
+                    replace: false,
+                })
+            }
+            ConflictPolicy::KeepNewer => {
+                let src_metadata = fs::metadata(src).await?;
+                if src_metadata.modified()? > existing.modified()? {
+                    Ok(replace)
+                } else {
+                    Ok(Resolution::Skip)
+                }
+            }
+            ConflictPolicy::KeepLarger => {
+                let src_metadata = fs::metadata(src).await?;
+                if src_metadata.len() > existing.len() {
+                    Ok(replace)
+                } else {
+                    Ok(Resolution::Skip)
+                }
+            }
+            ConflictPolicy::Fail => Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{:?} already exists", dest),
+            )),
+        }
+    }
+}
+
+/// `existing` это тот же файл, что и `src`: тот же путь или жесткая ссылка на него
+async fn is_same_file(src: &Path, existing: &Metadata) -> Result<bool, Error> {
+    let src = fs::symlink_metadata(src).await?;
+    Ok(src.dev() == existing.dev() && src.ino() == existing.ino())
+}
+
+/// Подбирает свободное имя рядом с `dest`: сначала `stem{suffix}.ext` (если суффикс не пустой),
+/// потом `stem{suffix} (1).ext`, `stem{suffix} (2).ext` и т.д.
+async fn free_name(dest: &Path, suffix: &str) -> Result<PathBuf, Error> {
+    let stem = dest
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let ext = dest
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let start = if suffix.is_empty() { 1 } else { 0 };
+    for n in start.. {
+        let name = if n == 0 {
+            format!("{}{}{}", stem, suffix, ext)
+        } else {
+            format!("{}{} ({}){}", stem, suffix, n, ext)
+        };
+        let candidate = dest.with_file_name(name);
+        if fs::symlink_metadata(&candidate).await.is_err() {
+            return Ok(candidate);
+        }
+    }
+    unreachable!()
+}
+
+/// Файлы, которые шаг заменил при конфликте имен. Без журнала шаг сам переименовывает
+/// результат поверх файла. С журналом файл сначала уходит в корзину, и запись об этом попадает
+/// в журнал перед записью о самом шаге: отмена шага освобождает путь, после чего замененный файл
+/// возвращается из корзины на место.
+#[derive(Debug)]
+pub struct Replaced {
+    trash: bool,
+    /// Корзина вместо `$XDG_DATA_HOME/Trash`, как `ExecOptions::trash_dir`
+    trash_dir: Option<PathBuf>,
+    trashed: Mutex<Vec<Applied>>,
+}
+
+impl Replaced {
+    pub const fn new(trash: bool, trash_dir: Option<PathBuf>) -> Self {
+        Self {
+            trash,
+            trash_dir,
+            trashed: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Готовит замену существующего `dest`. Папки не заменяются: rename поверх непустой папки
+    /// не работает, а удалить ее целиком до выполнения шага значит потерять ее при ошибке.
+    pub async fn prepare(&self, dest: &Path, dry_run: bool) -> Result<(), Error> {
+        if fs::symlink_metadata(dest).await?.is_dir() {
+            return Err(Error::new(
+                ErrorKind::IsADirectory,
+                format!("{:?} is a directory, only files are replaced", dest),
+            ));
+        }
+        if !self.trash {
+            return Ok(());
+        }
+        let trash = TrashAction::default();
+        if let Some(applied) = trash
+            .execute(dest, self.trash_dir.as_deref(), dry_run)
+            .await?
+        {
+            self.lock().push(applied);
+        }
+        Ok(())
+    }
+
+    /// Перемещенные в корзину файлы в порядке замены
+    pub fn take(&self) -> Vec<Applied> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Applied>> {
+        self.trashed.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::conditions::CheckArgs;
+    use super::super::template::TemplateContext;
+    use super::super::{move_file, resolve_conflict, MoveFileAction};
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src.txt");
+        let dest = dir.path().join("photo.jpg");
+        std::fs::write(&src, "larger file").unwrap();
+
+        let free = dir.path().join("free.jpg");
+        assert_eq!(
+            ConflictPolicy::Fail.resolve(&src, &free).await.unwrap(),
+            Resolution::Proceed {
+                dest: free,
+                replace: false
+            }
+        );
+
+        std::fs::write(&dest, "small").unwrap();
+        assert_eq!(
+            ConflictPolicy::Skip.resolve(&src, &dest).await.unwrap(),
+            Resolution::Skip
+        );
+        assert_eq!(
+            ConflictPolicy::Overwrite
+                .resolve(&src, &dest)
+                .await
+                .unwrap(),
+            Resolution::Proceed {
+                dest: dest.clone(),
+                replace: true
+            }
+        );
+        assert_eq!(
+            ConflictPolicy::KeepLarger
+                .resolve(&src, &dest)
+                .await
+                .unwrap(),
+            Resolution::Proceed {
+                dest: dest.clone(),
+                replace: true
+            }
+        );
+        // dest записан позже src
+        assert_eq!(
+            ConflictPolicy::KeepNewer
+                .resolve(&src, &dest)
+                .await
+                .unwrap(),
+            Resolution::Skip
+        );
+        assert_eq!(
+            ConflictPolicy::Fail
+                .resolve(&src, &dest)
+                .await
+                .unwrap_err()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+
+        std::fs::write(dir.path().join("photo (1).jpg"), "").unwrap();
+        assert_eq!(
+            ConflictPolicy::Rename.resolve(&src, &dest).await.unwrap(),
+            Resolution::Proceed {
+                dest: dir.path().join("photo (2).jpg"),
+                replace: false
+            }
+        );
+        let Resolution::Proceed { dest: renamed, .. } = ConflictPolicy::RenameWithTimestamp
+            .resolve(&src, &dest)
+            .await
+            .unwrap()
+        else {
+            panic!("must be renamed");
+        };
+        let name = renamed.file_name().unwrap().to_string_lossy();
+        assert!(
+            name.starts_with("photo_") && name.ends_with(".jpg"),
+            "{}",
+            name
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("photo.jpg");
+        std::fs::write(&src, "jpeg").unwrap();
+        let hard_link = dir.path().join("link.jpg");
+        std::fs::hard_link(&src, &hard_link).unwrap();
+
+        for policy in [
+            ConflictPolicy::Overwrite,
+            ConflictPolicy::Rename,
+            ConflictPolicy::KeepLarger,
+            ConflictPolicy::Fail,
+        ] {
+            assert_eq!(policy.resolve(&src, &src).await.unwrap(), Resolution::Skip);
+            assert_eq!(
+                policy.resolve(&src, &hard_link).await.unwrap(),
+                Resolution::Skip
+            );
+        }
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "jpeg");
+    }
+
+    #[tokio::test]
+    async fn test_move_to_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        // файл уже разложен по папке `{ext}`, новое событие по нему дает тот же путь
+        let src = dir.path().join("jpg/photo.jpg");
+        std::fs::create_dir(dir.path().join("jpg")).unwrap();
+        std::fs::write(&src, "jpeg").unwrap();
+        let action: MoveFileAction = serde_json::from_value(serde_json::json!({
+            "destination": dir.path().join("{ext}"),
+        }))
+        .unwrap();
+        let args = CheckArgs::for_path(&src);
+        let ctx = TemplateContext::for_args(&args);
+
+        let applied = move_file(&src, &action, &ctx, false).await.unwrap();
+        assert_eq!(applied, None);
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "jpeg");
+    }
+
+    async fn resolve(
+        src: &Path,
+        dest: &Path,
+        policy: ConflictPolicy,
+        dry_run: bool,
+    ) -> Option<PathBuf> {
+        let args = CheckArgs::for_path(src);
+        let ctx = TemplateContext::for_args(&args);
+        resolve_conflict(src, dest, policy, &ctx, dry_run)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_resolve_conflict_overwrite() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, "new").unwrap();
+        let dest = dir.path().join("dest.txt");
+        std::fs::write(&dest, "old").unwrap();
+        let policy = ConflictPolicy::Overwrite;
+
+        // файл заменит сам шаг, до этого он остается на месте
+        for dry_run in [true, false] {
+            assert_eq!(
+                resolve(&src, &dest, policy, dry_run).await,
+                Some(dest.clone())
+            );
+            assert_eq!(std::fs::read_to_string(&dest).unwrap(), "old");
+        }
+
+        // папки не заменяются
+        let dest_dir = dir.path().join("photos");
+        std::fs::create_dir_all(dest_dir.join("inner")).unwrap();
+        let args = CheckArgs::for_path(&src);
+        let ctx = TemplateContext::for_args(&args);
+        let err = resolve_conflict(&src, &dest_dir, policy, &ctx, false)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::IsADirectory);
+        assert!(dest_dir.join("inner").exists());
+
+        assert_eq!(resolve(&src, &src, policy, false).await, None);
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "new");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_conflict_keep_newer() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src.txt");
+        std::fs::write(&src, "src").unwrap();
+        let dest = dir.path().join("dest.txt");
+        std::fs::write(&dest, "dest").unwrap();
+        let policy = ConflictPolicy::KeepNewer;
+        let set_mtime = |path: &Path, secs| {
+            filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(secs, 0)).unwrap()
+        };
+
+        // существующий файл новее, его не трогаем
+        set_mtime(&src, 1_000_000);
+        set_mtime(&dest, 2_000_000);
+        assert_eq!(resolve(&src, &dest, policy, false).await, None);
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "dest");
+
+        set_mtime(&src, 3_000_000);
+        assert_eq!(
+            resolve(&src, &dest, policy, false).await,
+            Some(dest.clone())
+        );
+
+        assert_eq!(resolve(&src, &src, policy, false).await, None);
+        assert!(src.exists());
+    }
+
+    #[tokio::test]
+    async fn test_failed_move_keeps_dest() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("photos");
+        std::fs::create_dir_all(src.join("inner")).unwrap();
+        let sorted = dir.path().join("sorted");
+        std::fs::create_dir(&sorted).unwrap();
+        std::fs::write(sorted.join("photos"), "old").unwrap();
+        let action: MoveFileAction = serde_json::from_value(serde_json::json!({
+            "destination": sorted,
+        }))
+        .unwrap();
+        let args = CheckArgs::for_path(&src);
+        let ctx = TemplateContext::for_args(&args);
+
+        // папку нельзя переименовать поверх файла, файл остается на месте
+        assert!(move_file(&src, &action, &ctx, false).await.is_err());
+        assert_eq!(
+            std::fs::read_to_string(sorted.join("photos")).unwrap(),
+            "old"
+        );
+        assert!(src.join("inner").exists());
+    }
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, trace};
+
+use super::pipeline::Applied;
+use super::template::{PathTemplate, TemplateContext};
+use super::transfer::{self, CopyOptions, Preserve};
+use super::{prepare_dir, resolve_conflict, validate_template_dir, ConflictPolicy};
+use crate::validation::{pointer_child, Problem, Validate};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CopyFileAction {
+    destination: PathTemplate, // Папка назначения, может содержать подстановки
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+    // Копировать папки вместе с содержимым, без этого папки пропускаются с ошибкой
+    #[serde(default)]
+    recursive: bool,
+    #[serde(default)]
+    preserve: Preserve,
+    // Клонировать файлы, если файловая система поддерживает reflink, иначе обычная копия
+    #[serde(default = "default_reflink")]
+    reflink: bool,
+    #[serde(default)]
+    verify_checksum: bool,
+}
+
+fn default_reflink() -> bool {
+    true
+}
+
+impl CopyFileAction {
+    pub fn templates(&self) -> Vec<&PathTemplate> {
+        vec![&self.destination]
+    }
+
+    pub async fn execute(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext<'_>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        if !self.recursive && fs::symlink_metadata(src).await?.is_dir() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} is a directory, set `recursive` to copy it", src),
+            ));
+        }
+        let file_name = src.file_name().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("fail to copy, invalid filename {:?}", src),
+            )
+        })?;
+        let dest = prepare_dir(&self.destination, ctx, dry_run)
+            .await?
+            .join(file_name);
+        let Some(dest) = resolve_conflict(src, &dest, self.on_conflict, ctx, dry_run).await? else {
+            return Ok(None);
+        };
+        let applied = Applied::Copied { to: dest.clone() };
+        if dry_run {
+            info!("[dry-run] copy {:?} to {:?}", src, dest);
+            return Ok(Some(applied));
+        }
+        trace!("Copying {:?} to {:?}", src, dest);
+        let src = src.to_path_buf();
+        let options = CopyOptions {
+            verify_checksum: self.verify_checksum,
+            reflink: self.reflink,
+            preserve: self.preserve,
+        };
+        tokio::task::spawn_blocking(move || transfer::copy_path(&src, &dest, &options)).await??;
+        Ok(Some(applied))
+    }
+}
+
+impl Validate for CopyFileAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        validate_template_dir(
+            &self.destination,
+            &pointer_child(pointer, "destination"),
+            problems,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::conditions::CheckArgs;
+    use filetime::FileTime;
+    use std::path::PathBuf;
+
+    async fn run(action: &CopyFileAction, src: &Path) -> Result<Option<Applied>, Error> {
+        let args = CheckArgs::for_path(src);
+        action
+            .execute(src, &TemplateContext::for_args(&args), false)
+            .await
+    }
+
+    /// Папка `photos/2024/a.jpg` и действие, копирующее ее в `backup/{name}`
+    fn photos(dir: &Path) -> (PathBuf, CopyFileAction) {
+        let src = dir.join("photos");
+        std::fs::create_dir_all(src.join("2024")).unwrap();
+        std::fs::write(src.join("2024/a.jpg"), "jpeg").unwrap();
+        let action = serde_json::from_value(serde_json::json!({
+            "destination": dir.join("backup/{name}"),
+            "preserve": {"timestamps": false},
+            "recursive": true,
+        }))
+        .unwrap();
+        (src, action)
+    }
+
+    #[tokio::test]
+    async fn test_copy_requires_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, action) = photos(dir.path());
+        let action = CopyFileAction {
+            recursive: false,
+            ..action
+        };
+        assert!(run(&action, &src).await.is_err());
+        assert!(!dir.path().join("backup/photos/photos").exists());
+    }
+
+    #[tokio::test]
+    async fn test_copy_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, action) = photos(dir.path());
+        let mtime = FileTime::from_unix_time(1_700_000_000, 0);
+        filetime::set_file_mtime(src.join("2024/a.jpg"), mtime).unwrap();
+
+        run(&action, &src).await.unwrap();
+        let copied = dir.path().join("backup/photos/photos/2024/a.jpg");
+        assert_eq!(std::fs::read_to_string(&copied).unwrap(), "jpeg");
+        assert_ne!(
+            FileTime::from_last_modification_time(&std::fs::metadata(&copied).unwrap()),
+            mtime
+        );
+        // исходник остается на месте
+        assert!(src.join("2024/a.jpg").exists());
+    }
+
+    #[tokio::test]
+    async fn test_copy_rename_on_conflict() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, action) = photos(dir.path());
+        run(&action, &src).await.unwrap();
+        let action = CopyFileAction {
+            on_conflict: ConflictPolicy::Rename,
+            ..action
+        };
+        run(&action, &src).await.unwrap();
+        assert!(dir
+            .path()
+            .join("backup/photos/photos (1)/2024/a.jpg")
+            .exists());
+    }
+}
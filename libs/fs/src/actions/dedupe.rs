@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::io::Error;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::{info, trace};
+
+use super::conditions::{validate_dirs, CheckArgs};
+use super::pipeline::Applied;
+use super::transfer;
+use super::{ExecOptions, TrashAction};
+use crate::validation::{pointer_child, Problem, Validate};
+
+/// Убирает файл, если в папках есть его более старая копия, для остальных файлов ничего не
+/// делает. Копии ищутся так же, как в условии `is_duplicate_of`:
+/// `{"dedupe": {"mode": "hardlink", "dirs": ["/home/user/Downloads"]}}`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DedupeAction {
+    #[serde(default)]
+    mode: DedupeMode,
+    // Где искать оригинал, по умолчанию в папке из правила
+    #[serde(default)]
+    dirs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupeMode {
+    /// Удалить копию, с журналом она попадает в корзину, как в `delete_file`
+    Delete,
+    #[default]
+    Trash,
+    /// Заменить копию жесткой ссылкой на оригинал, место освобождается, путь остается
+    Hardlink,
+    /// Заменить копию символической ссылкой на оригинал
+    Symlink,
+}
+
+impl DedupeAction {
+    pub async fn execute(
+        &self,
+        path: &Path,
+        args: &CheckArgs,
+        options: &ExecOptions,
+    ) -> Result<Option<Applied>, Error> {
+        let dry_run = options.dry_run;
+        let trash_dir = options.trash_dir.as_deref();
+        let index = args.content_index();
+        let dirs = self.dirs.clone();
+        // после предыдущих шагов цепочки путь мог измениться, проверяем текущий
+        let lookup = CheckArgs {
+            file_metadata: args.file_metadata.clone(),
+            file_type: args.file_type,
+            file_kind: args.file_kind,
+            file_path: path.to_path_buf(),
+            watch_root: args.watch_root.clone(),
+            content_index: Some(index),
+            details: Default::default(),
+        };
+        let Some(original) = tokio::task::spawn_blocking(move || lookup.original(&dirs)).await?
+        else {
+            trace!("{:?} has no older copies", path);
+            return Ok(None);
+        };
+        trace!("{:?} is a copy of {:?}", path, original);
+        match self.mode {
+            DedupeMode::Trash => {
+                TrashAction::default()
+                    .execute(path, trash_dir, dry_run)
+                    .await
+            }
+            DedupeMode::Delete if options.journal.is_some() => {
+                TrashAction::default()
+                    .execute(path, trash_dir, dry_run)
+                    .await
+            }
+            DedupeMode::Delete => {
+                if dry_run {
+                    info!("[dry-run] remove {:?}, a copy of {:?}", path, original);
+                } else {
+                    fs::remove_file(path).await?;
+                }
+                Ok(Some(Applied::Deleted {
+                    path: path.to_path_buf(),
+                }))
+            }
+            DedupeMode::Hardlink | DedupeMode::Symlink => {
+                let applied = Applied::Deduplicated {
+                    path: path.to_path_buf(),
+                    original: original.clone(),
+                };
+                if dry_run {
+                    info!("[dry-run] replace {:?} with a link to {:?}", path, original);
+                    return Ok(Some(applied));
+                }
+                let (path, hard) = (path.to_path_buf(), self.mode == DedupeMode::Hardlink);
+                tokio::task::spawn_blocking(move || replace_with_link(&path, &original, hard))
+                    .await??;
+                Ok(Some(applied))
+            }
+        }
+    }
+}
+
+/// Ссылка создается рядом под временным именем и переименовывается поверх копии, поэтому путь
+/// не пропадает ни на момент замены, ни при ошибке
+fn replace_with_link(path: &Path, original: &Path, hard: bool) -> Result<(), Error> {
+    transfer::create_replacing(path, |tmp| {
+        if hard {
+            std::fs::hard_link(original, tmp)
+        } else {
+            std::os::unix::fs::symlink(std::fs::canonicalize(original)?, tmp)
+        }
+    })?;
+    trace!("replaced {:?} with a link to {:?}", path, original);
+    Ok(())
+}
+
+impl Validate for DedupeAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        validate_dirs(&self.dirs, &pointer_child(pointer, "dirs"), problems);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_index::ContentIndex;
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::Arc;
+    use std::time::{Duration, SystemTime};
+
+    fn args(path: &Path, index: &Arc<ContentIndex>) -> CheckArgs {
+        CheckArgs {
+            watch_root: Some(path.parent().unwrap().to_path_buf()),
+            content_index: Some(index.clone()),
+            ..CheckArgs::for_path(path)
+        }
+    }
+
+    fn action(mode: &str) -> DedupeAction {
+        serde_json::from_value(serde_json::json!({ "mode": mode })).unwrap()
+    }
+
+    /// `Downloads/book.pdf` часовой давности и его копия `book (1).pdf`
+    fn downloads(dir: &Path) -> (Arc<ContentIndex>, PathBuf, PathBuf) {
+        let index = Arc::new(ContentIndex::open(&dir.join("index.json")).unwrap());
+        let downloads = dir.join("Downloads");
+        std::fs::create_dir(&downloads).unwrap();
+        let original = downloads.join("book.pdf");
+        let copy = downloads.join("book (1).pdf");
+        std::fs::write(&original, "%PDF-1.7 book").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&original)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+        std::fs::write(&copy, "%PDF-1.7 book").unwrap();
+        (index, original, copy)
+    }
+
+    #[tokio::test]
+    async fn test_original_is_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, original, _) = downloads(dir.path());
+        let res = action("delete")
+            .execute(&original, &args(&original, &index), &ExecOptions::default())
+            .await;
+        assert_eq!(res.unwrap(), None);
+        assert!(original.exists());
+    }
+
+    #[tokio::test]
+    async fn test_delete_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, _, copy) = downloads(dir.path());
+        let applied = action("delete")
+            .execute(
+                &copy,
+                &args(&copy, &index),
+                &ExecOptions {
+                    dry_run: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(applied, Some(Applied::Deleted { path: copy.clone() }));
+        assert!(copy.exists());
+    }
+
+    #[tokio::test]
+    async fn test_hardlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, original, copy) = downloads(dir.path());
+        let applied = action("hardlink")
+            .execute(&copy, &args(&copy, &index), &ExecOptions::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            std::fs::metadata(&copy).unwrap().ino(),
+            std::fs::metadata(&original).unwrap().ino()
+        );
+        // ссылка на оригинал больше не копия
+        let res = action("symlink")
+            .execute(&copy, &args(&copy, &index), &ExecOptions::default())
+            .await;
+        assert_eq!(res.unwrap(), None);
+
+        applied.undo().await.unwrap();
+        assert_ne!(
+            std::fs::metadata(&copy).unwrap().ino(),
+            std::fs::metadata(&original).unwrap().ino()
+        );
+        assert_eq!(std::fs::read(&copy).unwrap(), b"%PDF-1.7 book");
+    }
+
+    #[tokio::test]
+    async fn test_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (index, original, copy) = downloads(dir.path());
+        action("symlink")
+            .execute(&copy, &args(&copy, &index), &ExecOptions::default())
+            .await
+            .unwrap();
+        assert!(std::fs::symlink_metadata(&copy).unwrap().is_symlink());
+        assert_eq!(
+            std::fs::read_link(&copy).unwrap(),
+            original.canonicalize().unwrap()
+        );
+    }
+}
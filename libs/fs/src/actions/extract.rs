@@ -0,0 +1,483 @@
+//! Распаковка архивов zip, tar, tar.gz, tar.xz и tar.zst. Архив распаковывается во временную
+//! папку рядом с назначением и переименовывается только после успешной распаковки всех записей,
+//! поэтому при ошибке или превышении лимитов в назначении ничего не остается. Записи с абсолютными
+//! путями, `..` или ссылками наружу считаются вредоносными и прерывают распаковку.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Error, ErrorKind, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use tracing::{info, trace, warn};
+
+use super::archive::{decoder, ArchiveFormat};
+use super::pipeline::Applied;
+use super::template::{PathTemplate, TemplateContext};
+use super::transfer;
+use super::{resolve_conflict, validate_template_dir, ConflictPolicy};
+use crate::validation::{pointer_child, Problem, Validate};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExtractAction {
+    destination: PathTemplate, // Папка, в которую распаковывается архив, например `/data/{stem}`
+    // Формат архива, по умолчанию определяется по расширению или по содержимому
+    #[serde(default)]
+    format: Option<ArchiveFormat>,
+    // По умолчанию существующая папка не затирается, а подбирается свободное имя
+    #[serde(default = "default_on_conflict")]
+    on_conflict: ConflictPolicy,
+    // Защита от zip-бомб: суммарный размер распакованных файлов в байтах и число записей
+    #[serde(default = "default_max_total_size")]
+    max_total_size: u64,
+    #[serde(default = "default_max_entries")]
+    max_entries: u64,
+    // Удалить архив после успешной распаковки
+    #[serde(default)]
+    delete_source: bool,
+}
+
+fn default_on_conflict() -> ConflictPolicy {
+    ConflictPolicy::Rename
+}
+
+fn default_max_total_size() -> u64 {
+    4 * 1024 * 1024 * 1024
+}
+
+fn default_max_entries() -> u64 {
+    100_000
+}
+
+impl ExtractAction {
+    pub fn deletes_source(&self) -> bool {
+        self.delete_source
+    }
+
+    pub fn templates(&self) -> Vec<&PathTemplate> {
+        vec![&self.destination]
+    }
+
+    pub async fn execute(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext<'_>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        let format = self
+            .format
+            .or_else(|| ArchiveFormat::from_path(&src.to_string_lossy()))
+            .or_else(|| {
+                ctx.args
+                    .file_kind
+                    .as_ref()
+                    .and_then(ArchiveFormat::from_kind)
+            })
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{:?} is not a supported archive", src),
+                )
+            })?;
+        let dest = self.destination.render(ctx)?;
+        let Some(dest) = resolve_conflict(src, &dest, self.on_conflict, ctx, dry_run).await? else {
+            return Ok(None);
+        };
+        let applied = Applied::Extracted {
+            from: src.to_path_buf(),
+            to: dest.clone(),
+            source_deleted: self.delete_source,
+        };
+        if dry_run {
+            info!(
+                "[dry-run] extract {:?} archive {:?} to {:?}",
+                format, src, dest
+            );
+            if self.delete_source {
+                info!("[dry-run] remove {:?}", src);
+            }
+            return Ok(Some(applied));
+        }
+
+        let src = src.to_path_buf();
+        let limits = Limits {
+            max_total_size: self.max_total_size,
+            max_entries: self.max_entries,
+            total_size: 0,
+            entries: 0,
+        };
+        let delete_source = self.delete_source;
+        tokio::task::spawn_blocking(move || {
+            extract(&src, &dest, format, limits)?;
+            trace!("{:?} extracted to {:?}", src, dest);
+            if delete_source {
+                fs::remove_file(&src)?;
+            }
+            Ok::<_, Error>(())
+        })
+        .await??;
+        Ok(Some(applied))
+    }
+}
+
+impl Validate for ExtractAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        validate_template_dir(
+            &self.destination,
+            &pointer_child(pointer, "destination"),
+            problems,
+        );
+        for (field, limit) in [
+            ("max_total_size", self.max_total_size),
+            ("max_entries", self.max_entries),
+        ] {
+            if limit == 0 {
+                problems.push(Problem::error(
+                    pointer_child(pointer, field),
+                    "limit is zero, nothing can be extracted",
+                ));
+            }
+        }
+    }
+}
+
+/// Счетчики распакованного, чтобы остановиться на zip-бомбе до того, как кончится диск
+struct Limits {
+    max_total_size: u64,
+    max_entries: u64,
+    total_size: u64,
+    entries: u64,
+}
+
+impl Limits {
+    fn add_entry(&mut self) -> Result<(), Error> {
+        self.entries += 1;
+        if self.entries > self.max_entries {
+            return Err(Error::other(format!(
+                "archive has more than {} entries",
+                self.max_entries
+            )));
+        }
+        Ok(())
+    }
+
+    /// Сколько еще байт можно распаковать
+    fn remaining(&self) -> u64 {
+        self.max_total_size.saturating_sub(self.total_size)
+    }
+
+    fn add_size(&mut self, size: u64) -> Result<(), Error> {
+        self.total_size = self.total_size.saturating_add(size);
+        if self.total_size > self.max_total_size {
+            return Err(Error::other(format!(
+                "extracted size exceeds {} bytes",
+                self.max_total_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Распаковывает архив в папку `dest`, которой еще не должно быть
+fn extract(
+    src: &Path,
+    dest: &Path,
+    format: ArchiveFormat,
+    mut limits: Limits,
+) -> Result<(), Error> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = transfer::tmp_path(dest)?;
+    fs::create_dir(&tmp)?;
+    let file = File::open(src)?;
+    let res = if format == ArchiveFormat::Zip {
+        extract_zip(file, &tmp, &mut limits)
+    } else {
+        extract_tar(file, format, &tmp, &mut limits)
+    }
+    .and_then(|()| fs::rename(&tmp, dest));
+    if let Err(err) = res {
+        if let Err(cleanup_err) = fs::remove_dir_all(&tmp) {
+            warn!("fail to remove temporary {:?}: {}", tmp, cleanup_err);
+        }
+        return Err(err);
+    }
+    transfer::sync_parent(dest)
+}
+
+fn extract_tar(
+    file: File,
+    format: ArchiveFormat,
+    tmp: &Path,
+    limits: &mut Limits,
+) -> Result<(), Error> {
+    let mut archive = tar::Archive::new(decoder(file, format)?);
+    archive.set_preserve_permissions(false);
+    archive.set_unpack_xattrs(false);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        limits.add_entry()?;
+        let name = entry.path()?.into_owned();
+        let relative = safe_path(&name)?;
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            tar::EntryType::Regular | tar::EntryType::Continuous | tar::EntryType::Directory => {}
+            tar::EntryType::Symlink => {
+                let target = entry.link_name()?.unwrap_or_default().into_owned();
+                check_symlink(&relative, &target)?;
+            }
+            // цель жесткой ссылки задается от корня архива
+            tar::EntryType::Link => {
+                let target = entry.link_name()?.unwrap_or_default().into_owned();
+                safe_path(&target)?;
+            }
+            _ => {
+                warn!("skip {:?}, unsupported entry type {:?}", name, entry_type);
+                continue;
+            }
+        }
+        limits.add_size(entry.size())?;
+        check_parents(tmp, &relative)?;
+        if !entry.unpack_in(tmp)? {
+            return Err(unsafe_entry(&name));
+        }
+    }
+    Ok(())
+}
+
+fn extract_zip(file: File, tmp: &Path, limits: &mut Limits) -> Result<(), Error> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        limits.add_entry()?;
+        let relative = safe_path(Path::new(file.name()))?;
+        check_parents(tmp, &relative)?;
+        let out = tmp.join(&relative);
+        if file.is_dir() {
+            fs::create_dir_all(&out)?;
+            continue;
+        }
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if file.is_symlink() {
+            let mut target = String::new();
+            file.by_ref().take(4096).read_to_string(&mut target)?;
+            check_symlink(&relative, Path::new(&target))?;
+            std::os::unix::fs::symlink(&target, &out)?;
+            continue;
+        }
+        // размер в заголовке zip может врать, поэтому считаются реально распакованные байты
+        let mut out_file = File::create(&out)?;
+        let written = io::copy(
+            &mut file.by_ref().take(limits.remaining() + 1),
+            &mut out_file,
+        )?;
+        limits.add_size(written)?;
+        if let Some(mode) = file.unix_mode() {
+            // без setuid, setgid и sticky bit
+            out_file.set_permissions(fs::Permissions::from_mode(mode & 0o777))?;
+        }
+        if let Some(mtime) = file.last_modified().and_then(zip_mtime) {
+            filetime::set_file_handle_times(&out_file, None, Some(mtime))?;
+        }
+    }
+    Ok(())
+}
+
+fn zip_mtime(time: zip::DateTime) -> Option<filetime::FileTime> {
+    use chrono::TimeZone;
+    let time = chrono::Local
+        .with_ymd_and_hms(
+            time.year().into(),
+            time.month().into(),
+            time.day().into(),
+            time.hour().into(),
+            time.minute().into(),
+            time.second().into(),
+        )
+        .earliest()?;
+    Some(filetime::FileTime::from_unix_time(time.timestamp(), 0))
+}
+
+fn unsafe_entry(name: &Path) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("unsafe path {:?} in archive", name),
+    )
+}
+
+/// Путь записи относительно папки распаковки. Абсолютные пути и `..` не допускаются.
+fn safe_path(name: &Path) -> Result<PathBuf, Error> {
+    let mut relative = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(unsafe_entry(name))
+            }
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        return Err(unsafe_entry(name));
+    }
+    Ok(relative)
+}
+
+/// Цель симлинка должна быть относительной и оставаться внутри папки распаковки
+fn check_symlink(relative: &Path, target: &Path) -> Result<(), Error> {
+    let mut depth = relative.components().count() - 1;
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "symlink {:?} -> {:?} points outside archive",
+                        relative, target
+                    ),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Запись не должна писаться через симлинк, созданный предыдущей записью: цепочка ссылок может
+/// увести за пределы папки распаковки
+fn check_parents(tmp: &Path, relative: &Path) -> Result<(), Error> {
+    let mut path = tmp.to_path_buf();
+    let Some(parent) = relative.parent() else {
+        return Ok(());
+    };
+    for component in parent.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_symlink() => return Err(unsafe_entry(relative)),
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn limits() -> Limits {
+        Limits {
+            max_total_size: 1024,
+            max_entries: 10,
+            total_size: 0,
+            entries: 0,
+        }
+    }
+
+    fn tar_with(path: &Path, entries: &[(&str, tar::EntryType, &str)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for (name, entry_type, content) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*entry_type);
+            header.set_mode(0o644);
+            if *entry_type == tar::EntryType::Symlink {
+                header.set_size(0);
+                // set_path и set_link_name проверяют `..`, поэтому имена пишутся напрямую
+                header.as_old_mut().linkname[..content.len()].copy_from_slice(content.as_bytes());
+            } else {
+                header.set_size(content.len() as u64);
+            }
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            let data = if *entry_type == tar::EntryType::Symlink {
+                ""
+            } else {
+                content
+            };
+            builder.append(&header, data.as_bytes()).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    /// Записывает `size` как распакованный размер первого файла в локальный и центральный
+    /// заголовки zip
+    fn forge_size(data: &mut [u8], size: u32) {
+        for (signature, offset) in [(b"PK\x03\x04", 22), (b"PK\x01\x02", 24)] {
+            let header = data.windows(4).position(|w| w == signature).unwrap();
+            data[header + offset..header + offset + 4].copy_from_slice(&size.to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        let dir = tempfile::tempdir().unwrap();
+        let regular = tar::EntryType::Regular;
+
+        let archive = dir.path().join("ok.tar");
+        tar_with(
+            &archive,
+            &[
+                ("docs/a.txt", regular, "first"),
+                ("docs/link", tar::EntryType::Symlink, "a.txt"),
+            ],
+        );
+        let dest = dir.path().join("ok");
+        extract(&archive, &dest, ArchiveFormat::Tar, limits()).unwrap();
+        assert_eq!(fs::read_to_string(dest.join("docs/link")).unwrap(), "first");
+
+        let big = "x".repeat(2000);
+        let evil = [
+            vec![("../evil.txt", regular, "evil")],
+            vec![("/tmp/evil.txt", regular, "evil")],
+            vec![("link", tar::EntryType::Symlink, "../..")],
+            vec![("big.txt", regular, &big)],
+        ];
+        for (i, entries) in evil.iter().enumerate() {
+            let archive = dir.path().join(format!("evil{}.tar", i));
+            tar_with(&archive, entries);
+            let dest = dir.path().join(format!("evil{}", i));
+            assert!(extract(&archive, &dest, ArchiveFormat::Tar, limits()).is_err());
+            assert!(!dest.exists());
+        }
+        assert!(!dir.path().join("evil.txt").exists());
+
+        // заголовок zip говорит о маленьком файле, но лимит считается по распакованному
+        let archive = dir.path().join("bomb.zip");
+        let mut zip = zip::ZipWriter::new(io::Cursor::new(vec![]));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("zeros", options).unwrap();
+        zip.write_all(&[0; 4096]).unwrap();
+        zip.start_file("../evil.txt", options).unwrap();
+        let mut data = zip.finish().unwrap().into_inner();
+        forge_size(&mut data, 16);
+        fs::write(&archive, &data).unwrap();
+        let forged = zip::ZipArchive::new(File::open(&archive).unwrap()).unwrap();
+        assert_eq!(forged.decompressed_size(), Some(16));
+        let dest = dir.path().join("bomb");
+        let err = extract(&archive, &dest, ArchiveFormat::Zip, limits()).unwrap_err();
+        assert!(err.to_string().contains("exceeds"), "{}", err);
+        let res = extract(
+            &archive,
+            &dest,
+            ArchiveFormat::Zip,
+            Limits {
+                max_total_size: 8192,
+                ..limits()
+            },
+        );
+        assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidData);
+        assert!(!dest.exists());
+        // временные папки удалены
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let name = entry.unwrap().file_name();
+            assert!(!name.to_string_lossy().starts_with('.'), "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,522 @@
+//! Действия с изображениями: уменьшение, конвертация, миниатюры и удаление EXIF. Декодирование и
+//! кодирование тяжелые, поэтому выполняются в пуле блокирующих задач. Перекодированное
+//! изображение поворачивается по EXIF Orientation, а сами метаданные в него не переносятся.
+//! Файлы пишутся через временный файл, так что при ошибке исходник остается целым.
+
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageReader};
+use img_parts::{Bytes, DynImage, ImageEXIF};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind};
+use std::path::Path;
+use tracing::{info, trace, warn};
+
+use super::pipeline::Applied;
+use super::template::{PathTemplate, TemplateContext};
+use super::transfer;
+use super::{prepare_dir, resolve_conflict, validate_template_dir, ConflictPolicy};
+use crate::validation::{pointer_child, Problem, Validate};
+
+/// Форматы, в которые можно записать изображение
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Gif,
+    Bmp,
+    Tiff,
+}
+
+impl ImageFormat {
+    fn codec(self) -> image::ImageFormat {
+        match self {
+            ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+            ImageFormat::Png => image::ImageFormat::Png,
+            ImageFormat::Webp => image::ImageFormat::WebP,
+            ImageFormat::Gif => image::ImageFormat::Gif,
+            ImageFormat::Bmp => image::ImageFormat::Bmp,
+            ImageFormat::Tiff => image::ImageFormat::Tiff,
+        }
+    }
+
+    fn from_codec(format: image::ImageFormat) -> Option<Self> {
+        [
+            ImageFormat::Jpeg,
+            ImageFormat::Png,
+            ImageFormat::Webp,
+            ImageFormat::Gif,
+            ImageFormat::Bmp,
+            ImageFormat::Tiff,
+        ]
+        .into_iter()
+        .find(|supported| supported.codec() == format)
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Gif => "gif",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Tiff => "tif",
+        }
+    }
+}
+
+fn default_quality() -> u8 {
+    90
+}
+
+fn default_thumbnail_size() -> u32 {
+    256
+}
+
+/// Уменьшает изображение на месте, чтобы оно помещалось в `max_width` x `max_height` с
+/// сохранением пропорций. Изображения меньше заданного размера не увеличиваются.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResizeImageAction {
+    #[serde(default)]
+    max_width: Option<u32>,
+    #[serde(default)]
+    max_height: Option<u32>,
+    // Качество JPEG от 1 до 100, для остальных форматов не используется
+    #[serde(default = "default_quality")]
+    quality: u8,
+}
+
+impl ResizeImageAction {
+    pub async fn execute(&self, path: &Path, dry_run: bool) -> Result<Option<Applied>, Error> {
+        let (max_width, max_height) = (
+            self.max_width.unwrap_or(u32::MAX),
+            self.max_height.unwrap_or(u32::MAX),
+        );
+        let quality = self.quality;
+        let src = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let (width, height) = ImageReader::open(&src)?
+                .with_guessed_format()?
+                .into_dimensions()
+                .map_err(image_error)?;
+            if width <= max_width && height <= max_height {
+                trace!("{:?} {}x{} already fits", src, width, height);
+                return Ok(None);
+            }
+            if dry_run {
+                info!(
+                    "[dry-run] resize {:?} {}x{} to fit {}x{}",
+                    src, width, height, max_width, max_height
+                );
+                return Ok(Some(Applied::Modified { path: src }));
+            }
+            let (img, format) = open(&src)?;
+            let img = img.resize(max_width, max_height, image::imageops::FilterType::Lanczos3);
+            trace!("resized {:?} to {}x{}", src, img.width(), img.height());
+            save(&img, &src, &src, format, quality)?;
+            Ok(Some(Applied::Modified { path: src }))
+        })
+        .await?
+    }
+}
+
+impl Validate for ResizeImageAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if self.max_width.is_none() && self.max_height.is_none() {
+            problems.push(Problem::error(
+                pointer,
+                "set at least one of `max_width` and `max_height`",
+            ));
+        }
+        for (field, size) in [
+            ("max_width", self.max_width),
+            ("max_height", self.max_height),
+        ] {
+            if size == Some(0) {
+                problems.push(Problem::error(
+                    pointer_child(pointer, field),
+                    "size must be positive",
+                ));
+            }
+        }
+        validate_quality(self.quality, pointer, problems);
+    }
+}
+
+/// Перекодирует изображение в другой формат и кладет рядом с исходником с новым расширением,
+/// например `photo.png` -> `photo.webp`. Следующие шаги цепочки работают с новым файлом.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConvertImageAction {
+    format: ImageFormat,
+    #[serde(default = "default_quality")]
+    quality: u8,
+    // Оставить исходный файл, по умолчанию он удаляется после записи нового
+    #[serde(default)]
+    keep_original: bool,
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+}
+
+impl ConvertImageAction {
+    pub async fn execute(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext<'_>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        let dest = src.with_extension(self.format.extension());
+        let in_place = dest == src;
+        let dest = if in_place {
+            dest
+        } else {
+            let Some(dest) = resolve_conflict(src, &dest, self.on_conflict, ctx, dry_run).await?
+            else {
+                return Ok(None);
+            };
+            dest
+        };
+        let applied = if in_place {
+            Applied::Modified {
+                path: src.to_path_buf(),
+            }
+        } else {
+            Applied::Converted {
+                from: src.to_path_buf(),
+                to: dest.clone(),
+                source_deleted: !self.keep_original,
+            }
+        };
+        if dry_run {
+            info!("[dry-run] convert {:?} to {:?}", src, dest);
+            return Ok(Some(applied));
+        }
+
+        let (src, format, quality) = (src.to_path_buf(), self.format.codec(), self.quality);
+        let remove_source = !self.keep_original && !in_place;
+        tokio::task::spawn_blocking(move || {
+            let (img, _) = open(&src)?;
+            save(&img, &src, &dest, format, quality)?;
+            trace!("converted {:?} to {:?}", src, dest);
+            if remove_source {
+                fs::remove_file(&src)?;
+            }
+            Ok::<_, Error>(())
+        })
+        .await??;
+        Ok(Some(applied))
+    }
+}
+
+impl Validate for ConvertImageAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        validate_quality(self.quality, pointer, problems);
+    }
+}
+
+/// Создает уменьшенную копию изображения в папке `destination` под тем же именем. Формат по
+/// умолчанию как у исходника.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ThumbnailAction {
+    destination: PathTemplate,
+    // Максимальная сторона миниатюры в пикселях
+    #[serde(default = "default_thumbnail_size")]
+    size: u32,
+    #[serde(default)]
+    format: Option<ImageFormat>,
+    #[serde(default = "default_quality")]
+    quality: u8,
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+}
+
+impl ThumbnailAction {
+    pub fn templates(&self) -> Vec<&PathTemplate> {
+        vec![&self.destination]
+    }
+
+    pub async fn execute(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext<'_>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        let format = match self.format {
+            Some(format) => format,
+            None => source_format(src).await?,
+        };
+        let file_name = src.file_stem().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("fail to make thumbnail, invalid filename {:?}", src),
+            )
+        })?;
+        let dest = prepare_dir(&self.destination, ctx, dry_run)
+            .await?
+            .join(file_name)
+            .with_extension(format.extension());
+        let Some(dest) = resolve_conflict(src, &dest, self.on_conflict, ctx, dry_run).await? else {
+            return Ok(None);
+        };
+        let applied = Applied::Copied { to: dest.clone() };
+        if dry_run {
+            info!("[dry-run] make thumbnail of {:?} at {:?}", src, dest);
+            return Ok(Some(applied));
+        }
+
+        let (src, size, quality) = (src.to_path_buf(), self.size, self.quality);
+        tokio::task::spawn_blocking(move || {
+            let (img, _) = open(&src)?;
+            let thumbnail = img.thumbnail(size, size);
+            save(&thumbnail, &dest, &dest, format.codec(), quality)
+        })
+        .await??;
+        Ok(Some(applied))
+    }
+}
+
+impl Validate for ThumbnailAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        validate_template_dir(
+            &self.destination,
+            &pointer_child(pointer, "destination"),
+            problems,
+        );
+        if self.size == 0 {
+            problems.push(Problem::error(
+                pointer_child(pointer, "size"),
+                "size must be positive",
+            ));
+        }
+        validate_quality(self.quality, pointer, problems);
+    }
+}
+
+/// Удаляет EXIF из JPEG, PNG и WebP без перекодирования. Вместе с EXIF пропадает и Orientation,
+/// поэтому повернутые снимки лучше сначала пропустить через `resize_image` или `convert_image`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StripExifAction {}
+
+impl StripExifAction {
+    pub async fn execute(&self, path: &Path, dry_run: bool) -> Result<Option<Applied>, Error> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let data = fs::read(&path)?;
+            let mut img = DynImage::from_bytes(Bytes::from(data))
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::Unsupported,
+                        format!("{:?} is not a JPEG, PNG or WebP image", path),
+                    )
+                })?;
+            if img.exif().is_none() {
+                trace!("{:?} has no EXIF", path);
+                return Ok(None);
+            }
+            if dry_run {
+                info!("[dry-run] strip EXIF from {:?}", path);
+                return Ok(Some(Applied::Modified { path }));
+            }
+            img.set_exif(None);
+            replace_file(&path, &path, |out| {
+                img.encoder().write_to(out)?;
+                Ok(())
+            })?;
+            trace!("stripped EXIF from {:?}", path);
+            Ok(Some(Applied::Modified { path }))
+        })
+        .await?
+    }
+}
+
+fn validate_quality(quality: u8, pointer: &str, problems: &mut Vec<Problem>) {
+    if !(1..=100).contains(&quality) {
+        problems.push(Problem::error(
+            pointer_child(pointer, "quality"),
+            "quality must be from 1 to 100",
+        ));
+    }
+}
+
+fn image_error(err: ImageError) -> Error {
+    match err {
+        ImageError::IoError(err) => err,
+        ImageError::Unsupported(err) => Error::new(ErrorKind::Unsupported, err),
+        err => Error::new(ErrorKind::InvalidData, err),
+    }
+}
+
+/// Формат исходника для записи миниатюры, нечитаемые форматы заменяются на PNG
+async fn source_format(src: &Path) -> Result<ImageFormat, Error> {
+    let src = src.to_path_buf();
+    let format = tokio::task::spawn_blocking(move || {
+        Ok::<_, Error>(ImageReader::open(&src)?.with_guessed_format()?.format())
+    })
+    .await??;
+    Ok(format
+        .and_then(ImageFormat::from_codec)
+        .unwrap_or(ImageFormat::Png))
+}
+
+/// Декодирует изображение и поворачивает его по EXIF Orientation
+fn open(path: &Path) -> Result<(DynamicImage, image::ImageFormat), Error> {
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    let format = reader.format().ok_or_else(|| {
+        Error::new(
+            ErrorKind::Unsupported,
+            format!("{:?} is not a supported image", path),
+        )
+    })?;
+    let mut decoder = reader.into_decoder().map_err(image_error)?;
+    let orientation = decoder.orientation().map_err(image_error)?;
+    let mut img = DynamicImage::from_decoder(decoder).map_err(image_error)?;
+    img.apply_orientation(orientation);
+    Ok((img, format))
+}
+
+/// Кодирует изображение в `dest`. Права берутся у `src`, если это разные файлы, а `src` есть.
+fn save(
+    img: &DynamicImage,
+    src: &Path,
+    dest: &Path,
+    format: image::ImageFormat,
+    quality: u8,
+) -> Result<(), Error> {
+    replace_file(src, dest, |out| {
+        match format {
+            // в JPEG нет прозрачности
+            image::ImageFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(out, quality)),
+            image::ImageFormat::WebP => DynamicImage::ImageRgba8(img.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(out)),
+            image::ImageFormat::Gif | image::ImageFormat::Bmp => {
+                DynamicImage::ImageRgba8(img.to_rgba8()).write_to(out, format)
+            }
+            format => img.write_to(out, format),
+        }
+        .map_err(image_error)
+    })
+}
+
+/// Пишет `dest` через временный файл рядом с ним с правами `src`
+fn replace_file(
+    src: &Path,
+    dest: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let tmp = transfer::tmp_path(dest)?;
+    let res = (|| {
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        write(&mut out)?;
+        let file = out.into_inner().map_err(|err| err.into_error())?;
+        if let Ok(metadata) = fs::metadata(src) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, dest)
+    })();
+    if let Err(err) = res {
+        if let Err(cleanup_err) = fs::remove_file(&tmp) {
+            if cleanup_err.kind() != ErrorKind::NotFound {
+                warn!("fail to remove temporary {:?}: {}", tmp, cleanup_err);
+            }
+        }
+        return Err(err);
+    }
+    transfer::sync_parent(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::conditions::CheckArgs;
+    use std::path::PathBuf;
+
+    /// Полупрозрачный PNG 400x200
+    fn photo(dir: &Path) -> PathBuf {
+        let src = dir.join("photo.png");
+        image::RgbaImage::from_pixel(400, 200, image::Rgba([200, 10, 10, 128]))
+            .save(&src)
+            .unwrap();
+        src
+    }
+
+    #[tokio::test]
+    async fn test_resize() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = photo(dir.path());
+        let resize: ResizeImageAction =
+            serde_json::from_value(serde_json::json!({"max_width": 100})).unwrap();
+        assert!(resize.execute(&src, false).await.unwrap().is_some());
+        assert_eq!(image::image_dimensions(&src).unwrap(), (100, 50));
+        // уже помещается
+        assert!(resize.execute(&src, false).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_thumbnail() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = photo(dir.path());
+        let args = CheckArgs::for_path(&src);
+        let thumbnail: ThumbnailAction = serde_json::from_value(serde_json::json!({
+            "destination": dir.path().join("thumbs"),
+            "size": 20,
+            "format": "webp",
+        }))
+        .unwrap();
+        thumbnail
+            .execute(&src, &TemplateContext::for_args(&args), false)
+            .await
+            .unwrap();
+        let thumb = dir.path().join("thumbs/photo.webp");
+        assert_eq!(image::image_dimensions(&thumb).unwrap(), (20, 10));
+    }
+
+    #[tokio::test]
+    async fn test_convert() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = photo(dir.path());
+        let convert: ConvertImageAction =
+            serde_json::from_value(serde_json::json!({"format": "jpeg"})).unwrap();
+        let args = CheckArgs::for_path(&src);
+        let applied = convert
+            .execute(&src, &TemplateContext::for_args(&args), false)
+            .await
+            .unwrap()
+            .unwrap();
+        let jpeg = dir.path().join("photo.jpg");
+        assert_eq!(applied.path_after(&src), Some(jpeg.clone()));
+        assert!(!src.exists());
+        assert_eq!(
+            ImageReader::open(&jpeg).unwrap().format(),
+            Some(image::ImageFormat::Jpeg)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strip_exif() {
+        let dir = tempfile::tempdir().unwrap();
+        let jpeg = dir.path().join("photo.jpg");
+        image::RgbImage::new(40, 20).save(&jpeg).unwrap();
+        let mut with_exif = DynImage::from_bytes(fs::read(&jpeg).unwrap().into())
+            .unwrap()
+            .unwrap();
+        with_exif.set_exif(Some(Bytes::from_static(b"MM\0*\0\0\0\x08\0\0")));
+        with_exif
+            .encoder()
+            .write_to(File::create(&jpeg).unwrap())
+            .unwrap();
+
+        let strip = StripExifAction::default();
+        assert!(strip.execute(&jpeg, false).await.unwrap().is_some());
+        let stripped = DynImage::from_bytes(fs::read(&jpeg).unwrap().into())
+            .unwrap()
+            .unwrap();
+        assert!(stripped.exif().is_none());
+        assert!(strip.execute(&jpeg, false).await.unwrap().is_none());
+        assert!(strip.execute(dir.path(), false).await.is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tracing::{info, trace};
+
+use super::pipeline::Applied;
+use super::template::{PathTemplate, TemplateContext};
+use super::transfer;
+use super::{prepare_dir, resolve_conflict, validate_template_dir, ConflictPolicy};
+use crate::validation::{pointer_child, Problem, Validate};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateSymlinkAction {
+    to: PathTemplate, // Папка, в которой создается ссылка
+    // Имя ссылки, по умолчанию совпадает с именем файла
+    #[serde(default)]
+    name: Option<PathTemplate>,
+    #[serde(default)]
+    kind: LinkKind,
+    #[serde(default)]
+    target: LinkTarget,
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+    // Заменять существующий файл независимо от on_conflict
+    #[serde(default)]
+    replace_existing: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    #[default]
+    Symbolic,
+    /// Жесткая ссылка, работает только для файлов в пределах одной файловой системы
+    Hard,
+}
+
+/// Куда указывает символическая ссылка
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkTarget {
+    /// Абсолютный путь до файла
+    #[default]
+    Absolute,
+    /// Путь относительно папки со ссылкой, переживает перенос общей родительской папки
+    Relative,
+}
+
+impl CreateSymlinkAction {
+    pub fn templates(&self) -> Vec<&PathTemplate> {
+        [&self.to].into_iter().chain(&self.name).collect()
+    }
+
+    pub async fn execute(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext<'_>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        let dir = prepare_dir(&self.to, ctx, dry_run).await?;
+        let link = match &self.name {
+            Some(name) => dir.join(name.render(ctx)?),
+            None => dir.join(src.file_name().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("fail to create link, invalid filename {:?}", src),
+                )
+            })?),
+        };
+        // до разрешения конфликта, иначе существующий файл зря ушел бы в корзину
+        if self.kind == LinkKind::Hard && fs::symlink_metadata(src).await?.is_dir() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("fail to hard link {:?}, it is a directory", src),
+            ));
+        }
+        let Some(link) = resolve_conflict(src, &link, self.policy(), ctx, dry_run).await? else {
+            return Ok(None);
+        };
+        let applied = Applied::Linked { link: link.clone() };
+
+        match self.kind {
+            LinkKind::Symbolic => {
+                let target = match self.target {
+                    LinkTarget::Absolute => real_path(src).await?,
+                    LinkTarget::Relative => {
+                        let link_dir = real_path(&link).await?;
+                        let link_dir = link_dir.parent().unwrap_or(Path::new("/"));
+                        relative_path(link_dir, &real_path(src).await?)
+                    }
+                };
+                if dry_run {
+                    info!(
+                        "[dry-run] create symlink {:?} pointing to {:?}",
+                        link, target
+                    );
+                    return Ok(Some(applied));
+                }
+                trace!("Creating symlink {:?} pointing to {:?}", link, target);
+                tokio::task::spawn_blocking(move || {
+                    transfer::create_replacing(&link, |tmp| {
+                        std::os::unix::fs::symlink(&target, tmp)
+                    })
+                })
+                .await??;
+                Ok(Some(applied))
+            }
+            LinkKind::Hard => {
+                if dry_run {
+                    info!("[dry-run] create hard link {:?} to {:?}", link, src);
+                    return Ok(Some(applied));
+                }
+                trace!("Creating hard link {:?} to {:?}", link, src);
+                let src = src.to_path_buf();
+                tokio::task::spawn_blocking(move || {
+                    transfer::create_replacing(&link, |tmp| std::fs::hard_link(&src, tmp))
+                })
+                .await??;
+                Ok(Some(applied))
+            }
+        }
+    }
+
+    fn policy(&self) -> ConflictPolicy {
+        if self.replace_existing {
+            ConflictPolicy::Overwrite
+        } else {
+            self.on_conflict
+        }
+    }
+}
+
+impl Validate for CreateSymlinkAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        validate_template_dir(&self.to, &pointer_child(pointer, "to"), problems);
+        if let Some(name) = &self.name {
+            name.validate(&pointer_child(pointer, "name"), problems);
+        }
+        if self.kind == LinkKind::Hard && self.target == LinkTarget::Relative {
+            problems.push(Problem::warning(
+                pointer_child(pointer, "target"),
+                "target is ignored for hard links",
+            ));
+        }
+        if self.replace_existing && self.on_conflict != ConflictPolicy::default() {
+            problems.push(Problem::warning(
+                pointer_child(pointer, "on_conflict"),
+                "on_conflict is ignored because replace_existing is set",
+            ));
+        }
+    }
+}
+
+/// Абсолютный путь с раскрытыми симлинками в родительских папках. Сам файл может еще не
+/// существовать (путь будущей ссылки), поэтому раскрывается только папка.
+async fn real_path(path: &Path) -> Result<PathBuf, Error> {
+    let path = std::path::absolute(path)?;
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Ok(path);
+    };
+    match fs::canonicalize(parent).await {
+        Ok(parent) => Ok(parent.join(file_name)),
+        // в dry-run папки назначения может еще не быть
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(path),
+        Err(err) => Err(err),
+    }
+}
+
+/// Путь до `to` относительно папки `from_dir`, оба пути должны быть абсолютными
+fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from_dir.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut out = PathBuf::new();
+    for _ in common..from.len() {
+        out.push("..");
+    }
+    for component in &to[common..] {
+        out.push(component);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::conditions::CheckArgs;
+    use std::os::unix::fs::MetadataExt;
+
+    fn action(json: serde_json::Value) -> CreateSymlinkAction {
+        serde_json::from_value(json).unwrap()
+    }
+
+    async fn run(action: &CreateSymlinkAction, src: &Path) -> Result<Option<Applied>, Error> {
+        let args = CheckArgs::for_path(src);
+        action
+            .execute(src, &TemplateContext::for_args(&args), false)
+            .await
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(
+            relative_path(Path::new("/a/b/links"), Path::new("/a/src/file.txt")),
+            Path::new("../../src/file.txt")
+        );
+        assert_eq!(
+            relative_path(Path::new("/a"), Path::new("/a/file.txt")),
+            Path::new("file.txt")
+        );
+    }
+
+    /// Файл `src/photo.jpg` в канонической временной папке, чтобы сравнивать пути ссылок
+    fn photo(dir: &Path) -> (PathBuf, PathBuf) {
+        let root = dir.canonicalize().unwrap();
+        let src = root.join("src/photo.jpg");
+        std::fs::create_dir_all(src.parent().unwrap()).unwrap();
+        std::fs::write(&src, "content").unwrap();
+        (root, src)
+    }
+
+    #[tokio::test]
+    async fn test_absolute_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        // недостающая папка `to` создается
+        let links = root.join("links/abs");
+        run(&action(serde_json::json!({ "to": links })), &src)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_link(links.join("photo.jpg")).unwrap(), src);
+    }
+
+    #[tokio::test]
+    async fn test_relative_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        let relative = action(serde_json::json!({
+            "to": root.join("links/rel"),
+            "name": "{stem}-link.{ext}",
+            "target": "relative",
+        }));
+        run(&relative, &src).await.unwrap();
+        let link = root.join("links/rel/photo-link.jpg");
+        assert_eq!(
+            std::fs::read_link(&link).unwrap(),
+            Path::new("../../src/photo.jpg")
+        );
+        assert_eq!(std::fs::read_to_string(&link).unwrap(), "content");
+    }
+
+    #[tokio::test]
+    async fn test_hard_link() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        let hard = action(serde_json::json!({"to": root.join("links"), "kind": "hard"}));
+        run(&hard, &src).await.unwrap();
+        // указывает на тот же inode
+        let link = root.join("links/photo.jpg");
+        assert!(!std::fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(
+            std::fs::metadata(&link).unwrap().ino(),
+            std::fs::metadata(&src).unwrap().ino()
+        );
+        // папку нельзя, файл с тем же именем не трогается
+        std::fs::write(root.join("links/src"), "old").unwrap();
+        let replace = action(serde_json::json!({
+            "to": root.join("links"),
+            "kind": "hard",
+            "replace_existing": true,
+        }));
+        assert!(run(&replace, &root.join("src")).await.is_err());
+        assert_eq!(
+            std::fs::read_to_string(root.join("links/src")).unwrap(),
+            "old"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replace_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        let existing = root.join("links/photo.jpg");
+        std::fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        std::fs::write(&existing, "old").unwrap();
+        // заменяет файл даже при on_conflict = fail
+        let fail = serde_json::json!({"to": root.join("links"), "on_conflict": "fail"});
+        assert!(run(&action(fail.clone()), &src).await.is_err());
+        let mut replace = fail;
+        replace["replace_existing"] = true.into();
+        run(&action(replace), &src).await.unwrap();
+        assert_eq!(std::fs::read_link(&existing).unwrap(), src);
+    }
+}
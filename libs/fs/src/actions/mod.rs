@@ -1,44 +1,228 @@
+mod archive;
 mod conditions;
+mod conflict;
+mod copy;
+mod dedupe;
+mod extract;
+mod images;
+mod link;
 mod matcher;
+mod pipeline;
+mod rename;
+mod template;
+mod transfer;
+mod trash;
 
+pub use archive::ArchiveAction;
 use notify::{Event, EventKind};
 use serde::{Deserialize, Serialize};
-use std::io::Error;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::{fs, io::AsyncReadExt, process::Command};
-use tracing::{error, trace};
+use tracing::{error, info, trace};
 
 use conditions::{CheckArgs, ConditionChecker, ConditionOrConditionsGroup};
+pub use conflict::ConflictPolicy;
+use conflict::Resolution;
+pub use copy::CopyFileAction;
+pub use dedupe::DedupeAction;
+pub use extract::ExtractAction;
+pub use images::{
+    ConvertImageAction, ImageFormat, ResizeImageAction, StripExifAction, ThumbnailAction,
+};
+pub use link::CreateSymlinkAction;
+pub use pipeline::{Applied, OnError, Step};
+pub use rename::RenameAction;
+pub use template::PathTemplate;
+use template::TemplateContext;
+pub use transfer::is_tmp_path;
+pub use trash::{TrashAction, Trashed};
+
+use crate::content_index::ContentIndex;
+use crate::journal::Journal;
+use crate::validation::{pointer_child, Problem, Validate};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Action {
+    // Имя правила, по нему можно отменить все его операции через `TriggerFS undo --rule`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
     triggers: Vec<EventKind>, // События файловой системы, на которые реагирует действие
     conditions: ConditionOrConditionsGroup, // Условия для выполнения действия
-    action_type: ActionType,  // Тип действия
+    // Одно действие, короткая запись для `steps` из одного шага
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    action_type: Option<ActionType>,
+    // Цепочка действий, выполняются по очереди над одним файлом
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    steps: Vec<Step>,
+    #[serde(default)]
+    dry_run: bool, // Только залогировать что будет сделано, не трогая файловую систему
+}
+
+/// Параметры выполнения действий, общие для всех правил
+#[derive(Debug, Clone, Default)]
+pub struct ExecOptions {
+    /// Глобальный режим dry-run, включает его для всех действий
+    pub dry_run: bool,
+    /// Журнал операций. Если он включен, то `delete_file` и замена файла при конфликте имен
+    /// перемещают файлы в корзину, чтобы удаление можно было отменить.
+    pub journal: Option<Arc<Journal>>,
+    /// Папка из правила, по которому пришло событие
+    pub watch_root: Option<PathBuf>,
+    /// Индекс содержимого для поиска копий, без него используется индекс в памяти
+    pub content_index: Option<Arc<ContentIndex>>,
+    /// Корзина для файлов с раздела домашней папки, по умолчанию `$XDG_DATA_HOME/Trash`
+    pub trash_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 pub enum ActionType {
     MoveFile(MoveFileAction),
+    CopyFile(CopyFileAction),
     DeleteFile(DeleteFileAction),
+    Trash(TrashAction),
+    Rename(RenameAction),
+    Archive(ArchiveAction),
+    Extract(ExtractAction),
+    ResizeImage(ResizeImageAction),
+    ConvertImage(ConvertImageAction),
+    Thumbnail(ThumbnailAction),
+    StripExif(StripExifAction),
     CreateSymlink(CreateSymlinkAction),
+    Dedupe(DedupeAction),
     Custom(CustomAction),
 }
 
+impl Validate for Action {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if self.triggers.is_empty() {
+            problems.push(Problem::warning(
+                pointer_child(pointer, "triggers"),
+                "no triggers, action will never run",
+            ));
+        }
+        self.conditions
+            .validate(&pointer_child(pointer, "conditions"), problems);
+        match (&self.action_type, self.steps.is_empty()) {
+            (Some(action_type), true) => {
+                action_type.validate(&pointer_child(pointer, "action_type"), problems)
+            }
+            (None, false) => {
+                pipeline::validate_steps(&self.steps, &pointer_child(pointer, "steps"), problems)
+            }
+            (Some(_), false) => problems.push(Problem::error(
+                pointer,
+                "`action_type` and `steps` can not be used together",
+            )),
+            (None, true) => problems.push(Problem::error(
+                pointer,
+                "either `action_type` or `steps` is required",
+            )),
+        }
+    }
+}
+
+impl Validate for ActionType {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        match self {
+            ActionType::MoveFile(move_file_action) => {
+                let pointer = pointer_child(&pointer_child(pointer, "move_file"), "destination");
+                validate_template_dir(&move_file_action.destination, &pointer, problems);
+            }
+            ActionType::CopyFile(copy_file_action) => {
+                copy_file_action.validate(&pointer_child(pointer, "copy_file"), problems);
+            }
+            ActionType::DeleteFile(_) | ActionType::Trash(_) => {}
+            ActionType::Rename(rename_action) => {
+                rename_action.validate(&pointer_child(pointer, "rename"), problems);
+            }
+            ActionType::Archive(archive_action) => {
+                archive_action.validate(&pointer_child(pointer, "archive"), problems);
+            }
+            ActionType::Extract(extract_action) => {
+                extract_action.validate(&pointer_child(pointer, "extract"), problems);
+            }
+            ActionType::ResizeImage(resize_action) => {
+                resize_action.validate(&pointer_child(pointer, "resize_image"), problems);
+            }
+            ActionType::ConvertImage(convert_action) => {
+                convert_action.validate(&pointer_child(pointer, "convert_image"), problems);
+            }
+            ActionType::Thumbnail(thumbnail_action) => {
+                thumbnail_action.validate(&pointer_child(pointer, "thumbnail"), problems);
+            }
+            ActionType::StripExif(_) => {}
+            ActionType::CreateSymlink(create_symlink_action) => {
+                create_symlink_action.validate(&pointer_child(pointer, "create_symlink"), problems);
+            }
+            ActionType::Dedupe(dedupe_action) => {
+                dedupe_action.validate(&pointer_child(pointer, "dedupe"), problems);
+            }
+            ActionType::Custom(custom_action) => {
+                if custom_action.command.trim().is_empty() {
+                    let pointer = pointer_child(&pointer_child(pointer, "custom"), "command");
+                    problems.push(Problem::error(pointer, "command is empty"));
+                }
+            }
+        }
+    }
+}
+
+/// Папки назначения создаются при выполнении, поэтому заранее проверяем только неизменяемую
+/// часть шаблона: если там файл, то действие точно не сработает
+fn validate_template_dir(template: &PathTemplate, pointer: &str, problems: &mut Vec<Problem>) {
+    template.validate(pointer, problems);
+    if template.has_counter() {
+        problems.push(Problem::error(
+            pointer,
+            "`{counter}` is only available in rename",
+        ));
+    }
+    let path = template.static_prefix();
+    if path.as_os_str().is_empty() {
+        return;
+    }
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => problems.push(Problem::error(
+            pointer,
+            format!("{:?} is not a directory", path),
+        )),
+        Err(err) if err.kind() == ErrorKind::NotFound => problems.push(Problem::warning(
+            pointer,
+            format!("{:?} does not exist, it will be created", path),
+        )),
+        Err(err) => problems.push(Problem::warning(
+            pointer,
+            format!("{:?} is not accessible: {}", path, err),
+        )),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MoveFileAction {
-    destination: PathBuf,
+    destination: PathTemplate, // Папка назначения, может содержать подстановки
+    #[serde(default)]
+    on_conflict: ConflictPolicy, // Что делать если файл с таким именем уже есть
+    // При перемещении на другую файловую систему сверять хеш копии, а не только размер
+    #[serde(default)]
+    verify_checksum: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DeleteFileAction {
+    // Удалять безвозвратно, даже если включен журнал и удаления идут через корзину
+    #[serde(default)]
+    permanent: bool,
+    // Не считать ошибкой, если файла уже нет
+    #[serde(default)]
     force: bool,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct CreateSymlinkAction {
-    to: PathBuf,
+    // Удалять папки вместе с содержимым
+    #[serde(default)]
+    recursive: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -46,8 +230,19 @@ pub struct CustomAction {
     command: String,
 }
 impl CustomAction {
-    pub async fn execute_command(&self, path: &Path) -> Result<(), Error> {
+    pub async fn execute_command(
+        &self,
+        path: &Path,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
         let command_with_path = self.command.replace("{}", &path.to_string_lossy());
+        let applied = Applied::Command {
+            command: command_with_path.clone(),
+        };
+        if dry_run {
+            info!("[dry-run] run command `sh -c {:?}`", command_with_path);
+            return Ok(Some(applied));
+        }
         let output = Command::new("sh")
             .arg("-c")
             .arg(command_with_path)
@@ -62,13 +257,35 @@ impl CustomAction {
             );
         }
 
-        Ok(())
+        Ok(Some(applied))
     }
 }
 
 // Пример использования
 impl Action {
-    pub async fn execute(&self, event: &Event) -> Result<(), Error> {
+    fn steps(&self) -> Vec<(&ActionType, OnError)> {
+        match &self.action_type {
+            Some(action_type) => vec![(action_type, OnError::Abort)],
+            None => self.steps.iter().map(Step::action).collect(),
+        }
+    }
+
+    /// Группы из регулярок собираются, только если их ждет какой-то шаблон
+    fn uses_captures(&self) -> bool {
+        self.steps().iter().any(|(action_type, _)| {
+            action_type
+                .templates()
+                .into_iter()
+                .any(PathTemplate::has_captures)
+        })
+    }
+
+    pub async fn execute(&self, event: &Event, options: &ExecOptions) -> Result<(), Error> {
+        let dry_run = options.dry_run || self.dry_run;
+        let options = ExecOptions {
+            dry_run,
+            ..options.clone()
+        };
         trace!("start check event");
         // Проверка, соответствует ли событие триггеру
         if self
@@ -81,9 +298,15 @@ impl Action {
                 trace!("check path {:#?}", path);
                 let metadata = tokio::fs::metadata(&path).await?;
                 // Проверка всех условий
-                let mut file = fs::File::open(path).await?;
                 let mut buffer = vec![0; 512];
-                let _ = file.read(&mut buffer).await?;
+                // у папок содержимого нет, тип определяем только для файлов
+                if metadata.is_file() {
+                    let mut file = fs::File::open(path).await?;
+                    let n = file.read(&mut buffer).await?;
+                    buffer.truncate(n);
+                } else {
+                    buffer.clear();
+                }
                 trace!("read buf len: {:#?}", buffer.len());
                 let inf = infer::get(&buffer);
                 trace!("inf: {:#?}", inf);
@@ -92,26 +315,28 @@ impl Action {
                 let args = CheckArgs {
                     file_metadata: metadata,
                     file_type: matcher_type,
+                    file_kind: inf,
                     file_path: path.to_owned(),
+                    watch_root: options.watch_root.clone(),
+                    content_index: options.content_index.clone(),
+                    details: Default::default(),
                 };
                 if self.conditions.check(&args) {
                     // if self.conditions.iter().all(|cond| cond.check(&args)) {
-                    match &self.action_type {
-                        ActionType::MoveFile(move_file_action) => {
-                            move_file(path, &move_file_action.destination).await?;
-                        }
-                        ActionType::DeleteFile(delete_file_action) => {
-                            trace!("Deleting file with force: {}", delete_file_action.force);
-                            remove_file(path).await?;
-                        }
-                        ActionType::CreateSymlink(create_symlink_action) => {
-                            create_symlink(path, &create_symlink_action.to).await?;
-                        }
-                        ActionType::Custom(custom_action) => {
-                            trace!("run custom command {} ", &custom_action.command);
-                            custom_action.execute_command(path).await?;
-                        }
+                    if dry_run {
+                        info!(
+                            "[dry-run] {:?} matched conditions {:?}",
+                            path,
+                            self.conditions.matched(&args)
+                        );
                     }
+                    let captures = if self.uses_captures() {
+                        self.conditions.captures(&args)
+                    } else {
+                        HashMap::new()
+                    };
+                    let rule = self.name.as_deref();
+                    pipeline::run(&self.steps(), args, captures, rule, &options).await?;
                 }
             }
         }
@@ -119,39 +344,254 @@ impl Action {
     }
 }
 
-async fn move_file(src: &Path, dst: &Path) -> Result<(), Error> {
-    //TODO: возможно стоит сделать проверку на то что файл с таким именем существует, и мб
-    //переименовать его как то
+impl ActionType {
+    /// Шаблоны путей действия
+    fn templates(&self) -> Vec<&PathTemplate> {
+        match self {
+            ActionType::MoveFile(move_file_action) => vec![&move_file_action.destination],
+            ActionType::CopyFile(copy_file_action) => copy_file_action.templates(),
+            ActionType::Rename(rename_action) => rename_action.templates(),
+            ActionType::Archive(archive_action) => archive_action.templates(),
+            ActionType::Extract(extract_action) => extract_action.templates(),
+            ActionType::Thumbnail(thumbnail_action) => thumbnail_action.templates(),
+            ActionType::CreateSymlink(create_symlink_action) => create_symlink_action.templates(),
+            ActionType::DeleteFile(_)
+            | ActionType::Trash(_)
+            | ActionType::ResizeImage(_)
+            | ActionType::ConvertImage(_)
+            | ActionType::StripExif(_)
+            | ActionType::Dedupe(_)
+            | ActionType::Custom(_) => vec![],
+        }
+    }
+
+    async fn execute(
+        &self,
+        path: &Path,
+        ctx: &TemplateContext<'_>,
+        options: &ExecOptions,
+    ) -> Result<Option<Applied>, Error> {
+        let dry_run = options.dry_run;
+        match self {
+            ActionType::MoveFile(move_file_action) => {
+                move_file(path, move_file_action, ctx, dry_run).await
+            }
+            ActionType::CopyFile(copy_file_action) => {
+                copy_file_action.execute(path, ctx, dry_run).await
+            }
+            ActionType::DeleteFile(delete_file_action) => {
+                trace!("Deleting file with force: {}", delete_file_action.force);
+                let trash = options.journal.is_some() && !delete_file_action.permanent;
+                let trash_dir = options.trash_dir.as_deref();
+                remove_file(path, delete_file_action, trash, trash_dir, dry_run).await
+            }
+            ActionType::Trash(trash_action) => {
+                let trash_dir = options.trash_dir.as_deref();
+                trash_action.execute(path, trash_dir, dry_run).await
+            }
+            ActionType::Rename(rename_action) => rename_action.execute(path, ctx, dry_run).await,
+            ActionType::Archive(archive_action) => archive_action.execute(path, ctx, dry_run).await,
+            ActionType::Extract(extract_action) => extract_action.execute(path, ctx, dry_run).await,
+            ActionType::ResizeImage(resize_action) => resize_action.execute(path, dry_run).await,
+            ActionType::ConvertImage(convert_action) => {
+                convert_action.execute(path, ctx, dry_run).await
+            }
+            ActionType::Thumbnail(thumbnail_action) => {
+                thumbnail_action.execute(path, ctx, dry_run).await
+            }
+            ActionType::StripExif(strip_action) => strip_action.execute(path, dry_run).await,
+            ActionType::CreateSymlink(create_symlink_action) => {
+                create_symlink_action.execute(path, ctx, dry_run).await
+            }
+            ActionType::Dedupe(dedupe_action) => {
+                dedupe_action.execute(path, ctx.args, options).await
+            }
+            ActionType::Custom(custom_action) => {
+                trace!("run custom command {} ", &custom_action.command);
+                custom_action.execute_command(path, dry_run).await
+            }
+        }
+    }
+}
+
+async fn move_file(
+    src: &Path,
+    action: &MoveFileAction,
+    ctx: &TemplateContext<'_>,
+    dry_run: bool,
+) -> Result<Option<Applied>, Error> {
     let file_name = match src.file_name() {
         Some(file_name) => file_name,
         None => {
             let err = "fail to move file, invalid filename";
             error!(err);
             // TODO: не ок
-            return Ok(());
+            return Ok(None);
         }
     };
-    trace!("dest before mut {:?}", dst);
-    let dest = dst.join(file_name);
+    trace!("dest before mut {:?}", action.destination);
+    let dest = prepare_dir(&action.destination, ctx, dry_run)
+        .await?
+        .join(file_name);
+    let Some(dest) = resolve_conflict(src, &dest, action.on_conflict, ctx, dry_run).await? else {
+        return Ok(None);
+    };
+    let applied = Applied::Moved {
+        from: src.to_path_buf(),
+        to: dest.clone(),
+    };
+    if dry_run {
+        info!("[dry-run] move {:?} to {:?}", src, dest);
+        return Ok(Some(applied));
+    }
     trace!("Moving file to {:?}", dest);
-    fs::rename(src, dest).await
+    let src = src.to_path_buf();
+    let options = transfer::CopyOptions {
+        verify_checksum: action.verify_checksum,
+        ..Default::default()
+    };
+    tokio::task::spawn_blocking(move || transfer::move_path(&src, &dest, &options)).await??;
+    Ok(Some(applied))
 }
 
-async fn remove_file(path: &Path) -> Result<(), Error> {
-    fs::remove_file(path).await
+/// Удаляет файл или папку, с `trash` перемещает их в корзину. Проверки `recursive` и `force`
+/// одинаковые для обоих случаев, с `force` отсутствующий файл не попадает в журнал.
+async fn remove_file(
+    path: &Path,
+    action: &DeleteFileAction,
+    trash: bool,
+    trash_dir: Option<&Path>,
+    dry_run: bool,
+) -> Result<Option<Applied>, Error> {
+    let applied = Applied::Deleted {
+        path: path.to_path_buf(),
+    };
+    let res = match fs::symlink_metadata(path).await {
+        Ok(metadata) if metadata.is_dir() && !action.recursive => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{:?} is a directory, set `recursive` to delete it", path),
+            ));
+        }
+        Ok(_) if trash => {
+            return TrashAction::default()
+                .execute(path, trash_dir, dry_run)
+                .await
+        }
+        Ok(metadata) if metadata.is_dir() => {
+            if dry_run {
+                info!("[dry-run] remove directory {:?}", path);
+                return Ok(Some(applied));
+            }
+            fs::remove_dir_all(path).await
+        }
+        Ok(_) => {
+            if dry_run {
+                info!("[dry-run] remove {:?}", path);
+                return Ok(Some(applied));
+            }
+            fs::remove_file(path).await
+        }
+        Err(err) => Err(err),
+    };
+    match res {
+        Ok(()) => Ok(Some(applied)),
+        Err(err) if action.force && err.kind() == ErrorKind::NotFound => {
+            trace!("{:?} is already removed", path);
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
 }
 
-async fn create_symlink(src: &Path, dst: &Path) -> Result<(), Error> {
-    let file_name = match src.file_name() {
-        Some(file_name) => file_name,
-        None => {
-            let err = "fail to create simlink, invalid filename";
-            error!(err);
-            // TODO: не ок
-            return Ok(());
+/// Вычисляет папку назначения по шаблону и создает ее вместе с промежуточными папками
+async fn prepare_dir(
+    template: &PathTemplate,
+    ctx: &TemplateContext<'_>,
+    dry_run: bool,
+) -> Result<PathBuf, Error> {
+    let dir = template.render(ctx)?;
+    if fs::metadata(&dir).await.is_err() {
+        if dry_run {
+            info!("[dry-run] create directory {:?}", dir);
+        } else {
+            trace!("create directory {:?}", dir);
+            fs::create_dir_all(&dir).await?;
         }
-    };
-    let to = src.join(file_name);
-    trace!("Creating symlink from {:?} to {:?}", src, to);
-    fs::symlink(src, dst).await
+    }
+    Ok(dir)
+}
+
+/// Применяет политику конфликта имен, возвращает итоговый путь или `None` если действие нужно
+/// пропустить. Если политика требует замены, место готовит `ctx.replaced`, а сам шаг
+/// переименовывает результат поверх существующего файла, поэтому при ошибке файл остается.
+async fn resolve_conflict(
+    src: &Path,
+    dest: &Path,
+    policy: ConflictPolicy,
+    ctx: &TemplateContext<'_>,
+    dry_run: bool,
+) -> Result<Option<PathBuf>, Error> {
+    match policy.resolve(src, dest).await? {
+        Resolution::Skip => {
+            if dry_run {
+                info!("[dry-run] skip {:?}, {:?} already exists", src, dest);
+            } else {
+                trace!("skip {:?}, {:?} already exists", src, dest);
+            }
+            Ok(None)
+        }
+        Resolution::Proceed { dest, replace } => {
+            if replace {
+                ctx.replaced.prepare(&dest, dry_run).await?;
+            }
+            Ok(Some(dest))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delete(json: serde_json::Value) -> DeleteFileAction {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_delete_dir_requires_recursive() {
+        let dir = tempfile::tempdir().unwrap();
+        let photos = dir.path().join("photos");
+        std::fs::create_dir(&photos).unwrap();
+        // корзина не отменяет проверку `recursive`
+        for trash in [false, true] {
+            let res =
+                remove_file(&photos, &delete(serde_json::json!({})), trash, None, false).await;
+            assert_eq!(res.unwrap_err().kind(), ErrorKind::InvalidInput);
+            assert!(photos.exists());
+        }
+        let recursive = delete(serde_json::json!({"recursive": true}));
+        remove_file(&photos, &recursive, false, None, false)
+            .await
+            .unwrap();
+        assert!(!photos.exists());
+    }
+
+    #[tokio::test]
+    async fn test_delete_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.txt");
+        for trash in [false, true] {
+            let res =
+                remove_file(&missing, &delete(serde_json::json!({})), trash, None, false).await;
+            assert_eq!(res.unwrap_err().kind(), ErrorKind::NotFound);
+            let force = delete(serde_json::json!({"force": true}));
+            assert_eq!(
+                remove_file(&missing, &force, trash, None, false)
+                    .await
+                    .unwrap(),
+                None
+            );
+        }
+    }
 }
@@ -0,0 +1,531 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tracing::{error, info, trace, warn};
+
+use super::conditions::CheckArgs;
+use super::conflict::Replaced;
+use super::template::TemplateContext;
+use super::transfer::{self, CopyOptions};
+use super::trash::Trashed;
+use super::{ActionType, ExecOptions};
+use crate::validation::{pointer_child, Problem, Validate};
+
+/// Шаг цепочки действий. Каждый шаг получает путь, который получился после предыдущего: после
+/// перемещения следующий шаг работает уже с новым местом файла.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Step {
+    action_type: ActionType,
+    #[serde(default)]
+    on_error: OnError,
+}
+
+impl Step {
+    pub fn action(&self) -> (&ActionType, OnError) {
+        (&self.action_type, self.on_error)
+    }
+}
+
+/// Что делать, если шаг завершился с ошибкой
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnError {
+    /// Остановить цепочку, уже выполненные шаги остаются как есть
+    #[default]
+    Abort,
+    /// Залогировать ошибку и перейти к следующему шагу с тем же путем
+    Continue,
+    /// Отменить выполненные шаги в обратном порядке и остановить цепочку
+    Rollback,
+}
+
+/// Что сделал шаг. По этому вычисляется путь для следующего шага и выполняется откат.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Applied {
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Copied {
+        to: PathBuf,
+    },
+    Linked {
+        link: PathBuf,
+    },
+    Trashed {
+        from: PathBuf,
+        /// `None` в dry-run, место в корзине заранее неизвестно
+        trashed: Option<Trashed>,
+    },
+    Deleted {
+        path: PathBuf,
+    },
+    Archived {
+        from: PathBuf,
+        archive: PathBuf,
+        source_deleted: bool,
+    },
+    Extracted {
+        from: PathBuf,
+        to: PathBuf,
+        source_deleted: bool,
+    },
+    Converted {
+        from: PathBuf,
+        to: PathBuf,
+        source_deleted: bool,
+    },
+    /// Копия заменена ссылкой на оригинал с тем же содержимым
+    Deduplicated {
+        path: PathBuf,
+        original: PathBuf,
+    },
+    /// Файл изменен на месте
+    Modified {
+        path: PathBuf,
+    },
+    Command {
+        command: String,
+    },
+}
+
+impl Applied {
+    /// Путь файла после шага, `None` если файла больше нет
+    pub fn path_after(&self, path: &Path) -> Option<PathBuf> {
+        match self {
+            Applied::Moved { to, .. } | Applied::Converted { to, .. } => Some(to.clone()),
+            Applied::Trashed { .. } | Applied::Deleted { .. } => None,
+            Applied::Archived { source_deleted, .. }
+            | Applied::Extracted { source_deleted, .. } => {
+                (!source_deleted).then(|| path.to_path_buf())
+            }
+            Applied::Copied { .. }
+            | Applied::Linked { .. }
+            | Applied::Deduplicated { .. }
+            | Applied::Modified { .. }
+            | Applied::Command { .. } => Some(path.to_path_buf()),
+        }
+    }
+
+    /// Отменяет шаг. Удаление и команды отменить нельзя, для них возвращается ошибка.
+    pub async fn undo(&self) -> Result<(), Error> {
+        match self {
+            Applied::Moved { from, to } => move_back(to, from).await,
+            Applied::Copied { to } => remove_existing(to).await,
+            Applied::Linked { link } => fs::remove_file(link).await,
+            Applied::Trashed {
+                from,
+                trashed: Some(trashed),
+            } => {
+                move_back(&trashed.file, from).await?;
+                fs::remove_file(&trashed.info).await
+            }
+            Applied::Trashed { trashed: None, .. } => Ok(()),
+            Applied::Deleted { path } => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{:?} is deleted permanently", path),
+            )),
+            Applied::Archived { archive, .. } => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("entries are not removed from archive {:?}", archive),
+            )),
+            Applied::Extracted {
+                from,
+                source_deleted: true,
+                ..
+            } => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("archive {:?} is deleted permanently", from),
+            )),
+            Applied::Extracted { to, .. } => remove_existing(to).await,
+            Applied::Converted {
+                from,
+                source_deleted: true,
+                ..
+            } => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("original {:?} is deleted after conversion", from),
+            )),
+            Applied::Converted { to, .. } => fs::remove_file(to).await,
+            Applied::Deduplicated { path, original } => restore_copy(path, original).await,
+            Applied::Modified { path } => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{:?} is changed in place", path),
+            )),
+            Applied::Command { command } => Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("command `{}` can not be undone", command),
+            )),
+        }
+    }
+}
+
+/// Возвращает файл на старое место, не затирая то, что могло там появиться
+async fn move_back(from: &Path, to: &Path) -> Result<(), Error> {
+    if fs::symlink_metadata(to).await.is_ok() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("can not restore {:?}, the path is taken", to),
+        ));
+    }
+    let (from, to) = (from.to_path_buf(), to.to_path_buf());
+    tokio::task::spawn_blocking(move || transfer::move_path(&from, &to, &CopyOptions::default()))
+        .await?
+}
+
+/// Удаляет созданный шагом файл или папку
+async fn remove_existing(path: &Path) -> Result<(), Error> {
+    if fs::symlink_metadata(path).await?.is_dir() {
+        fs::remove_dir_all(path).await
+    } else {
+        fs::remove_file(path).await
+    }
+}
+
+/// Возвращает отдельную копию файла на место ссылки на оригинал
+async fn restore_copy(path: &Path, original: &Path) -> Result<(), Error> {
+    let tmp = transfer::tmp_path(path)?;
+    fs::copy(original, &tmp).await?;
+    if let Err(err) = fs::rename(&tmp, path).await {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Выполненный шаг и его id в журнале, если журнал включен
+type Done = (Applied, Option<String>);
+
+/// Выполняет шаги по очереди и записывает каждый выполненный шаг в журнал. Возвращает
+/// выполненные шаги.
+pub async fn run(
+    steps: &[(&ActionType, OnError)],
+    args: CheckArgs,
+    captures: HashMap<String, String>,
+    rule: Option<&str>,
+    options: &ExecOptions,
+) -> Result<Vec<Applied>, Error> {
+    let dry_run = options.dry_run;
+    let mut args = args;
+    let mut applied: Vec<Done> = vec![];
+    let mut gone_after = None;
+    for (i, (action_type, on_error)) in steps.iter().enumerate() {
+        if let Some(prev) = gone_after {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "step {} can not run, {:?} is removed by step {}",
+                    i, args.file_path, prev
+                ),
+            ));
+        }
+        let replaced = Replaced::new(options.journal.is_some(), options.trash_dir.clone());
+        let ctx = TemplateContext {
+            args: &args,
+            captures: captures.clone(),
+            counter: None,
+            replaced: &replaced,
+        };
+        trace!("run step {} on {:?}", i, args.file_path);
+        let res = action_type.execute(&args.file_path, &ctx, options).await;
+        for trashed in replaced.take() {
+            // шаг упал, замененный файл возвращается на место
+            if res.is_err() {
+                match trashed.undo().await {
+                    Ok(()) => continue,
+                    Err(err) => warn!("fail to restore replaced {:?}: {}", trashed, err),
+                }
+            }
+            let id = record(options, rule, &trashed);
+            applied.push((trashed, id));
+        }
+        match res {
+            Ok(Some(done)) => {
+                match done.path_after(&args.file_path) {
+                    Some(path) if path != args.file_path => {
+                        // в dry-run файл никуда не переместился, метаданные остаются прежними
+                        let file_metadata = if dry_run {
+                            args.file_metadata.clone()
+                        } else {
+                            fs::metadata(&path).await?
+                        };
+                        args = CheckArgs {
+                            file_metadata,
+                            file_path: path,
+                            details: Default::default(),
+                            ..args
+                        };
+                    }
+                    Some(_) => {}
+                    None => gone_after = Some(i),
+                }
+                let id = record(options, rule, &done);
+                applied.push((done, id));
+            }
+            Ok(None) => {}
+            Err(err) => match on_error {
+                OnError::Abort => return Err(err),
+                OnError::Continue => {
+                    error!("step {} failed on {:?}: {}", i, args.file_path, err);
+                }
+                OnError::Rollback => {
+                    rollback(&applied, options).await;
+                    return Err(err);
+                }
+            },
+        }
+    }
+    Ok(applied.into_iter().map(|(done, _)| done).collect())
+}
+
+/// Записывает выполненный шаг в журнал, если он включен, возвращает id записи
+fn record(options: &ExecOptions, rule: Option<&str>, done: &Applied) -> Option<String> {
+    match &options.journal {
+        Some(journal) if !options.dry_run => journal
+            .record(rule, done)
+            .map_err(|err| warn!("fail to write journal: {}", err))
+            .ok(),
+        _ => None,
+    }
+}
+
+/// Отменяет шаги в обратном порядке и отмечает это в журнале. Ошибки отката логируются, откат
+/// продолжается.
+async fn rollback(applied: &[Done], options: &ExecOptions) {
+    for (done, id) in applied.iter().rev() {
+        if options.dry_run {
+            info!("[dry-run] rollback {:?}", done);
+            continue;
+        }
+        trace!("rollback {:?}", done);
+        if let Err(err) = done.undo().await {
+            warn!("fail to rollback {:?}: {}", done, err);
+            continue;
+        }
+        if let (Some(journal), Some(id)) = (&options.journal, id) {
+            if let Err(err) = journal.mark_undone(id) {
+                warn!("fail to write journal: {}", err);
+            }
+        }
+    }
+}
+
+impl Validate for Step {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        self.action_type
+            .validate(&pointer_child(pointer, "action_type"), problems);
+    }
+}
+
+/// Шаги после удаления файла никогда не выполнятся
+pub fn validate_steps(steps: &[Step], pointer: &str, problems: &mut Vec<Problem>) {
+    for (i, step) in steps.iter().enumerate() {
+        let step_pointer = pointer_child(pointer, i);
+        step.validate(&step_pointer, problems);
+        let removes = match &step.action_type {
+            ActionType::DeleteFile(_) | ActionType::Trash(_) => true,
+            ActionType::Archive(archive_action) => archive_action.deletes_source(),
+            ActionType::Extract(extract_action) => extract_action.deletes_source(),
+            _ => false,
+        };
+        if removes && i + 1 < steps.len() {
+            problems.push(Problem::warning(
+                step_pointer,
+                "the file is removed at this step, next steps will fail",
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::Journal;
+    use std::sync::Arc;
+
+    fn steps(json: serde_json::Value) -> Vec<Step> {
+        serde_json::from_value(json).unwrap()
+    }
+
+    async fn run_steps(steps: &[Step], path: &Path) -> Result<Vec<Applied>, Error> {
+        let args = CheckArgs::for_path(path);
+        let steps: Vec<_> = steps.iter().map(Step::action).collect();
+        run(&steps, args, HashMap::new(), None, &ExecOptions::default()).await
+    }
+
+    /// Файл `in/photo.jpg` в канонической временной папке
+    fn photo(dir: &Path) -> (PathBuf, PathBuf) {
+        let root = dir.canonicalize().unwrap();
+        let src = root.join("in/photo.jpg");
+        std::fs::create_dir_all(src.parent().unwrap()).unwrap();
+        std::fs::write(&src, "jpeg").unwrap();
+        (root, src)
+    }
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        // копия, перемещение, ссылка на уже перемещенный файл
+        let pipeline = steps(serde_json::json!([
+            {"action_type": {"copy_file": {"destination": root.join("backup")}}},
+            {"action_type": {"move_file": {"destination": root.join("sorted")}}},
+            {"action_type": {"create_symlink": {"to": root.join("gallery")}}},
+        ]));
+        let applied = run_steps(&pipeline, &src).await.unwrap();
+        assert_eq!(applied.len(), 3);
+        assert!(root.join("backup/photo.jpg").exists());
+        assert!(!src.exists());
+        assert_eq!(
+            std::fs::read_link(root.join("gallery/photo.jpg")).unwrap(),
+            root.join("sorted/photo.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        std::fs::create_dir(root.join("sorted")).unwrap();
+        std::fs::write(root.join("sorted/photo.jpg"), "other").unwrap();
+        // второй шаг падает (файл уже есть), первый откатывается
+        let pipeline = steps(serde_json::json!([
+            {"action_type": {"copy_file": {"destination": root.join("copy")}}},
+            {
+                "action_type": {"move_file": {"destination": root.join("sorted"), "on_conflict": "fail"}},
+                "on_error": "rollback"
+            },
+        ]));
+        assert!(run_steps(&pipeline, &src).await.is_err());
+        assert!(src.exists());
+        assert!(!root.join("copy/photo.jpg").exists());
+    }
+
+    #[tokio::test]
+    async fn test_continue() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        std::fs::create_dir(root.join("sorted")).unwrap();
+        std::fs::write(root.join("sorted/photo.jpg"), "other").unwrap();
+        // с continue цепочка идет дальше
+        let pipeline = steps(serde_json::json!([
+            {
+                "action_type": {"move_file": {"destination": root.join("sorted"), "on_conflict": "fail"}},
+                "on_error": "continue"
+            },
+            {"action_type": {"move_file": {"destination": root.join("other")}}},
+        ]));
+        let applied = run_steps(&pipeline, &src).await.unwrap();
+        assert_eq!(
+            applied,
+            vec![Applied::Moved {
+                from: src.clone(),
+                to: root.join("other/photo.jpg")
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_undo_restores_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, src) = photo(dir.path());
+        let journal = Arc::new(Journal::open(&root.join("journal.jsonl")).unwrap());
+        // корзина во временной папке, а не в домашней
+        let options = ExecOptions {
+            journal: Some(journal.clone()),
+            trash_dir: Some(root.join("Trash")),
+            ..Default::default()
+        };
+        for dest in ["sorted", "backup"] {
+            std::fs::create_dir(root.join(dest)).unwrap();
+            std::fs::write(root.join(dest).join("photo.jpg"), "old").unwrap();
+        }
+
+        let pipeline = steps(serde_json::json!([
+            {"action_type": {"copy_file": {"destination": root.join("backup")}}},
+            {"action_type": {"move_file": {"destination": root.join("sorted")}}},
+        ]));
+        let pipeline: Vec<_> = pipeline.iter().map(Step::action).collect();
+        let args = CheckArgs::for_path(&src);
+        run(&pipeline, args, HashMap::new(), None, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("sorted/photo.jpg")).unwrap(),
+            "jpeg"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("backup/photo.jpg")).unwrap(),
+            "jpeg"
+        );
+
+        // замененные файлы записаны перед шагами, которые их заменили
+        let entries = journal.entries().unwrap();
+        let kinds: Vec<_> = entries
+            .iter()
+            .map(|entry| match &entry.applied {
+                Applied::Trashed {
+                    from,
+                    trashed: Some(trashed),
+                } if trashed.file.starts_with(root.join("Trash")) => {
+                    format!("trashed {}", from.display())
+                }
+                Applied::Copied { .. } => "copied".to_owned(),
+                Applied::Moved { .. } => "moved".to_owned(),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                format!("trashed {}", root.join("backup/photo.jpg").display()),
+                "copied".to_owned(),
+                format!("trashed {}", root.join("sorted/photo.jpg").display()),
+                "moved".to_owned(),
+            ]
+        );
+
+        let results = journal.undo(entries).await;
+        assert!(results.iter().all(|(_, res)| res.is_ok()), "{:?}", results);
+        assert_eq!(std::fs::read_to_string(&src).unwrap(), "jpeg");
+        for dest in ["sorted", "backup"] {
+            assert_eq!(
+                std::fs::read_to_string(root.join(dest).join("photo.jpg")).unwrap(),
+                "old"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_restores_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let src = root.join("photos.zip");
+        std::fs::write(&src, "not a zip").unwrap();
+        let dest = root.join("photos");
+        std::fs::write(&dest, "old").unwrap();
+        let journal = Arc::new(Journal::open(&root.join("journal.jsonl")).unwrap());
+        let options = ExecOptions {
+            journal: Some(journal.clone()),
+            trash_dir: Some(root.join("Trash")),
+            ..Default::default()
+        };
+
+        // файл уходит в корзину до распаковки, после ошибки возвращается обратно
+        let pipeline = steps(serde_json::json!([
+            {"action_type": {"extract": {"destination": dest, "on_conflict": "overwrite"}}},
+        ]));
+        let pipeline: Vec<_> = pipeline.iter().map(Step::action).collect();
+        let args = CheckArgs::for_path(&src);
+        assert!(run(&pipeline, args, HashMap::new(), None, &options)
+            .await
+            .is_err());
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "old");
+        let mut trashed = std::fs::read_dir(root.join("Trash/files")).unwrap();
+        assert!(trashed.next().is_none());
+        assert!(journal.entries().unwrap().is_empty());
+    }
+}
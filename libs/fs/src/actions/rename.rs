@@ -0,0 +1,357 @@
+//! Переименование файла на месте. Новое имя собирается по шагам: шаблон `name`, замены по
+//! регуляркам, чистка пробелов, slug, регистр и исправление расширения по содержимому.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, trace};
+
+use super::conditions::{extension_matches, Pattern};
+use super::pipeline::Applied;
+use super::template::{PathTemplate, TemplateContext};
+use super::{resolve_conflict, ConflictPolicy};
+use crate::validation::{pointer_child, Problem, Validate};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameAction {
+    // Шаблон нового имени, например `{mtime:%Y-%m-%d}_{counter:3}.{ext}`, по умолчанию текущее имя
+    #[serde(default)]
+    name: Option<PathTemplate>,
+    // Замены по регуляркам, применяются по порядку ко всему имени, `$1` ссылается на группу
+    #[serde(default)]
+    replace: Vec<Replacement>,
+    // Пробелы в начале и конце имени убираются, подряд идущие пробелы заменяются этой строкой
+    #[serde(default)]
+    whitespace: Option<String>,
+    #[serde(default)]
+    slugify: bool,
+    #[serde(default)]
+    case: Option<Case>,
+    // Поставить расширение, соответствующее содержимому файла
+    #[serde(default)]
+    fix_extension: bool,
+    // По умолчанию занятое имя не затирается, а подбирается свободное
+    #[serde(default = "default_on_conflict")]
+    on_conflict: ConflictPolicy,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Replacement {
+    pattern: Pattern,
+    #[serde(default)]
+    with: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Case {
+    Lower,
+    Upper,
+    /// Каждое слово имени с заглавной буквы, расширение не меняется
+    Title,
+}
+
+fn default_on_conflict() -> ConflictPolicy {
+    ConflictPolicy::Rename
+}
+
+impl RenameAction {
+    pub fn templates(&self) -> Vec<&PathTemplate> {
+        self.name.iter().collect()
+    }
+
+    pub async fn execute(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext<'_>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        let replace = self
+            .replace
+            .iter()
+            .map(|replacement| {
+                let re = replacement.pattern.regex().ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("invalid regex {:?}", replacement.pattern.as_str()),
+                    )
+                })?;
+                Ok((re, replacement.with.as_str()))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let uses_counter = self.name.as_ref().is_some_and(PathTemplate::has_counter);
+
+        let mut dest = src.to_path_buf();
+        for counter in 1.. {
+            let ctx = TemplateContext {
+                args: ctx.args,
+                captures: ctx.captures.clone(),
+                counter: Some(counter),
+                replaced: ctx.replaced,
+            };
+            dest = src.with_file_name(self.new_name(src, &ctx, &replace)?);
+            // счетчик перебирается, пока имя не станет свободным
+            if dest == src || !uses_counter || fs::symlink_metadata(&dest).await.is_err() {
+                break;
+            }
+        }
+        if dest == src {
+            trace!("{:?} already has the right name", src);
+            return Ok(None);
+        }
+
+        let Some(dest) = resolve_conflict(src, &dest, self.on_conflict, ctx, dry_run).await? else {
+            return Ok(None);
+        };
+        let applied = Applied::Moved {
+            from: src.to_path_buf(),
+            to: dest.clone(),
+        };
+        if dry_run {
+            info!("[dry-run] rename {:?} to {:?}", src, dest);
+            return Ok(Some(applied));
+        }
+        trace!("Renaming {:?} to {:?}", src, dest);
+        fs::rename(src, &dest).await?;
+        Ok(Some(applied))
+    }
+
+    fn new_name(
+        &self,
+        src: &Path,
+        ctx: &TemplateContext,
+        replace: &[(&Regex, &str)],
+    ) -> Result<String, Error> {
+        let mut name = match &self.name {
+            Some(template) => template.render(ctx)?.to_string_lossy().into_owned(),
+            None => src
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+        };
+        for (re, with) in replace {
+            name = re.replace_all(&name, *with).into_owned();
+        }
+
+        let (mut stem, mut ext) = split_extension(&name);
+        if let Some(separator) = &self.whitespace {
+            stem = stem.split_whitespace().collect::<Vec<_>>().join(separator);
+            ext = ext.trim().to_owned();
+        }
+        if self.slugify {
+            stem = slugify(&stem);
+            ext = ext.to_lowercase();
+        }
+        match self.case {
+            Some(Case::Lower) => (stem, ext) = (stem.to_lowercase(), ext.to_lowercase()),
+            Some(Case::Upper) => (stem, ext) = (stem.to_uppercase(), ext.to_uppercase()),
+            Some(Case::Title) => stem = title_case(&stem),
+            None => {}
+        }
+        if self.fix_extension {
+            if let Some(kind) = ctx.args.file_kind {
+                fix_extension(&mut stem, &mut ext, kind.extension());
+            }
+        }
+
+        let name = if ext.is_empty() {
+            stem
+        } else {
+            format!("{}.{}", stem, ext)
+        };
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid new name {:?} for {:?}", name, src),
+            ));
+        }
+        Ok(name)
+    }
+}
+
+impl Validate for RenameAction {
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        if let Some(template) = &self.name {
+            let pointer = pointer_child(pointer, "name");
+            template.validate(&pointer, problems);
+            if template.as_str().contains('/') {
+                problems.push(Problem::error(
+                    pointer,
+                    "name can not contain `/`, use move_file to change the directory",
+                ));
+            }
+        }
+        let replace_pointer = pointer_child(pointer, "replace");
+        for (i, replacement) in self.replace.iter().enumerate() {
+            replacement.pattern.validate(
+                &pointer_child(&pointer_child(&replace_pointer, i), "pattern"),
+                problems,
+            );
+        }
+        let changes_name = self.name.is_some()
+            || !self.replace.is_empty()
+            || self.whitespace.is_some()
+            || self.slugify
+            || self.case.is_some()
+            || self.fix_extension;
+        if !changes_name {
+            problems.push(Problem::warning(pointer, "rename does not change the name"));
+        }
+    }
+}
+
+/// `photo.JPG` -> (`photo`, `JPG`). У скрытых файлов вроде `.bashrc` расширения нет.
+fn split_extension(name: &str) -> (String, String) {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => (name[..dot].to_owned(), name[dot + 1..].to_owned()),
+        _ => (name.to_owned(), String::new()),
+    }
+}
+
+/// Нижний регистр, буквы и цифры (в том числе не латинские) остаются, остальное заменяется на `-`
+fn slugify(stem: &str) -> String {
+    let mut slug = String::new();
+    for c in stem.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_owned()
+}
+
+fn title_case(stem: &str) -> String {
+    let mut out = String::new();
+    let mut word_start = true;
+    for c in stem.chars() {
+        if word_start {
+            out.extend(c.to_uppercase());
+        } else {
+            out.extend(c.to_lowercase());
+        }
+        word_start = c.is_whitespace() || c == '-' || c == '_';
+    }
+    out
+}
+
+/// Меняет расширение на `expected`, если текущее ему не соответствует. Хвосты вроде `v1.2` не
+/// считаются расширением, к ним `expected` дописывается.
+fn fix_extension(stem: &mut String, ext: &mut String, expected: &str) {
+    if extension_matches(ext, expected) {
+        return;
+    }
+    let looks_like_extension = ext.len() <= 5
+        && ext.chars().all(|c| c.is_ascii_alphanumeric())
+        && ext.chars().any(|c| c.is_ascii_alphabetic());
+    if !ext.is_empty() && !looks_like_extension {
+        stem.push('.');
+        stem.push_str(ext);
+    }
+    *ext = expected.to_owned();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::actions::conditions::CheckArgs;
+
+    async fn rename(json: serde_json::Value, src: &Path) -> Option<Applied> {
+        let action: RenameAction = serde_json::from_value(json).unwrap();
+        let args = CheckArgs::for_path(src);
+        action
+            .execute(src, &TemplateContext::for_args(&args), false)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("  My   Holiday_Photo (copy).PNG");
+        // JPEG по содержимому
+        std::fs::write(
+            &src,
+            [0xFF, 0xD8, 0xFF, 0xE0, 0, 0x10, b'J', b'F', b'I', b'F'],
+        )
+        .unwrap();
+
+        let applied = rename(
+            serde_json::json!({
+                "replace": [{"pattern": r"\s*\(copy\)"}],
+                "whitespace": " ",
+                "case": "title",
+                "fix_extension": true,
+            }),
+            &src,
+        )
+        .await;
+        let renamed = dir.path().join("My Holiday_Photo.jpg");
+        assert_eq!(
+            applied,
+            Some(Applied::Moved {
+                from: src,
+                to: renamed.clone()
+            })
+        );
+        assert!(renamed.exists());
+    }
+
+    #[tokio::test]
+    async fn test_rename_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("My Holiday Photo.jpg");
+        std::fs::write(&src, "").unwrap();
+        let slug = serde_json::json!({"slugify": true});
+        assert!(rename(slug.clone(), &src).await.is_some());
+        // имя уже подходит
+        let src = dir.path().join("my-holiday-photo.jpg");
+        assert!(rename(slug, &src).await.is_none());
+        assert!(src.exists());
+    }
+
+    #[tokio::test]
+    async fn test_rename_counter() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("photo.jpg");
+        std::fs::write(&src, "").unwrap();
+        std::fs::write(dir.path().join("IMG_001.jpg"), "").unwrap();
+        rename(serde_json::json!({"name": "IMG_{counter:3}.{ext}"}), &src)
+            .await
+            .unwrap();
+        assert!(dir.path().join("IMG_002.jpg").exists());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_pattern() {
+        let action: RenameAction =
+            serde_json::from_value(serde_json::json!({"replace": [{"pattern": "(["}]})).unwrap();
+        let mut problems = vec![];
+        action.validate("/action_type/rename", &mut problems);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].pointer, "/action_type/rename/replace/0/pattern");
+
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("photo.jpg");
+        std::fs::write(&src, "").unwrap();
+        let args = CheckArgs::for_path(&src);
+        let ctx = TemplateContext::for_args(&args);
+        assert!(action.execute(&src, &ctx, false).await.is_err());
+        assert!(src.exists());
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Отчёт за 2024 — финал!"), "отчёт-за-2024-финал");
+    }
+
+    #[test]
+    fn test_fix_extension() {
+        let (mut stem, mut ext) = ("report v1".to_owned(), "2".to_owned());
+        fix_extension(&mut stem, &mut ext, "pdf");
+        assert_eq!((stem.as_str(), ext.as_str()), ("report v1.2", "pdf"));
+    }
+}
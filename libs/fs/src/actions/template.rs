@@ -0,0 +1,399 @@
+use chrono::{format::StrftimeItems, DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::conditions::CheckArgs;
+use super::conflict::Replaced;
+use crate::validation::Problem;
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Путь с подстановками, которые вычисляются для каждого файла, например
+/// `/home/user/Images/{mtime:%Y/%m}` или `/home/user/Sorted/{matcher_type}/{ext}`.
+///
+/// Поддерживаемые подстановки:
+/// - `{name}`, `{stem}`, `{ext}` имя файла целиком, без расширения и расширение без точки
+/// - `{parent}` имя папки, в которой лежит файл
+/// - `{mime}` MIME тип по содержимому, например `image/png` (создаст две папки)
+/// - `{matcher_type}` категория файла: `image`, `audio`, `archive` и т.д.
+/// - `{mtime:%Y/%m}`, `{ctime:%Y}` дата изменения и создания в формате strftime, по умолчанию
+///   `%Y-%m-%d`
+/// - `{now:%Y-%m-%d}` текущая дата в том же формате
+/// - `{counter}`, `{counter:3}` номер, с которым имя свободно, дополненный нулями до ширины.
+///   Доступен только в имени действия `rename`
+/// - `{size_bucket}` размер файла словом: `tiny`, `small`, `medium`, `large`, `huge`
+/// - `{cap:1}`, `{cap:year}` группы из регулярки `file_name_pattern_condition`, `.` и `..`
+///   заменяются на `_`
+/// - `{artist}`, `{album_artist}`, `{album}`, `{genre}`, `{title}`, `{year}`, `{track}` теги
+///   аудио и видео, `unknown` если тега нет. `/` в значении заменяется на `_`
+///
+/// Фигурные скобки экранируются удвоением: `{{` и `}}`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct PathTemplate(String);
+
+/// Данные о файле, из которых заполняется шаблон
+pub struct TemplateContext<'a> {
+    pub args: &'a CheckArgs,
+    /// Группы из регулярок условий, ключи это номера групп или их имена
+    pub captures: HashMap<String, String>,
+    /// Значение `{counter}`, задается только при переименовании
+    pub counter: Option<u64>,
+    /// Файлы, замененные шагом при конфликте имен
+    pub replaced: &'a Replaced,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    Literal(String),
+    Var { name: &'a str, arg: Option<&'a str> },
+}
+
+impl PathTemplate {
+    pub fn render(&self, ctx: &TemplateContext) -> Result<PathBuf, Error> {
+        let segments = self
+            .parse()
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        let mut out = String::new();
+        for segment in segments {
+            match segment {
+                Segment::Literal(literal) => out.push_str(&literal),
+                Segment::Var { name, arg } => {
+                    let value = render_var(name, arg, ctx).map_err(|err| {
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("template {:?}: {}", self.0, err),
+                        )
+                    })?;
+                    out.push_str(&value);
+                }
+            }
+        }
+        Ok(PathBuf::from(out))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn has_counter(&self) -> bool {
+        self.has_var("counter")
+    }
+
+    /// Есть `{cap:…}`, значит для файла нужны группы из регулярок условий
+    pub fn has_captures(&self) -> bool {
+        self.has_var("cap")
+    }
+
+    fn has_var(&self, var: &str) -> bool {
+        self.parse().is_ok_and(|segments| {
+            segments
+                .iter()
+                .any(|segment| matches!(segment, Segment::Var { name, .. } if *name == var))
+        })
+    }
+
+    /// Часть пути до первой подстановки, ее можно проверить на существование заранее
+    pub fn static_prefix(&self) -> PathBuf {
+        let literal_end = self.0.find(['{', '}']).unwrap_or(self.0.len());
+        let literal = &self.0[..literal_end];
+        if literal_end == self.0.len() {
+            return PathBuf::from(literal);
+        }
+        match literal.rfind('/') {
+            Some(0) => PathBuf::from("/"),
+            Some(i) => PathBuf::from(&literal[..i]),
+            None => PathBuf::new(),
+        }
+    }
+
+    pub fn validate(&self, pointer: &str, problems: &mut Vec<Problem>) {
+        let segments = match self.parse() {
+            Ok(segments) => segments,
+            Err(err) => {
+                problems.push(Problem::error(pointer, err));
+                return;
+            }
+        };
+        for segment in segments {
+            if let Segment::Var { name, arg } = segment {
+                if let Err(err) = check_var(name, arg) {
+                    problems.push(Problem::error(pointer, err));
+                }
+            }
+        }
+    }
+
+    fn parse(&self) -> Result<Vec<Segment<'_>>, String> {
+        let src = self.0.as_str();
+        let mut segments = vec![];
+        let mut literal = String::new();
+        let mut rest = src;
+        while let Some(i) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..i]);
+            let tail = &rest[i..];
+            if tail.starts_with("{{") || tail.starts_with("}}") {
+                literal.push_str(&tail[..1]);
+                rest = &tail[2..];
+                continue;
+            }
+            if tail.starts_with('}') {
+                return Err(format!("unmatched `}}` at {}", src.len() - tail.len()));
+            }
+            let end = tail
+                .find('}')
+                .ok_or_else(|| format!("unclosed `{{` at {}", src.len() - tail.len()))?;
+            let var = &tail[1..end];
+            let (name, arg) = match var.split_once(':') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (var, None),
+            };
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            segments.push(Segment::Var { name, arg });
+            rest = &tail[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(segments)
+    }
+}
+
+impl From<&str> for PathTemplate {
+    fn from(template: &str) -> Self {
+        Self(template.to_owned())
+    }
+}
+
+fn check_var(name: &str, arg: Option<&str>) -> Result<(), String> {
+    match (name, arg) {
+        ("name" | "stem" | "ext" | "parent" | "mime" | "matcher_type" | "size_bucket", None) => {
+            Ok(())
+        }
+        ("mtime" | "ctime" | "now", arg) => check_date_format(arg.unwrap_or(DEFAULT_DATE_FORMAT)),
+        ("counter", None) => Ok(()),
+        ("artist" | "album_artist" | "album" | "genre" | "title" | "year" | "track", None) => {
+            Ok(())
+        }
+        ("counter", Some(width)) if width.parse::<usize>().is_ok() => Ok(()),
+        ("cap", Some(group)) if !group.is_empty() => Ok(()),
+        _ => Err(format!("unknown placeholder `{{{}}}`", join_var(name, arg))),
+    }
+}
+
+fn render_var(name: &str, arg: Option<&str>, ctx: &TemplateContext) -> Result<String, String> {
+    check_var(name, arg)?;
+    let path = &ctx.args.file_path;
+    let lossy = |s: Option<&std::ffi::OsStr>| s.unwrap_or_default().to_string_lossy().into_owned();
+    let value = match name {
+        "name" => lossy(path.file_name()),
+        "stem" => lossy(path.file_stem()),
+        "ext" => lossy(path.extension()),
+        "parent" => lossy(path.parent().and_then(Path::file_name)),
+        "mime" => ctx
+            .args
+            .file_kind
+            .map_or("unknown", |kind| kind.mime_type())
+            .to_owned(),
+        "matcher_type" => matcher_type_name(ctx.args.file_type).to_owned(),
+        "size_bucket" => size_bucket(ctx.args.file_metadata.len()).to_owned(),
+        "mtime" | "ctime" => {
+            let metadata = &ctx.args.file_metadata;
+            let time = if name == "ctime" {
+                // не все файловые системы хранят время создания
+                metadata.created().or_else(|_| metadata.modified())
+            } else {
+                metadata.modified()
+            }
+            .map_err(|err| format!("{} is not available: {}", name, err))?;
+            format_date(time, arg.unwrap_or(DEFAULT_DATE_FORMAT))?
+        }
+        "now" => format_date(SystemTime::now(), arg.unwrap_or(DEFAULT_DATE_FORMAT))?,
+        "counter" => {
+            let counter = ctx
+                .counter
+                .ok_or("`{counter}` is only available in rename")?;
+            let width = arg.map_or(Ok(0), str::parse).unwrap_or_default();
+            format!("{:0width$}", counter, width = width)
+        }
+        "artist" | "album_artist" | "album" | "genre" | "title" | "year" | "track" => ctx
+            .args
+            .media()
+            .and_then(|media| media.tags.get(name))
+            .map_or_else(|| "unknown".to_owned(), path_component),
+        "cap" => {
+            let group = arg.unwrap_or_default();
+            ctx.captures
+                .get(group)
+                .map(|value| path_component(value))
+                .ok_or_else(|| format!("capture group `{}` did not match", group))?
+        }
+        _ => unreachable!("checked by check_var"),
+    };
+    Ok(value)
+}
+
+/// Значение тега или группы как одна папка: без `/` и без `.` / `..`
+fn path_component(value: &str) -> String {
+    let value = value.replace(['/', '\0'], "_");
+    match value.as_str() {
+        "." | ".." => "_".to_owned(),
+        _ => value,
+    }
+}
+
+fn join_var(name: &str, arg: Option<&str>) -> String {
+    match arg {
+        Some(arg) => format!("{}:{}", name, arg),
+        None => name.to_owned(),
+    }
+}
+
+fn check_date_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|item| item == chrono::format::Item::Error) {
+        return Err(format!("invalid date format `{}`", format));
+    }
+    Ok(())
+}
+
+fn format_date(time: SystemTime, format: &str) -> Result<String, String> {
+    check_date_format(format)?;
+    let time: DateTime<Local> = time.into();
+    let mut out = String::new();
+    write!(out, "{}", time.format(format)).map_err(|err| err.to_string())?;
+    Ok(out)
+}
+
+pub fn matcher_type_name(matcher_type: Option<infer::MatcherType>) -> &'static str {
+    match matcher_type {
+        Some(infer::MatcherType::App) => "app",
+        Some(infer::MatcherType::Archive) => "archive",
+        Some(infer::MatcherType::Audio) => "audio",
+        Some(infer::MatcherType::Book) => "book",
+        Some(infer::MatcherType::Doc) => "doc",
+        Some(infer::MatcherType::Font) => "font",
+        Some(infer::MatcherType::Image) => "image",
+        Some(infer::MatcherType::Text) => "text",
+        Some(infer::MatcherType::Video) => "video",
+        Some(infer::MatcherType::Custom) => "custom",
+        None => "unknown",
+    }
+}
+
+fn size_bucket(size: u64) -> &'static str {
+    const KB: u64 = 1024;
+    const MB: u64 = 1024 * KB;
+    const GB: u64 = 1024 * MB;
+    match size {
+        s if s < 100 * KB => "tiny",
+        s if s < MB => "small",
+        s if s < 100 * MB => "medium",
+        s if s < GB => "large",
+        _ => "huge",
+    }
+}
+
+#[cfg(test)]
+impl<'a> TemplateContext<'a> {
+    /// Контекст без групп из регулярок и счетчика
+    pub fn for_args(args: &'a CheckArgs) -> Self {
+        static REPLACED: Replaced = Replaced::new(false, None);
+        TemplateContext {
+            args,
+            captures: HashMap::new(),
+            counter: None,
+            replaced: &REPLACED,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(dir: &Path) -> CheckArgs {
+        let path = dir.join("Downloads").join("IMG_2024.tar.gz");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "content").unwrap();
+        let mtime = filetime::FileTime::from_unix_time(1_715_000_000, 0);
+        filetime::set_file_mtime(&path, mtime).unwrap();
+        CheckArgs {
+            file_type: Some(infer::MatcherType::Archive),
+            ..CheckArgs::for_path(&path)
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = args(dir.path());
+        let ctx = TemplateContext {
+            captures: HashMap::from([
+                ("1".to_owned(), "2024".to_owned()),
+                ("up".to_owned(), "..".to_owned()),
+            ]),
+            counter: Some(7),
+            ..TemplateContext::for_args(&args)
+        };
+        let render = |t: &str| PathTemplate::from(t).render(&ctx).unwrap();
+
+        assert_eq!(render("/a/{name}"), Path::new("/a/IMG_2024.tar.gz"));
+        assert_eq!(render("/a/{stem}.{ext}"), Path::new("/a/IMG_2024.tar.gz"));
+        assert_eq!(
+            render("/{parent}/{matcher_type}"),
+            Path::new("/Downloads/archive")
+        );
+        assert_eq!(render("/{size_bucket}/{mime}"), Path::new("/tiny/unknown"));
+        assert_eq!(render("/{mtime:%Y}/{cap:1}"), Path::new("/2024/2024"));
+        assert_eq!(render("/{{literal}}"), Path::new("/{literal}"));
+        assert_eq!(
+            render("/{stem}_{counter:3}"),
+            Path::new("/IMG_2024.tar_007")
+        );
+        assert!(PathTemplate::from("/{cap:2}").render(&ctx).is_err());
+        // группа не выводит за пределы папки
+        assert_eq!(render("/a/{cap:up}/b"), Path::new("/a/_/b"));
+        // не аудио, тегов нет
+        assert_eq!(
+            render("/Music/{artist}/{album}"),
+            Path::new("/Music/unknown/unknown")
+        );
+        assert_eq!(path_component("AC/DC"), "AC_DC");
+    }
+
+    #[test]
+    fn test_validate() {
+        let problems = |t: &str| {
+            let mut problems = vec![];
+            PathTemplate::from(t).validate("", &mut problems);
+            problems.len()
+        };
+        assert_eq!(problems("/a/{mtime:%Y/%m}/{name}"), 0);
+        assert_eq!(problems("/a/{unknown}"), 1);
+        assert_eq!(problems("/a/{name"), 1);
+        assert_eq!(problems("/a/{mtime:%Q}"), 1);
+    }
+
+    #[test]
+    fn test_static_prefix() {
+        let prefix = |t: &str| PathTemplate::from(t).static_prefix();
+        assert_eq!(prefix("/a/b"), Path::new("/a/b"));
+        assert_eq!(prefix("/a/b/{ext}/c"), Path::new("/a/b"));
+        assert_eq!(prefix("/a/b{ext}"), Path::new("/a"));
+        assert_eq!(prefix("/{ext}"), Path::new("/"));
+    }
+
+    #[test]
+    fn test_has_captures() {
+        assert!(PathTemplate::from("/photos/{cap:year}/{name}").has_captures());
+        assert!(!PathTemplate::from("/photos/{mtime:%Y}/{{cap:1}}").has_captures());
+        assert!(PathTemplate::from("IMG_{counter:3}.{ext}").has_counter());
+    }
+}
@@ -0,0 +1,395 @@
+//! Копирование и перемещение файлов и папок между файловыми системами. `move_path`,
+//! `copy_path`, `checksum` и `sync_parent` блокируют поток, из асинхронного кода их нужно
+//! вызывать через `spawn_blocking`.
+
+use std::fs::{self, File, Metadata};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use tracing::{trace, warn};
+use walkdir::WalkDir;
+
+const BUF_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CopyOptions {
+    /// Сверить хеш копии с исходником, а не только размер
+    pub verify_checksum: bool,
+    /// Пробовать клонировать файл (`FICLONE`), если файловая система это умеет (btrfs, xfs)
+    pub reflink: bool,
+    pub preserve: Preserve,
+}
+
+/// Какие метаданные переносить на копию
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct Preserve {
+    pub permissions: bool,
+    /// Время доступа и изменения
+    pub timestamps: bool,
+    pub xattrs: bool,
+}
+
+impl Default for Preserve {
+    fn default() -> Self {
+        Self {
+            permissions: true,
+            timestamps: true,
+            xattrs: true,
+        }
+    }
+}
+
+/// Перемещает файл или папку. Если `src` и `dest` на разных файловых системах (`EXDEV`), то
+/// копирует с сохранением метаданных, проверяет копию и только потом удаляет исходник.
+pub fn move_path(src: &Path, dest: &Path, options: &CopyOptions) -> Result<(), Error> {
+    match fs::rename(src, dest) {
+        Ok(()) => Ok(()),
+        Err(err) if err.raw_os_error() == Some(libc::EXDEV) => {
+            trace!(
+                "{:?} and {:?} are on different filesystems, copy",
+                src,
+                dest
+            );
+            move_by_copy(src, dest, options)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Перемещение копированием: исходник удаляется, только если копия собрана и проверена
+fn move_by_copy(src: &Path, dest: &Path, options: &CopyOptions) -> Result<(), Error> {
+    copy_path(src, dest, options)?;
+    remove_any(src)
+}
+
+/// Копирует файл, симлинк или папку целиком. Копия собирается во временном файле рядом с
+/// `dest` и переименовывается только после проверки, поэтому по пути `dest` никогда не
+/// окажется недописанный файл.
+pub fn copy_path(src: &Path, dest: &Path, options: &CopyOptions) -> Result<(), Error> {
+    create_replacing(dest, |tmp| copy_any(src, tmp, options))
+}
+
+/// Создает `dest` через `create` под временным именем рядом и переименовывает поверх, поэтому
+/// существующий файл по пути `dest` не пропадает ни на момент замены, ни при ошибке
+pub fn create_replacing(
+    dest: &Path,
+    create: impl FnOnce(&Path) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let tmp = tmp_path(dest)?;
+    let res = create(&tmp).and_then(|()| fs::rename(&tmp, dest));
+    if let Err(err) = res {
+        if let Err(cleanup_err) = remove_any(&tmp) {
+            if cleanup_err.kind() != ErrorKind::NotFound {
+                warn!("fail to remove temporary {:?}: {}", tmp, cleanup_err);
+            }
+        }
+        return Err(err);
+    }
+    sync_parent(dest)
+}
+
+fn copy_any(src: &Path, dest: &Path, options: &CopyOptions) -> Result<(), Error> {
+    let metadata = fs::symlink_metadata(src)?;
+    if metadata.is_dir() {
+        copy_dir(src, dest, options)
+    } else if metadata.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(src)?, dest)
+    } else {
+        copy_file(src, dest, &metadata, options)
+    }
+}
+
+fn copy_dir(src: &Path, dest: &Path, options: &CopyOptions) -> Result<(), Error> {
+    // время изменения папок выставляем в самом конце, иначе его перетрет создание файлов внутри
+    let mut dirs = vec![];
+    for entry in WalkDir::new(src).follow_links(false) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(src).map_err(Error::other)?;
+        let target = dest.join(relative);
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            fs::create_dir(&target)?;
+            dirs.push((entry.into_path(), target, metadata));
+        } else if metadata.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+        } else {
+            copy_file(entry.path(), &target, &metadata, options)?;
+        }
+    }
+    for (src_dir, dir, metadata) in dirs.iter().rev() {
+        copy_metadata(src_dir, dir, metadata, &options.preserve)?;
+    }
+    Ok(())
+}
+
+fn copy_file(
+    src: &Path,
+    dest: &Path,
+    metadata: &Metadata,
+    options: &CopyOptions,
+) -> Result<(), Error> {
+    let mut reader = File::open(src)?;
+    let mut writer = File::create(dest)?;
+    let mut hasher = blake3::Hasher::new();
+    if options.reflink && reflink(&reader, &writer) {
+        trace!("{:?} cloned to {:?}", src, dest);
+        if options.verify_checksum {
+            hasher.update_reader(&mut reader)?;
+        }
+    } else {
+        let mut buf = vec![0; BUF_SIZE];
+        loop {
+            let n = reader.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            if options.verify_checksum {
+                hasher.update(&buf[..n]);
+            }
+            writer.write_all(&buf[..n])?;
+        }
+    }
+    writer.sync_all()?;
+    drop(writer);
+
+    let copied = fs::metadata(dest)?;
+    if copied.len() != metadata.len() {
+        return Err(Error::other(format!(
+            "size mismatch after copy {:?}: {} != {}",
+            dest,
+            copied.len(),
+            metadata.len()
+        )));
+    }
+    if options.verify_checksum && checksum(dest)? != hasher.finalize() {
+        return Err(Error::other(format!(
+            "checksum mismatch after copy {:?}",
+            dest
+        )));
+    }
+    copy_metadata(src, dest, metadata, &options.preserve)
+}
+
+/// Клонирует содержимое файла без копирования данных. Если файловая система этого не умеет
+/// (или файлы на разных файловых системах), возвращает `false` и копировать нужно обычным
+/// способом.
+#[cfg(target_os = "linux")]
+fn reflink(src: &File, dest: &File) -> bool {
+    use std::os::fd::AsRawFd;
+    // SAFETY: оба дескриптора открыты и живут до конца вызова
+    let res = unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    if res != 0 {
+        trace!("reflink is not available: {}", Error::last_os_error());
+    }
+    res == 0
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_src: &File, _dest: &File) -> bool {
+    false
+}
+
+/// Переносит права, xattr и время изменения. xattr поддерживаются не везде (например tmpfs без
+/// user xattr), поэтому ошибки на них только логируются.
+fn copy_metadata(
+    src: &Path,
+    dest: &Path,
+    metadata: &Metadata,
+    preserve: &Preserve,
+) -> Result<(), Error> {
+    if preserve.xattrs {
+        copy_xattrs(src, dest);
+    }
+    if preserve.permissions {
+        fs::set_permissions(dest, metadata.permissions())?;
+    }
+    if preserve.timestamps {
+        filetime::set_file_times(
+            dest,
+            FileTime::from_last_access_time(metadata),
+            FileTime::from_last_modification_time(metadata),
+        )?;
+    }
+    Ok(())
+}
+
+fn copy_xattrs(src: &Path, dest: &Path) {
+    match xattr::list(src) {
+        Ok(names) => {
+            for name in names {
+                let res = xattr::get(src, &name)
+                    .and_then(|value| xattr::set(dest, &name, &value.unwrap_or_default()));
+                if let Err(err) = res {
+                    warn!("fail to copy xattr {:?} to {:?}: {}", name, dest, err);
+                }
+            }
+        }
+        Err(err) => trace!("fail to list xattrs of {:?}: {}", src, err),
+    }
+}
+
+pub fn checksum(path: &Path) -> Result<blake3::Hash, Error> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize())
+}
+
+pub fn tmp_path(dest: &Path) -> Result<PathBuf, Error> {
+    let file_name = dest.file_name().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid destination {:?}", dest),
+        )
+    })?;
+    Ok(dest.with_file_name(format!(
+        ".{}.triggerfs-{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    )))
+}
+
+/// Временный файл из `tmp_path` этого процесса, события по нему обрабатывать не нужно
+pub fn is_tmp_path(path: &Path) -> bool {
+    let suffix = format!(".triggerfs-{}", std::process::id());
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(&suffix))
+}
+
+fn remove_any(path: &Path) -> Result<(), Error> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// fsync папки нужен, чтобы rename пережил падение системы
+pub fn sync_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_copy_dir_preserves_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("nested/file.txt"), "content").unwrap();
+        fs::set_permissions(
+            src.join("nested/file.txt"),
+            fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink("nested/file.txt", src.join("link")).unwrap();
+        let mtime = FileTime::from_unix_time(1_700_000_000, 0);
+        filetime::set_file_mtime(src.join("nested/file.txt"), mtime).unwrap();
+
+        let dest = dir.path().join("dest");
+        let options = CopyOptions {
+            verify_checksum: true,
+            reflink: true,
+            ..Default::default()
+        };
+        copy_path(&src, &dest, &options).unwrap();
+
+        let copied = dest.join("nested/file.txt");
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "content");
+        let metadata = fs::metadata(&copied).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o640);
+        assert_eq!(FileTime::from_last_modification_time(&metadata), mtime);
+        assert_eq!(
+            fs::read_link(dest.join("link")).unwrap(),
+            Path::new("nested/file.txt")
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    fn verified() -> CopyOptions {
+        CopyOptions {
+            verify_checksum: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_move_file_by_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("file.txt");
+        fs::write(&src, "content").unwrap();
+        let mtime = FileTime::from_unix_time(1_700_000_000, 0);
+        filetime::set_file_mtime(&src, mtime).unwrap();
+
+        let dest = dir.path().join("moved.txt");
+        move_by_copy(&src, &dest, &verified()).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "content");
+        assert_eq!(
+            FileTime::from_last_modification_time(&fs::metadata(&dest).unwrap()),
+            mtime
+        );
+    }
+
+    #[test]
+    fn test_move_dir_by_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("nested/file.txt"), "content").unwrap();
+        std::os::unix::fs::symlink("nested/file.txt", src.join("link")).unwrap();
+
+        let dest = dir.path().join("dest");
+        move_by_copy(&src, &dest, &verified()).unwrap();
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dest.join("link")).unwrap(), "content");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_move_symlink_by_copy() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target.txt");
+        fs::write(&target, "content").unwrap();
+        let src = dir.path().join("link");
+        std::os::unix::fs::symlink(&target, &src).unwrap();
+
+        // переносится сама ссылка, файл, на который она указывает, остается на месте
+        let dest = dir.path().join("moved");
+        move_by_copy(&src, &dest, &verified()).unwrap();
+        assert!(fs::symlink_metadata(&src).is_err());
+        assert_eq!(fs::read_link(&dest).unwrap(), target);
+        assert_eq!(fs::read_to_string(&target).unwrap(), "content");
+    }
+
+    #[test]
+    fn test_move_by_copy_keeps_source_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("nested")).unwrap();
+        fs::write(src.join("nested/file.txt"), "content").unwrap();
+        let file = src.join("nested/file.txt");
+
+        // копию некуда положить: исходники целы, временных файлов не осталось
+        let missing = dir.path().join("missing/dest");
+        assert!(move_by_copy(&file, &missing, &verified()).is_err());
+        assert!(move_by_copy(&src, &missing, &verified()).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+        // папку нельзя заменить файлом, собранная копия убирается
+        let occupied = dir.path().join("occupied");
+        fs::create_dir_all(occupied.join("inner")).unwrap();
+        assert!(move_by_copy(&file, &occupied, &verified()).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "content");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+        assert_eq!(fs::read_dir(&occupied).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,253 @@
+//! Корзина по спецификации freedesktop.org
+//! (<https://specifications.freedesktop.org/trash-spec/latest/>). Файлы с того же раздела, что и
+//! домашняя папка, попадают в `$XDG_DATA_HOME/Trash`, с других разделов в `$topdir/.Trash/$uid`
+//! или `$topdir/.Trash-$uid`, чтобы не копировать их между файловыми системами.
+//!
+//! `TrashAction::execute` сам переносит работу в `spawn_blocking`, а `trash` и `home_trash_dir`
+//! блокируют поток, из асинхронного кода их нужно вызывать через `spawn_blocking`.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tracing::{info, trace, warn};
+
+use super::pipeline::Applied;
+use super::transfer::{self, CopyOptions};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct TrashAction {}
+
+impl TrashAction {
+    /// Без `trash_dir` файлы с раздела домашней папки попадают в `home_trash_dir`
+    pub async fn execute(
+        &self,
+        path: &Path,
+        trash_dir: Option<&Path>,
+        dry_run: bool,
+    ) -> Result<Option<Applied>, Error> {
+        let from = path.to_path_buf();
+        if dry_run {
+            info!("[dry-run] move {:?} to trash", path);
+            return Ok(Some(Applied::Trashed {
+                from,
+                trashed: None,
+            }));
+        }
+        let path = path.to_path_buf();
+        let trash_dir = trash_dir.map(Path::to_path_buf);
+        let trashed = tokio::task::spawn_blocking(move || {
+            let home_trash = match trash_dir {
+                Some(trash_dir) => trash_dir,
+                None => home_trash_dir()?,
+            };
+            trash(&path, &home_trash)
+        })
+        .await??;
+        trace!("moved to trash {:?}", trashed.file);
+        Ok(Some(Applied::Trashed {
+            from,
+            trashed: Some(trashed),
+        }))
+    }
+}
+
+/// Куда попал файл в корзине
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Trashed {
+    pub file: PathBuf,
+    /// Файл `.trashinfo` с исходным путем и временем удаления
+    pub info: PathBuf,
+}
+
+/// `$XDG_DATA_HOME/Trash`, по умолчанию `~/.local/share/Trash`
+pub fn home_trash_dir() -> Result<PathBuf, Error> {
+    if let Some(data_home) = std::env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+        return Ok(PathBuf::from(data_home).join("Trash"));
+    }
+    let home = std::env::var_os("HOME")
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "neither XDG_DATA_HOME nor HOME is set"))?;
+    Ok(PathBuf::from(home).join(".local/share/Trash"))
+}
+
+/// Перемещает файл или папку в корзину, подходящую для раздела, на котором они лежат
+pub fn trash(path: &Path, home_trash: &Path) -> Result<Trashed, Error> {
+    let path = std::path::absolute(path)?;
+    let metadata = fs::symlink_metadata(&path)?;
+
+    let home_dev = create_trash_dir(home_trash)
+        .and_then(|()| fs::metadata(home_trash))
+        .map(|home| home.dev());
+    if matches!(home_dev, Ok(dev) if dev == metadata.dev()) {
+        return trash_into(&path, home_trash, None);
+    }
+
+    let topdir = mount_point(&path, metadata.dev())?;
+    match topdir_trash(&topdir) {
+        Ok(trash_dir) => return trash_into(&path, &trash_dir, Some(&topdir)),
+        Err(err) => warn!(
+            "fail to use trash on {:?}: {}, fall back to home trash",
+            topdir, err
+        ),
+    }
+    home_dev?;
+    trash_into(&path, home_trash, None)
+}
+
+/// Кладет файл в корзину `trash_dir`. Если задан `topdir`, то путь в `.trashinfo` пишется
+/// относительно него, как требует спецификация для корзин на разделах.
+fn trash_into(path: &Path, trash_dir: &Path, topdir: Option<&Path>) -> Result<Trashed, Error> {
+    let files = trash_dir.join("files");
+    let info_dir = trash_dir.join("info");
+    let original = match topdir {
+        Some(topdir) => path.strip_prefix(topdir).unwrap_or(path),
+        None => path,
+    };
+    let content = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        url_encode(original),
+        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+
+    let name = path
+        .file_name()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid path {:?}", path)))?
+        .to_string_lossy()
+        .into_owned();
+    for n in 0.. {
+        let candidate = if n == 0 {
+            name.clone()
+        } else {
+            numbered(&name, n)
+        };
+        let info = info_dir.join(format!("{}.trashinfo", candidate));
+        // .trashinfo создается атомарно первым, он и резервирует имя в корзине
+        let mut info_file = match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+        let file = files.join(&candidate);
+        if fs::symlink_metadata(&file).is_ok() {
+            // чужой мусор без .trashinfo, имя занято
+            drop(info_file);
+            fs::remove_file(&info)?;
+            continue;
+        }
+        let res = info_file
+            .write_all(content.as_bytes())
+            .and_then(|()| info_file.sync_all())
+            .and_then(|()| transfer::move_path(path, &file, &CopyOptions::default()));
+        if let Err(err) = res {
+            let _ = fs::remove_file(&info);
+            return Err(err);
+        }
+        return Ok(Trashed { file, info });
+    }
+    unreachable!()
+}
+
+/// `name.ext` -> `name (n).ext`, как и при разрешении конфликтов имен
+fn numbered(name: &str, n: usize) -> String {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => format!("{} ({}){}", &name[..dot], n, &name[dot..]),
+        _ => format!("{} ({})", name, n),
+    }
+}
+
+fn create_trash_dir(trash_dir: &Path) -> Result<(), Error> {
+    for sub in ["files", "info"] {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(trash_dir.join(sub))?;
+    }
+    Ok(())
+}
+
+/// Корзина на разделе: `$topdir/.Trash/$uid`, если администратор создал `.Trash` с sticky bit,
+/// иначе `$topdir/.Trash-$uid`
+fn topdir_trash(topdir: &Path) -> Result<PathBuf, Error> {
+    // SAFETY: getuid всегда успешен
+    let uid = unsafe { libc::getuid() };
+    let shared = topdir.join(".Trash");
+    match fs::symlink_metadata(&shared) {
+        Ok(metadata)
+            if metadata.is_dir()
+                && !metadata.is_symlink()
+                && metadata.permissions().mode() & libc::S_ISVTX != 0 =>
+        {
+            let trash_dir = shared.join(uid.to_string());
+            match create_trash_dir(&trash_dir) {
+                Ok(()) => return Ok(trash_dir),
+                Err(err) => trace!("fail to use {:?}: {}", trash_dir, err),
+            }
+        }
+        Ok(_) => warn!("{:?} is not a sticky directory, ignore it", shared),
+        Err(_) => {}
+    }
+    let trash_dir = topdir.join(format!(".Trash-{}", uid));
+    create_trash_dir(&trash_dir)?;
+    Ok(trash_dir)
+}
+
+/// Ищет точку монтирования, поднимаясь по родителям, пока не сменится устройство
+fn mount_point(path: &Path, dev: u64) -> Result<PathBuf, Error> {
+    let mut mount = path.parent().unwrap_or(path).canonicalize()?;
+    while let Some(parent) = mount.parent() {
+        if fs::metadata(parent)?.dev() != dev {
+            break;
+        }
+        mount = parent.to_path_buf();
+    }
+    Ok(mount)
+}
+
+/// Кодирует путь для `.trashinfo` как часть URL (RFC 2396), `/` не кодируется
+fn url_encode(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    let mut out = String::new();
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~!*'()".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash() {
+        let dir = tempfile::tempdir().unwrap();
+        let home_trash = dir.path().join("data/Trash");
+        let file = dir.path().join("отчет 1.txt");
+        std::fs::write(&file, "content").unwrap();
+
+        let trashed = trash(&file, &home_trash).unwrap();
+        assert!(!file.exists());
+        assert_eq!(trashed.file, home_trash.join("files/отчет 1.txt"));
+        assert_eq!(std::fs::read_to_string(&trashed.file).unwrap(), "content");
+        let info = std::fs::read_to_string(&trashed.info).unwrap();
+        let expected = format!(
+            "Path={}/%D0%BE%D1%82%D1%87%D0%B5%D1%82%201.txt\n",
+            dir.path().display()
+        );
+        assert!(info.starts_with("[Trash Info]\n"), "{}", info);
+        assert!(info.contains(&expected), "{}", info);
+        assert!(info.contains("DeletionDate="), "{}", info);
+
+        // имя уже занято в корзине
+        std::fs::create_dir(&file).unwrap();
+        let trashed = trash(&file, &home_trash).unwrap();
+        assert_eq!(trashed.file, home_trash.join("files/отчет 1 (1).txt"));
+        assert!(trashed.file.is_dir());
+        assert!(home_trash.join("info/отчет 1 (1).txt.trashinfo").exists());
+    }
+}
@@ -0,0 +1,703 @@
+//! Индекс содержимого файлов для поиска дубликатов. Папку поиска индекс просматривает целиком
+//! один раз, при первом поиске в ней, и раскладывает ее файлы по размеру, дальше папка
+//! обновляется по событиям файловой системы через `update`. Поиск сравнивает только файлы того же
+//! размера по двум хешам BLAKE3: быстрому по началу и концу файла и полному, полный считается,
+//! если совпал быстрый. Хеши пересчитываются, если у файла изменились размер или время изменения,
+//! и записываются в файл не чаще раза в `SAVE_INTERVAL`, поэтому переживают перезапуск.
+//!
+//! Методы читают диск, из асинхронного кода их нужно вызывать через `spawn_blocking`.
+
+use notify::event::ModifyKind;
+use notify::{Event, EventKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{trace, warn};
+use walkdir::WalkDir;
+
+/// Сколько байт с начала и с конца файла входит в быстрый хеш
+const QUICK_HASH_BYTES: u64 = 64 * 1024;
+/// Как часто новые хеши записываются в файл, остальное дописывает `flush`
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Индексы, открытые через `ContentIndex::shared`, по пути файла
+fn opened() -> MutexGuard<'static, HashMap<PathBuf, Arc<ContentIndex>>> {
+    static OPENED: OnceLock<Mutex<HashMap<PathBuf, Arc<ContentIndex>>>> = OnceLock::new();
+    OPENED
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+}
+
+pub struct ContentIndex {
+    /// `None` для индекса только в памяти
+    path: Option<PathBuf>,
+    state: Mutex<State>,
+    /// Файл пишется без блокировки `state`, этот мьютекс не дает двум записям перемешаться
+    saving: Mutex<()>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Просмотренные папки, их файлы дальше обновляются через `update`
+    roots: Vec<PathBuf>,
+    entries: HashMap<PathBuf, IndexEntry>,
+    /// Файлы из просмотренных папок по размеру
+    by_size: HashMap<u64, HashSet<PathBuf>>,
+    /// Есть хеши, которых еще нет в файле
+    dirty: bool,
+    saved_at: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct IndexEntry {
+    size: u64,
+    modified: SystemTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    quick: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    full: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    path: PathBuf,
+    #[serde(flatten)]
+    entry: IndexEntry,
+}
+
+impl fmt::Debug for ContentIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContentIndex")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ContentIndex {
+    /// Читает индекс из файла, если его еще нет, то начинает с пустого
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let entries = match fs::read(path) {
+            Ok(data) => match serde_json::from_slice::<Vec<Record>>(&data) {
+                Ok(records) => records
+                    .into_iter()
+                    .map(|record| (record.path, record.entry))
+                    .collect(),
+                // это только кеш хешей, его можно посчитать заново
+                Err(err) => {
+                    warn!(
+                        "{}: broken content index, start empty: {}",
+                        path.display(),
+                        err
+                    );
+                    HashMap::new()
+                }
+            },
+            Err(err) if err.kind() == ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self::new(
+            Some(path.to_path_buf()),
+            State {
+                entries,
+                ..Default::default()
+            },
+        ))
+    }
+
+    /// Индекс из файла, общий для всех исполнителей процесса
+    pub fn shared(path: &Path) -> Result<Arc<Self>, Error> {
+        let mut opened = opened();
+        if let Some(index) = opened.get(path) {
+            return Ok(index.clone());
+        }
+        let index = Arc::new(Self::open(path)?);
+        opened.insert(path.to_path_buf(), index.clone());
+        Ok(index)
+    }
+
+    /// Записывает все индексы из `shared`. Нужно при остановке по сигналу: `exit` завершает
+    /// процесс раньше, чем исполнители успевают сделать `flush` сами.
+    pub fn flush_shared() {
+        let indexes = opened().values().cloned().collect::<Vec<_>>();
+        for index in indexes {
+            if let Err(err) = index.flush() {
+                warn!("fail to save content index {:?}: {}", index.path, err);
+            }
+        }
+    }
+
+    /// Индекс без файла, когда `content_index_path` не задан: хеши живут до перезапуска
+    pub fn in_memory() -> Arc<Self> {
+        static MEMORY: OnceLock<Arc<ContentIndex>> = OnceLock::new();
+        MEMORY
+            .get_or_init(|| Arc::new(Self::new(None, State::default())))
+            .clone()
+    }
+
+    fn new(path: Option<PathBuf>, state: State) -> Self {
+        Self {
+            path,
+            state: Mutex::new(state),
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// Обновляет файлы из события, если они в просмотренных папках. Новые и переименованные
+    /// папки просматриваются целиком, удаленные убирают из индекса все свои файлы.
+    pub fn update(&self, event: &Event) {
+        let walk_dirs = matches!(
+            event.kind,
+            EventKind::Any | EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+        );
+        for path in &event.paths {
+            if !self.lock().covers(path) {
+                continue;
+            }
+            match fs::symlink_metadata(path) {
+                Ok(metadata) if metadata.is_file() => {
+                    if let Ok(modified) = metadata.modified() {
+                        self.lock().insert(path.clone(), metadata.len(), modified);
+                    }
+                }
+                Ok(metadata) if metadata.is_dir() => {
+                    if walk_dirs {
+                        let files = walk(path);
+                        let mut state = self.lock();
+                        for (path, size, modified) in files {
+                            state.insert(path, size, modified);
+                        }
+                    }
+                }
+                Ok(_) => {
+                    self.lock().remove(path);
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    let mut state = self.lock();
+                    if !state.remove(path) {
+                        state.remove_dir(path);
+                    }
+                }
+                Err(err) => trace!("skip {:?} in content index: {}", path, err),
+            }
+        }
+        self.save_if_due();
+    }
+
+    /// Ищет в `dirs` файлы с тем же содержимым, что у `file`. Оригиналом считается самая старая
+    /// копия по времени изменения, при равном времени первая по пути, поэтому из нескольких
+    /// копий оригинал ровно один. Возвращает оригинал, если `file` не он. Пустые файлы и
+    /// жесткие ссылки на тот же файл дубликатами не считаются.
+    pub fn find_original(&self, file: &Path, dirs: &[PathBuf]) -> Result<Option<PathBuf>, Error> {
+        let metadata = fs::metadata(file)?;
+        if !metadata.is_file() || metadata.len() == 0 {
+            return Ok(None);
+        }
+        let (size, modified) = (metadata.len(), metadata.modified()?);
+        self.scan(dirs);
+
+        // под блокировкой только выбираются кандидаты, файлы читаются без нее
+        let (mut own, candidates) = {
+            let mut state = self.lock();
+            if state.covers(file) {
+                state.insert(file.to_path_buf(), size, modified);
+            }
+            let own = state
+                .cached(file, size, modified)
+                .unwrap_or_else(|| IndexEntry::new(size, modified));
+            let candidates = state
+                .by_size
+                .get(&size)
+                .into_iter()
+                .flatten()
+                .filter(|path| *path != file && dirs.iter().any(|dir| path.starts_with(dir)))
+                .map(|path| (path.clone(), state.entries[path].clone()))
+                .collect::<Vec<_>>();
+            (own, candidates)
+        };
+
+        // событие о файле могло еще не прийти, поэтому кандидаты сверяются с диском
+        let mut gone = vec![];
+        let mut changed = vec![];
+        let mut checked = vec![];
+        for (path, mut entry) in candidates {
+            let other = match fs::symlink_metadata(&path) {
+                Ok(other) if other.is_file() => other,
+                Ok(_) => {
+                    gone.push(path);
+                    continue;
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {
+                    gone.push(path);
+                    continue;
+                }
+                Err(err) => {
+                    trace!("skip {:?} while looking for duplicates: {}", path, err);
+                    continue;
+                }
+            };
+            let Ok(other_modified) = other.modified() else {
+                continue;
+            };
+            if other.len() != entry.size || other_modified != entry.modified {
+                changed.push((path.clone(), other.len(), other_modified));
+                if other.len() != size {
+                    continue;
+                }
+                entry = IndexEntry::new(other.len(), other_modified);
+            }
+            if other.dev() != metadata.dev() || other.ino() != metadata.ino() {
+                checked.push((path, entry, false));
+            }
+        }
+
+        if !checked.is_empty() {
+            let quick = hash(file, &mut own, HashKind::Quick);
+            for (path, entry, same) in &mut checked {
+                *same = quick.is_some() && hash(path, entry, HashKind::Quick) == quick;
+            }
+            if checked.iter().any(|(_, _, same)| *same) {
+                let full = hash(file, &mut own, HashKind::Full);
+                for (path, entry, same) in checked.iter_mut().filter(|(_, _, same)| *same) {
+                    *same = full.is_some() && hash(path, entry, HashKind::Full) == full;
+                }
+            }
+        }
+
+        {
+            let mut state = self.lock();
+            for path in &gone {
+                state.remove(path);
+            }
+            for (path, size, modified) in changed {
+                state.insert(path, size, modified);
+            }
+            state.store(file, &own);
+            for (path, entry, _) in &checked {
+                state.store(path, entry);
+            }
+        }
+        self.save_if_due();
+
+        let original = checked
+            .into_iter()
+            .filter(|(_, _, same)| *same)
+            .map(|(path, entry, _)| (entry.modified, path))
+            .chain([(modified, file.to_path_buf())])
+            .min()
+            .map(|(_, path)| path);
+        Ok(original.filter(|original| original != file))
+    }
+
+    /// Записывает в файл хеши, посчитанные после прошлой записи
+    pub fn flush(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let _saving = self.saving.lock().unwrap_or_else(|err| err.into_inner());
+        let data = {
+            let mut state = self.lock();
+            if !state.dirty {
+                return Ok(());
+            }
+            // записи без хешей восстановит просмотр папок
+            let records = state
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.quick.is_some() || entry.full.is_some())
+                .map(|(path, entry)| Record {
+                    path: path.clone(),
+                    entry: entry.clone(),
+                })
+                .collect::<Vec<_>>();
+            state.dirty = false;
+            state.saved_at = Some(Instant::now());
+            serde_json::to_vec(&records).map_err(Error::other)?
+        };
+        // пишет во временный файл и переименовывает, чтобы при падении не оставить половину
+        let tmp = path.with_extension("tmp");
+        let saved = fs::write(&tmp, data).and_then(|_| fs::rename(&tmp, path));
+        if saved.is_err() {
+            self.lock().dirty = true;
+        }
+        saved
+    }
+
+    fn save_if_due(&self) {
+        let due = {
+            let state = self.lock();
+            state.dirty
+                && state
+                    .saved_at
+                    .is_none_or(|saved_at| saved_at.elapsed() >= SAVE_INTERVAL)
+        };
+        if due {
+            if let Err(err) = self.flush() {
+                warn!("fail to save content index {:?}: {}", self.path, err);
+            }
+        }
+    }
+
+    /// Просматривает папки из `dirs`, которых еще нет в индексе
+    fn scan(&self, dirs: &[PathBuf]) {
+        let missing = {
+            let state = self.lock();
+            dirs.iter()
+                .filter(|dir| !state.covers(dir))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        for dir in missing {
+            let files = walk(&dir);
+            let mut state = self.lock();
+            // другой поиск мог просмотреть ее раньше
+            if state.covers(&dir) {
+                continue;
+            }
+            let seen = files
+                .iter()
+                .map(|(path, _, _)| path.clone())
+                .collect::<HashSet<_>>();
+            let vanished = state
+                .entries
+                .keys()
+                .filter(|path| path.starts_with(&dir) && !seen.contains(*path))
+                .cloned()
+                .collect::<Vec<_>>();
+            for path in &vanished {
+                state.remove(path);
+            }
+            for (path, size, modified) in files {
+                state.insert(path, size, modified);
+            }
+            state.roots.push(dir);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl State {
+    fn covers(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+
+    /// Добавляет файл или обновляет его размер и время, хеши измененного файла сбрасываются
+    fn insert(&mut self, path: PathBuf, size: u64, modified: SystemTime) {
+        if let Some(entry) = self.entries.get_mut(&path) {
+            if entry.size != size || entry.modified != modified {
+                let old = std::mem::replace(entry, IndexEntry::new(size, modified));
+                self.dirty |= old.quick.is_some() || old.full.is_some();
+                self.forget_size(&path, old.size);
+            }
+        } else {
+            self.entries
+                .insert(path.clone(), IndexEntry::new(size, modified));
+        }
+        self.by_size.entry(size).or_default().insert(path);
+    }
+
+    /// Убирает файл, `false` если его не было в индексе
+    fn remove(&mut self, path: &Path) -> bool {
+        let Some(entry) = self.entries.remove(path) else {
+            return false;
+        };
+        self.dirty |= entry.quick.is_some() || entry.full.is_some();
+        self.forget_size(path, entry.size);
+        true
+    }
+
+    fn remove_dir(&mut self, dir: &Path) {
+        let paths = self
+            .entries
+            .keys()
+            .filter(|path| path.starts_with(dir))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in paths {
+            self.remove(&path);
+        }
+    }
+
+    fn forget_size(&mut self, path: &Path, size: u64) {
+        if let Some(paths) = self.by_size.get_mut(&size) {
+            paths.remove(path);
+            if paths.is_empty() {
+                self.by_size.remove(&size);
+            }
+        }
+    }
+
+    /// Запись файла, если она соответствует его размеру и времени изменения
+    fn cached(&self, path: &Path, size: u64, modified: SystemTime) -> Option<IndexEntry> {
+        self.entries
+            .get(path)
+            .filter(|entry| entry.size == size && entry.modified == modified)
+            .cloned()
+    }
+
+    /// Запоминает посчитанные хеши, если файл не изменился, пока они считались
+    fn store(&mut self, path: &Path, hashed: &IndexEntry) {
+        let Some(entry) = self.entries.get_mut(path) else {
+            return;
+        };
+        if entry.size != hashed.size || entry.modified != hashed.modified {
+            return;
+        }
+        if entry.quick.is_none() && hashed.quick.is_some() {
+            entry.quick.clone_from(&hashed.quick);
+            self.dirty = true;
+        }
+        if entry.full.is_none() && hashed.full.is_some() {
+            entry.full.clone_from(&hashed.full);
+            self.dirty = true;
+        }
+    }
+}
+
+impl IndexEntry {
+    fn new(size: u64, modified: SystemTime) -> Self {
+        Self {
+            size,
+            modified,
+            quick: None,
+            full: None,
+        }
+    }
+}
+
+/// Обычные файлы в папке с размером и временем изменения
+fn walk(dir: &Path) -> Vec<(PathBuf, u64, SystemTime)> {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|entry| {
+            entry
+                .map_err(|err| trace!("skip while indexing: {}", err))
+                .ok()
+        })
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let modified = metadata.modified().ok()?;
+            Some((entry.into_path(), metadata.len(), modified))
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+enum HashKind {
+    Quick,
+    Full,
+}
+
+/// Хеш из записи, если его еще нет, то считается и запоминается в ней
+fn hash(path: &Path, entry: &mut IndexEntry, kind: HashKind) -> Option<String> {
+    let slot = match kind {
+        HashKind::Quick => &mut entry.quick,
+        HashKind::Full => &mut entry.full,
+    };
+    if slot.is_none() {
+        let hash = match kind {
+            HashKind::Quick => quick_hash(path, entry.size),
+            HashKind::Full => full_hash(path),
+        };
+        *slot = hash
+            .map_err(|err| trace!("fail to hash {:?}: {}", path, err))
+            .ok();
+    }
+    slot.clone()
+}
+
+/// Хеш начала и конца файла, отсекает большую часть файлов одного размера без полного чтения
+fn quick_hash(path: &Path, size: u64) -> Result<String, Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader((&mut file).take(QUICK_HASH_BYTES))?;
+    if size > QUICK_HASH_BYTES * 2 {
+        file.seek(SeekFrom::End(-(QUICK_HASH_BYTES as i64)))?;
+        hasher.update_reader(file)?;
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn full_hash(path: &Path) -> Result<String, Error> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, RemoveKind, RenameMode};
+
+    fn set_modified(path: &Path, secs: u64) {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    fn event(kind: EventKind, path: &Path) -> Event {
+        Event::new(kind).add_path(path.to_path_buf())
+    }
+
+    /// `Downloads` с отчетом, его более новой копией и файлом того же размера, у которого
+    /// совпадают начало и конец
+    fn downloads(dir: &Path) -> (PathBuf, PathBuf, PathBuf, PathBuf) {
+        let downloads = dir.join("Downloads");
+        fs::create_dir_all(downloads.join("old")).unwrap();
+        let report = downloads.join("old/report.pdf");
+        let copy = downloads.join("report (1).pdf");
+        let other = downloads.join("other.pdf");
+        let mut data = vec![7u8; 300 * 1024];
+        fs::write(&report, &data).unwrap();
+        fs::write(&copy, &data).unwrap();
+        data[150 * 1024] = 8;
+        fs::write(&other, &data).unwrap();
+        set_modified(&report, 1000);
+        set_modified(&copy, 2000);
+        set_modified(&other, 500);
+        (downloads, report, copy, other)
+    }
+
+    #[test]
+    fn test_find_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let (downloads, report, copy, other) = downloads(dir.path());
+        let dirs = [downloads];
+        let index = ContentIndex::open(&dir.path().join("state/index.json")).unwrap();
+        // большие файлы с одинаковыми началом и концом различает только полный хеш
+        assert_eq!(
+            index.find_original(&copy, &dirs).unwrap(),
+            Some(report.clone())
+        );
+        assert_eq!(index.find_original(&report, &dirs).unwrap(), None);
+        assert_eq!(index.find_original(&other, &dirs).unwrap(), None);
+
+        // жесткая ссылка на оригинал уже не дубликат
+        fs::remove_file(&copy).unwrap();
+        fs::hard_link(&report, &copy).unwrap();
+        assert_eq!(index.find_original(&copy, &dirs).unwrap(), None);
+        // пропавший файл того же размера уходит из индекса и без события
+        fs::remove_file(&other).unwrap();
+        index.find_original(&report, &dirs).unwrap();
+        assert!(!index.lock().entries.contains_key(&other));
+    }
+
+    #[test]
+    fn test_same_modified() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        for path in [&a, &b] {
+            fs::write(path, "same").unwrap();
+            set_modified(path, 3000);
+        }
+        let index = ContentIndex::new(None, State::default());
+        let dirs = [dir.path().to_path_buf()];
+        // при одинаковом времени оригинал первый по пути
+        assert_eq!(index.find_original(&b, &dirs).unwrap(), Some(a.clone()));
+        assert_eq!(index.find_original(&a, &dirs).unwrap(), None);
+    }
+
+    #[test]
+    fn test_saved_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (downloads, report, copy, _) = downloads(dir.path());
+        let dirs = [downloads];
+        let index_path = dir.path().join("state/index.json");
+        let index = ContentIndex::open(&index_path).unwrap();
+        // первые хеши записываются сразу
+        index.find_original(&copy, &dirs).unwrap();
+        let saved = ContentIndex::open(&index_path).unwrap();
+        assert_eq!(saved.lock().entries.len(), 3);
+        assert!(saved.lock().entries[&copy].full.is_some());
+
+        // следующие ждут `SAVE_INTERVAL` или `flush`
+        fs::write(&copy, "changed").unwrap();
+        index.find_original(&report, &dirs).unwrap();
+        let saved = ContentIndex::open(&index_path).unwrap();
+        assert!(saved.lock().entries[&copy].full.is_some());
+        index.flush().unwrap();
+        let saved = ContentIndex::open(&index_path).unwrap();
+        assert!(!saved.lock().entries.contains_key(&copy));
+        assert!(saved.lock().entries[&report].quick.is_some());
+    }
+
+    #[test]
+    fn test_flush_shared() {
+        let dir = tempfile::tempdir().unwrap();
+        let (downloads, report, copy, _) = downloads(dir.path());
+        let dirs = [downloads];
+        let index_path = dir.path().join("state/index.json");
+        let index = ContentIndex::shared(&index_path).unwrap();
+        index.find_original(&copy, &dirs).unwrap();
+        fs::write(&copy, "changed").unwrap();
+        index.find_original(&report, &dirs).unwrap();
+
+        // так индекс дописывается при остановке по SIGTERM и SIGINT
+        ContentIndex::flush_shared();
+        let saved = ContentIndex::open(&index_path).unwrap();
+        assert!(!saved.lock().entries.contains_key(&copy));
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let (downloads, report, copy, _) = downloads(dir.path());
+        let dirs = [downloads.clone()];
+        let index = ContentIndex::new(None, State::default());
+        index.find_original(&report, &dirs).unwrap();
+
+        // новый файл виден поиску только после события
+        let new = downloads.join("new.pdf");
+        fs::copy(&report, &new).unwrap();
+        set_modified(&new, 3000);
+        assert_eq!(index.find_original(&report, &dirs).unwrap(), None);
+        assert!(!index.lock().by_size[&(300 * 1024)].contains(&new));
+        index.update(&event(EventKind::Create(CreateKind::File), &new));
+        assert!(index.lock().by_size[&(300 * 1024)].contains(&new));
+
+        // перенесенная папка просматривается целиком, старые пути уходят
+        let moved = dir.path().join("Downloads/archive");
+        fs::rename(downloads.join("old"), &moved).unwrap();
+        let moved_report = moved.join("report.pdf");
+        index.update(&event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)),
+            &downloads.join("old"),
+        ));
+        index.update(&event(
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)),
+            &moved,
+        ));
+        assert!(!index.lock().entries.contains_key(&report));
+        assert!(index.lock().entries.contains_key(&moved_report));
+        assert_eq!(
+            index.find_original(&copy, &dirs).unwrap(),
+            Some(moved_report)
+        );
+
+        // удаленный файл уходит, файлы вне просмотренных папок не попадают в индекс
+        fs::remove_file(&new).unwrap();
+        index.update(&event(EventKind::Remove(RemoveKind::File), &new));
+        assert!(!index.lock().entries.contains_key(&new));
+        let outside = dir.path().join("outside.pdf");
+        fs::write(&outside, "outside").unwrap();
+        index.update(&event(EventKind::Create(CreateKind::File), &outside));
+        assert!(!index.lock().entries.contains_key(&outside));
+    }
+}
@@ -44,7 +44,7 @@ impl FsWatcher {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum RecursiveModeInernal {
     Recursive,
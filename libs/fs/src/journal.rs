@@ -0,0 +1,220 @@
+//! Журнал выполненных операций. Каждая операция пишется отдельной строкой JSON сразу после
+//! выполнения, поэтому журнал переживает падение процесса. Отмена операции тоже дописывается в
+//! журнал, файл никогда не переписывается: демон и `TriggerFS undo` могут работать с ним
+//! одновременно.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::warn;
+
+pub use crate::actions::Applied;
+
+/// Запись журнала о выполненной операции
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Entry {
+    pub id: String,
+    /// Локальное время выполнения в RFC 3339
+    pub time: String,
+    /// Имя правила, которое выполнило операцию
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    pub applied: Applied,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Done(Entry),
+    Undone(String),
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.time)?;
+        if let Some(rule) = &self.rule {
+            write!(f, "[{}] ", rule)?;
+        }
+        match &self.applied {
+            Applied::Moved { from, to } => write!(f, "moved {:?} to {:?}", from, to),
+            Applied::Copied { to } => write!(f, "copied to {:?}", to),
+            Applied::Linked { link } => write!(f, "linked {:?}", link),
+            Applied::Trashed { from, .. } => write!(f, "trashed {:?}", from),
+            Applied::Deleted { path } => write!(f, "deleted {:?}", path),
+            Applied::Archived { from, archive, .. } => {
+                write!(f, "archived {:?} to {:?}", from, archive)
+            }
+            Applied::Extracted { from, to, .. } => write!(f, "extracted {:?} to {:?}", from, to),
+            Applied::Converted { from, to, .. } => write!(f, "converted {:?} to {:?}", from, to),
+            Applied::Deduplicated { path, original } => {
+                write!(f, "replaced {:?} with a link to {:?}", path, original)
+            }
+            Applied::Modified { path } => write!(f, "modified {:?}", path),
+            Applied::Command { command } => write!(f, "ran `{}`", command),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// Открывает журнал на дозапись, создавая файл и папки при необходимости
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Записывает выполненную операцию, возвращает ее id
+    pub fn record(&self, rule: Option<&str>, applied: &Applied) -> Result<String, Error> {
+        let entry = Entry {
+            id: new_id(),
+            time: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            rule: rule.map(str::to_owned),
+            applied: applied.clone(),
+        };
+        let id = entry.id.clone();
+        self.append(&Record::Done(entry))?;
+        Ok(id)
+    }
+
+    pub fn mark_undone(&self, id: &str) -> Result<(), Error> {
+        self.append(&Record::Undone(id.to_owned()))
+    }
+
+    fn append(&self, record: &Record) -> Result<(), Error> {
+        let mut line = serde_json::to_string(record).map_err(Error::other)?;
+        line.push('\n');
+        // строка пишется одним write в файл с O_APPEND, поэтому записи разных процессов не
+        // перемешиваются
+        let mut file = self.file.lock().unwrap_or_else(|err| err.into_inner());
+        file.write_all(line.as_bytes())?;
+        file.sync_data()
+    }
+
+    /// Операции, которые еще не отменены, в порядке выполнения
+    pub fn entries(&self) -> Result<Vec<Entry>, Error> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let mut entries = vec![];
+        let mut undone = HashSet::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line) {
+                Ok(Record::Done(entry)) => entries.push(entry),
+                Ok(Record::Undone(id)) => {
+                    undone.insert(id);
+                }
+                // например недописанная строка после падения
+                Err(err) => warn!(
+                    "{}:{}: skip broken record: {}",
+                    self.path.display(),
+                    i + 1,
+                    err
+                ),
+            }
+        }
+        entries.retain(|entry| !undone.contains(&entry.id));
+        Ok(entries)
+    }
+
+    /// Отменяет операции в обратном порядке. Отмена продолжается после ошибок, результат
+    /// возвращается для каждой операции.
+    pub async fn undo(&self, entries: Vec<Entry>) -> Vec<(Entry, Result<(), Error>)> {
+        let mut results = vec![];
+        for entry in entries.into_iter().rev() {
+            let mut res = entry.applied.undo().await;
+            if res.is_ok() {
+                res = self.mark_undone(&entry.id);
+            }
+            results.push((entry, res));
+        }
+        results
+    }
+}
+
+fn new_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(
+        "{:x}-{:x}-{:x}",
+        nanos,
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_journal_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::open(&dir.path().join("state/journal.jsonl")).unwrap();
+        let (a, b, c) = (
+            dir.path().join("a.txt"),
+            dir.path().join("b.txt"),
+            dir.path().join("c.txt"),
+        );
+        std::fs::write(&c, "content").unwrap();
+
+        // a -> b -> c, отмена должна вернуть файл в a
+        for (from, to) in [(&a, &b), (&b, &c)] {
+            let applied = Applied::Moved {
+                from: from.clone(),
+                to: to.clone(),
+            };
+            journal.record(Some("sort"), &applied).unwrap();
+        }
+        let deleted = Applied::Deleted { path: a.clone() };
+        journal.record(Some("clean"), &deleted).unwrap();
+        assert_eq!(journal.entries().unwrap().len(), 3);
+
+        let by_rule: Vec<_> = journal
+            .entries()
+            .unwrap()
+            .into_iter()
+            .filter(|e| e.rule.as_deref() == Some("sort"))
+            .collect();
+        let results = journal.undo(by_rule).await;
+        assert!(results.iter().all(|(_, res)| res.is_ok()));
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "content");
+
+        let entries = journal.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        let results = journal.undo(entries).await;
+        assert_eq!(
+            results[0].1.as_ref().unwrap_err().kind(),
+            ErrorKind::Unsupported
+        );
+        assert_eq!(journal.entries().unwrap().len(), 1);
+    }
+}
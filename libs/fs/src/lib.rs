@@ -1,3 +1,6 @@
 pub mod actions;
+pub mod content_index;
 mod fs_watcher;
+pub mod journal;
+pub mod validation;
 pub use fs_watcher::*;
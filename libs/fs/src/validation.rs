@@ -0,0 +1,252 @@
+use std::fmt::{self, Display};
+
+/// Проверка конфига на ошибки, которые не ловит serde: невалидные регулярки, несуществующие
+/// пути, несовместимые операторы и т.д. Все найденные проблемы складываются в `problems`,
+/// чтобы показать пользователю их разом, а не по одной.
+pub trait Validate {
+    /// `pointer` JSON pointer (RFC 6901) до проверяемого значения в файле с правилами
+    fn validate(&self, pointer: &str, problems: &mut Vec<Problem>);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// С такой ошибкой правила не могут быть применены
+    Error,
+    /// Правила применятся, но скорее всего работать будут не так как ожидается, например
+    /// папки еще нет, но она может появиться позже
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub pointer: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn error(pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            pointer: pointer.into(),
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(pointer: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            pointer: pointer.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{}: {}: {}", self.severity, pointer, self.message)
+    }
+}
+
+/// Добавляет к JSON pointer еще один сегмент, экранируя `~` и `/`
+pub fn pointer_child(pointer: &str, token: impl Display) -> String {
+    let token = token.to_string().replace('~', "~0").replace('/', "~1");
+    format!("{}/{}", pointer, token)
+}
+
+/// Находит строку и колонку (начиная с 1) значения по JSON pointer в исходном тексте. Serde
+/// после разбора позиции не хранит, поэтому проходим по тексту еще раз.
+pub fn locate(json: &str, pointer: &str) -> Option<(usize, usize)> {
+    let tokens: Vec<String> = if pointer.is_empty() {
+        vec![]
+    } else {
+        pointer
+            .strip_prefix('/')?
+            .split('/')
+            .map(|t| t.replace("~1", "/").replace("~0", "~"))
+            .collect()
+    };
+
+    let mut scanner = Scanner {
+        src: json.as_bytes(),
+        pos: 0,
+    };
+    scanner.skip_ws();
+    for token in tokens.iter() {
+        match scanner.peek()? {
+            b'{' => {
+                scanner.pos += 1;
+                loop {
+                    scanner.skip_ws();
+                    if scanner.peek()? == b'}' {
+                        return None;
+                    }
+                    let key = scanner.string()?;
+                    scanner.skip_ws();
+                    scanner.expect(b':')?;
+                    scanner.skip_ws();
+                    if &key == token {
+                        break;
+                    }
+                    scanner.skip_value()?;
+                    scanner.skip_ws();
+                    if scanner.peek()? == b',' {
+                        scanner.pos += 1;
+                    }
+                }
+            }
+            b'[' => {
+                let index: usize = token.parse().ok()?;
+                scanner.pos += 1;
+                for _ in 0..index {
+                    scanner.skip_ws();
+                    if scanner.peek()? == b']' {
+                        return None;
+                    }
+                    scanner.skip_value()?;
+                    scanner.skip_ws();
+                    scanner.expect(b',')?;
+                }
+                scanner.skip_ws();
+                if scanner.peek()? == b']' {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(line_col(json, scanner.pos))
+}
+
+/// Переводит смещение в байтах в строку и колонку (начиная с 1)
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text.as_bytes()[..offset.min(text.len())];
+    let line = before.iter().filter(|b| **b == b'\n').count() + 1;
+    let line_start = before
+        .iter()
+        .rposition(|b| *b == b'\n')
+        .map_or(0, |p| p + 1);
+    let col = String::from_utf8_lossy(&before[line_start..])
+        .chars()
+        .count()
+        + 1;
+    (line, col)
+}
+
+struct Scanner<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.peek()? == byte).then(|| self.pos += 1)
+    }
+
+    fn skip_ws(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\n' | b'\r' | b'\t')) {
+            self.pos += 1;
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.skip_string()?;
+        serde_json::from_slice(&self.src[start..self.pos]).ok()
+    }
+
+    fn skip_string(&mut self) -> Option<()> {
+        self.expect(b'"')?;
+        loop {
+            match self.peek()? {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    return Some(());
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        match self.peek()? {
+            b'"' => self.skip_string(),
+            open @ (b'{' | b'[') => {
+                let close = if open == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                loop {
+                    self.skip_ws();
+                    match self.peek()? {
+                        c if c == close => {
+                            self.pos += 1;
+                            return Some(());
+                        }
+                        b',' | b':' => self.pos += 1,
+                        _ => self.skip_value()?,
+                    }
+                }
+            }
+            _ => {
+                while !matches!(
+                    self.peek(),
+                    None | Some(b',' | b'}' | b']' | b' ' | b'\n' | b'\r' | b'\t')
+                ) {
+                    self.pos += 1;
+                }
+                Some(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r#"[
+  {
+    "path": "/tmp",
+    "action": {
+      "a/b": [1, {"x": "y\"z"}, 3],
+      "c": {"d": true}
+    }
+  }
+]"#;
+
+    #[test]
+    fn test_locate() {
+        assert_eq!(locate(JSON, ""), Some((1, 1)));
+        assert_eq!(locate(JSON, "/0"), Some((2, 3)));
+        assert_eq!(locate(JSON, "/0/path"), Some((3, 13)));
+        assert_eq!(locate(JSON, "/0/action/a~1b/1/x"), Some((5, 24)));
+        assert_eq!(locate(JSON, "/0/action/a~1b/2"), Some((5, 33)));
+        assert_eq!(locate(JSON, "/0/action/c/d"), Some((6, 18)));
+        assert_eq!(locate(JSON, "/0/missing"), None);
+        assert_eq!(locate(JSON, "/1"), None);
+    }
+
+    #[test]
+    fn test_pointer_child() {
+        assert_eq!(pointer_child("", 0), "/0");
+        assert_eq!(pointer_child("/0", "a/b~c"), "/0/a~1b~0c");
+    }
+}
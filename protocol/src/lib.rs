@@ -1,7 +1,7 @@
 use std::{
     fmt::{Debug, Display},
     hash::{DefaultHasher, Hash, Hasher},
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
 
 use elfo::prelude::*;
@@ -39,6 +39,8 @@ use notify::Event;
 #[message(part)]
 pub struct KeyAction {
     pub path: PathBuf,
+    /// Папка из правила, от нее условия считают относительные пути
+    pub root: PathBuf,
     pub action: Action,
 }
 
@@ -55,7 +57,7 @@ impl Eq for KeyAction {}
 
 impl Display for KeyAction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({:?}_{:?})", self.path, self.action)
+        write!(f, "({:?}_{:?}_{:?})", self.path, self.root, self.action)
     }
 }
 
@@ -1,13 +1,20 @@
+use std::path::Path;
+
+/// Имя группы слушателей, под ним же лежит секция в конфиге
+pub const FS_WATCHER_GROUP: &str = "fs-watcher";
+/// Имя группы исполнителей действий
+pub const EXECUTORS_GROUP: &str = "executors";
+
 // Topology definition with actor groups and connections between them.
-pub fn topology() -> elfo::Topology {
+pub fn topology(config_path: &Path) -> elfo::Topology {
     let topology = elfo::Topology::empty();
 
     // However, it's more useful to control logging in the config file.
     let logger = elfo::batteries::logger::init();
 
     // Define actor groups.
-    let fs_watcher = topology.local("fs-watcher");
-    let executors = topology.local("executors");
+    let fs_watcher = topology.local(FS_WATCHER_GROUP);
+    let executors = topology.local(EXECUTORS_GROUP);
     let loggers = topology.local("system.loggers");
     let configurers = topology.local("system.configurers").entrypoint();
 
@@ -20,8 +27,6 @@ pub fn topology() -> elfo::Topology {
 
     loggers.mount(logger);
 
-    // TODO: вынести в отедльную папку с конфигами
-    let config_path = "/home/data/Work/rust/FileOrganizer/config.toml";
     configurers.mount(elfo::batteries::configurer::from_path(
         &topology,
         config_path,